@@ -1,10 +1,12 @@
 use std::{
-    path::PathBuf,
-    sync::{mpsc, Arc},
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock, Weak},
     thread,
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use notify::event::{CreateKind, MetadataKind, ModifyKind, RemoveKind, RenameMode};
 use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use thiserror::Error;
@@ -16,7 +18,11 @@ pub enum FileChangeKind {
     Modified,
     Removed,
     Renamed { from: PathBuf, to: PathBuf },
-    Metadata,
+    /// Timestamps or other attributes changed (e.g., a `touch`); usually safe to ignore.
+    /// Backends that cannot tell what changed (inotify) report this for any metadata change.
+    AttributesChanged,
+    /// Permission bits changed (chmod).
+    PermissionsChanged,
     Other,
 }
 
@@ -31,14 +37,263 @@ pub struct FileEvent {
 /// Sinks receive normalized file events; typically the sync orchestrator implements this.
 pub trait FileEventSink: Send + Sync + 'static {
     fn handle(&self, event: FileEvent);
+
+    /// Like `handle`, but hands the event back if the sink could not accept it.
+    /// The default always accepts.
+    fn try_handle(&self, event: FileEvent) -> Result<(), FileEvent> {
+        self.handle(event);
+        Ok(())
+    }
+
+    /// Receive several events for one path at once (see `BatchSink`). The default hands
+    /// them to `handle` in order.
+    fn handle_batch(&self, events: Vec<FileEvent>) {
+        for event in events {
+            self.handle(event);
+        }
+    }
+}
+
+/// Counters describing what the monitor has seen, for health dashboards.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MonitorStats {
+    /// Every item pulled off the backend channel, including errors.
+    pub events_received: u64,
+    /// Items received but not delivered to the sink (unrecovered backend errors, filtered
+    /// events).
+    pub events_dropped: u64,
+    /// Watches recreated under `FileMonitorConfig::reconnect_policy` after a backend error.
+    pub reconnects: u64,
+    /// Delivered events keyed by kind label (`created`, `modified`, ...).
+    pub events_by_kind: HashMap<String, u64>,
+    /// Path with the most delivered events so far.
+    pub busiest_path: Option<PathBuf>,
+}
+
+/// Worker-side accumulator; keeps per-path counts out of the public snapshot.
+#[derive(Debug, Default)]
+struct StatsRecorder {
+    stats: MonitorStats,
+    per_path: HashMap<PathBuf, u64>,
+}
+
+impl StatsRecorder {
+    fn record_dropped(&mut self) {
+        self.stats.events_received += 1;
+        self.stats.events_dropped += 1;
+    }
+
+    /// A backend error handed to the reconnect policy rather than dropped.
+    fn record_watch_error(&mut self) {
+        self.stats.events_received += 1;
+    }
+
+    fn record_reconnected(&mut self) {
+        self.stats.reconnects += 1;
+    }
+
+    fn record_delivered(&mut self, event: &FileEvent) {
+        self.stats.events_received += 1;
+        *self
+            .stats
+            .events_by_kind
+            .entry(kind_label(&event.kind).to_string())
+            .or_default() += 1;
+
+        let count = self.per_path.entry(event.path.clone()).or_default();
+        *count += 1;
+        let count = *count;
+        let busiest = self
+            .stats
+            .busiest_path
+            .as_ref()
+            .and_then(|p| self.per_path.get(p))
+            .copied()
+            .unwrap_or(0);
+        if count > busiest {
+            self.stats.busiest_path = Some(event.path.clone());
+        }
+    }
+}
+
+fn kind_label(kind: &FileChangeKind) -> &'static str {
+    match kind {
+        FileChangeKind::Created => "created",
+        FileChangeKind::Modified => "modified",
+        FileChangeKind::Removed => "removed",
+        FileChangeKind::Renamed { .. } => "renamed",
+        FileChangeKind::AttributesChanged => "attributes",
+        FileChangeKind::PermissionsChanged => "permissions",
+        FileChangeKind::Other => "other",
+    }
+}
+
+/// Options for starting a `FileMonitor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMonitorConfig {
+    /// Files or directories to watch; each gets its own backend watcher.
+    pub paths: Vec<PathBuf>,
+    /// Watch directories recursively. Off by default to avoid implied folder ownership.
+    pub recursive: bool,
+    /// Emit synthetic `Created` events for files already present when watching starts.
+    pub initial_scan: bool,
+    /// Threads used for the initial scan; values below 2 scan on the calling thread.
+    pub initial_scan_concurrency: usize,
+    /// Gitignore-format pattern file (e.g., `.syncignore`); matching paths are not delivered.
+    /// Patterns are relative to the file's directory.
+    pub ignore_file: Option<PathBuf>,
+    /// Drop `AttributesChanged` events (timestamp-only touches).
+    pub ignore_attribute_changes: bool,
+    /// Merge events for the same path arriving within this window of the first one into a
+    /// single event carrying the latest kind, delivered when the window closes.
+    pub coalesce_window: Option<Duration>,
+    /// How long a removal is held back waiting for a matching creation (same inode) before it
+    /// is delivered as `Removed`. Matches are reported as a single `Renamed`.
+    #[cfg(feature = "inode_tracking")]
+    pub rename_window: Duration,
+    /// Recreate a path's watcher when the backend reports an error for it, e.g. because a
+    /// network mount went away. Without a policy the monitor stops on the first backend error.
+    pub reconnect_policy: Option<ReconnectPolicy>,
+}
+
+/// How a `FileMonitor` retries a failed watch (see `FileMonitorConfig::reconnect_policy`).
+///
+/// The worker waits `backoff` before the first attempt and doubles the wait after each failure,
+/// delivering events from other watches in the meantime. Once a watch is back it emits an `Other` event for the watched path, since changes made
+/// while it was down were missed. If `max_attempts` run out, the monitor stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for FileMonitorConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            recursive: false,
+            initial_scan: false,
+            initial_scan_concurrency: 4,
+            ignore_file: None,
+            ignore_attribute_changes: false,
+            coalesce_window: None,
+            #[cfg(feature = "inode_tracking")]
+            rename_window: Duration::from_millis(200),
+            reconnect_policy: None,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum FileMonitorError {
     #[error("no paths provided to monitor")]
     NoPaths,
+    #[error("{0:?} does not name a file")]
+    NotAFile(PathBuf),
     #[error(transparent)]
     Notify(#[from] notify::Error),
+    #[error(transparent)]
+    Ignore(#[from] ignore::Error),
+}
+
+/// Bound on buffered backend events; a full channel blocks the backend instead of growing.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Extra time `drain_and_stop` waits for the worker to report back after its own deadline.
+const DRAIN_GRACE: Duration = Duration::from_millis(100);
+
+type BackendEvent = notify::Result<Event>;
+
+/// Creates the backend watcher for one path, feeding `tx`.
+type Connect = dyn Fn(
+        &Path,
+        RecursiveMode,
+        mpsc::SyncSender<BackendEvent>,
+    ) -> notify::Result<Box<dyn Watcher + Send>>
+    + Send
+    + Sync;
+
+fn connect_recommended(
+    path: &Path,
+    mode: RecursiveMode,
+    tx: mpsc::SyncSender<BackendEvent>,
+) -> notify::Result<Box<dyn Watcher + Send>> {
+    let mut watcher = RecommendedWatcher::new(forward_to(tx), Config::default())?;
+    watcher.watch(path, mode)?;
+    Ok(Box::new(watcher))
+}
+
+/// One backend watcher per configured path, plus what is needed to recreate them.
+///
+/// Owned by the monitor handle. The worker only holds a `Weak` reference, so dropping the
+/// handle still drops every sender and lets the worker see the channel close.
+struct WatcherSet {
+    watchers: Vec<(PathBuf, Box<dyn Watcher + Send>)>,
+    tx: mpsc::SyncSender<BackendEvent>,
+    mode: RecursiveMode,
+    connect: Box<Connect>,
+}
+
+impl WatcherSet {
+    /// Watched paths affected by an error reporting `paths`; all of them if none match.
+    fn affected(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let roots: Vec<PathBuf> = self.watchers.iter().map(|(root, _)| root.clone()).collect();
+        let hit: Vec<PathBuf> = roots
+            .iter()
+            .filter(|root| paths.iter().any(|p| p.starts_with(root)))
+            .cloned()
+            .collect();
+        if hit.is_empty() {
+            roots
+        } else {
+            hit
+        }
+    }
+
+    fn reconnect(&mut self, root: &Path) -> notify::Result<()> {
+        let watcher = (self.connect)(root, self.mode, self.tx.clone())?;
+        if let Some(entry) = self.watchers.iter_mut().find(|(path, _)| path == root) {
+            entry.1 = watcher;
+        }
+        Ok(())
+    }
+}
+
+/// State shared between the monitor handle and its worker thread.
+#[derive(Debug, Default)]
+struct WorkerShared {
+    stats: Mutex<StatsRecorder>,
+    /// Set by `drain_and_stop`; the worker stops delivering once it passes.
+    drain_deadline: Mutex<Option<Instant>>,
+    ignore: RwLock<Option<Gitignore>>,
+    /// Set by `watch_single_file`; events not touching this path are dropped.
+    only_file: Option<PathBuf>,
+    ignore_attribute_changes: bool,
+    coalesce_window: Option<Duration>,
+    reconnect_policy: Option<ReconnectPolicy>,
+}
+
+impl WorkerShared {
+    fn deadline(&self) -> Option<Instant> {
+        self.drain_deadline.lock().ok().and_then(|d| *d)
+    }
+
+    fn is_draining(&self) -> bool {
+        self.deadline().is_some()
+    }
+
+    fn normalize(&self, event: Event) -> Option<FileEvent> {
+        let ignore = self.ignore.read().ok();
+        normalize_event(
+            event,
+            ignore.as_ref().and_then(|gi| gi.as_ref()),
+            self.only_file.as_deref(),
+        )
+        .filter(|e| {
+            let touch_only = e.kind == FileChangeKind::AttributesChanged;
+            !(touch_only && self.ignore_attribute_changes)
+        })
+    }
 }
 
 /// In-memory watcher manager that keeps recommended platform-specific watchers alive.
@@ -46,8 +301,11 @@ pub enum FileMonitorError {
 /// It does not assume folder ownership; you can watch arbitrary file paths or directories.
 /// Events are delivered immediately to the provided sink without user interaction.
 pub struct FileMonitor {
-    _watchers: Vec<RecommendedWatcher>,
+    watchers: Arc<Mutex<WatcherSet>>,
     _worker: thread::JoinHandle<()>,
+    done: mpsc::Receiver<Vec<FileEvent>>,
+    shared: Arc<WorkerShared>,
+    ignore_file: Option<PathBuf>,
 }
 
 impl FileMonitor {
@@ -57,75 +315,555 @@ impl FileMonitor {
         paths: impl IntoIterator<Item = PathBuf>,
         sink: Arc<S>,
     ) -> Result<Self, FileMonitorError> {
-        let mut watchers = Vec::new();
-        let (tx, rx) = mpsc::channel();
+        // Non-recursive by default to avoid unintended folder ownership; caller can pass a directory
+        // and set recursion explicitly via `watch_recursive`.
+        let config = FileMonitorConfig {
+            paths: paths.into_iter().collect(),
+            ..FileMonitorConfig::default()
+        };
+        Self::with_config(config, sink)
+    }
+
+    /// Watch a directory recursively (opt-in). This can be used for higher-level workflows that
+    /// still avoid claiming ownership—callers choose the directory explicitly.
+    pub fn watch_recursive<S: FileEventSink>(
+        path: PathBuf,
+        sink: Arc<S>,
+    ) -> Result<Self, FileMonitorError> {
+        let config = FileMonitorConfig {
+            paths: vec![path],
+            recursive: true,
+            ..FileMonitorConfig::default()
+        };
+        Self::with_config(config, sink)
+    }
+
+    /// Like `watch`, but first reports every file already present in the watched directories
+    /// (non-recursively) as `Created`, so existing files are not invisible until modified.
+    pub fn with_initial_scan<S: FileEventSink>(
+        paths: Vec<PathBuf>,
+        sink: Arc<S>,
+    ) -> Result<Self, FileMonitorError> {
+        let config = FileMonitorConfig {
+            paths,
+            initial_scan: true,
+            ..FileMonitorConfig::default()
+        };
+        Self::with_config(config, sink)
+    }
+
+    /// Track exactly one file by watching its parent directory and dropping events for
+    /// siblings. The file need not exist yet.
+    ///
+    /// On backends that only offer directory-level notifications (e.g., macOS FSEvents), sibling
+    /// filtering is best-effort: coalesced events may be attributed to the directory instead of
+    /// the file and are then dropped.
+    pub fn watch_single_file<S: FileEventSink>(
+        path: PathBuf,
+        sink: Arc<S>,
+    ) -> Result<Self, FileMonitorError> {
+        let Some(name) = path.file_name() else {
+            return Err(FileMonitorError::NotAFile(path));
+        };
+        // Rebuild the path from its parent so it matches what the backend reports.
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let target = parent.join(name);
+        let config = FileMonitorConfig {
+            paths: vec![parent],
+            ..FileMonitorConfig::default()
+        };
+        Self::build(config, Some(target), sink, Box::new(connect_recommended))
+    }
 
-        let mut any = false;
-        for path in paths {
-            any = true;
-            let tx = tx.clone();
-            let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-            // Non-recursive by default to avoid unintended folder ownership; caller can pass a directory
-            // and set recursion explicitly via `watch_recursive`.
-            watcher.watch(&path, RecursiveMode::NonRecursive)?;
-            watchers.push(watcher);
-        }
-        if !any {
+    /// Start monitoring according to `config`.
+    pub fn with_config<S: FileEventSink>(
+        config: FileMonitorConfig,
+        sink: Arc<S>,
+    ) -> Result<Self, FileMonitorError> {
+        Self::build(config, None, sink, Box::new(connect_recommended))
+    }
+
+    fn build<S: FileEventSink>(
+        config: FileMonitorConfig,
+        only_file: Option<PathBuf>,
+        sink: Arc<S>,
+        connect: Box<Connect>,
+    ) -> Result<Self, FileMonitorError> {
+        if config.paths.is_empty() {
             return Err(FileMonitorError::NoPaths);
         }
+        let mode = if config.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+
+        let ignore = config
+            .ignore_file
+            .as_deref()
+            .map(load_ignore_file)
+            .transpose()?;
+
+        // Prime before watching so renames of files that already exist are recognized.
+        #[cfg(feature = "inode_tracking")]
+        let tracker = inode::InodeTracker::primed(&config.paths, config.rename_window);
+
+        let (tx, rx) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        let mut watchers = Vec::new();
+        for path in &config.paths {
+            watchers.push((path.clone(), connect(path, mode, tx.clone())?));
+        }
+        let watchers = WatcherSet {
+            watchers,
+            tx: tx.clone(),
+            mode,
+            connect,
+        };
 
-        let worker_sink = sink.clone();
+        // Start the worker before scanning so the bounded channel keeps draining.
+        let shared = WorkerShared {
+            ignore: RwLock::new(ignore),
+            only_file,
+            ignore_attribute_changes: config.ignore_attribute_changes,
+            coalesce_window: config.coalesce_window,
+            reconnect_policy: config.reconnect_policy,
+            ..WorkerShared::default()
+        };
+        let mut monitor = Self::start(
+            watchers,
+            rx,
+            sink,
+            shared,
+            #[cfg(feature = "inode_tracking")]
+            tracker,
+        );
+        monitor.ignore_file = config.ignore_file;
+        if config.initial_scan {
+            initial_scan(&config.paths, config.initial_scan_concurrency, &tx);
+        }
+        Ok(monitor)
+    }
+
+    fn start<S: FileEventSink>(
+        watchers: WatcherSet,
+        rx: mpsc::Receiver<BackendEvent>,
+        sink: Arc<S>,
+        shared: WorkerShared,
+        #[cfg(feature = "inode_tracking")] tracker: inode::InodeTracker,
+    ) -> Self {
+        let shared = Arc::new(shared);
+        let watchers = Arc::new(Mutex::new(watchers));
+        let (done_tx, done) = mpsc::channel();
+        let worker_shared = shared.clone();
+        let worker_watchers = Arc::downgrade(&watchers);
         let worker = thread::spawn(move || {
-            for res in rx {
-                match res {
-                    Ok(event) => {
-                        if let Some(normalized) = normalize_event(event) {
-                            worker_sink.handle(normalized);
-                        }
-                    }
-                    Err(_recv_err) => break,
-                }
-            }
+            let worker = Worker {
+                sink,
+                shared: &worker_shared,
+                watchers: worker_watchers,
+                undelivered: Vec::new(),
+                coalescing: HashMap::new(),
+                reconnecting: HashMap::new(),
+                #[cfg(feature = "inode_tracking")]
+                inodes: tracker,
+            };
+            let _ = done_tx.send(worker.run(rx));
         });
 
-        Ok(Self {
-            _watchers: watchers,
+        Self {
+            watchers,
             _worker: worker,
-        })
+            done,
+            shared,
+            ignore_file: None,
+        }
     }
 
-    /// Watch a directory recursively (opt-in). This can be used for higher-level workflows that
-    /// still avoid claiming ownership—callers choose the directory explicitly.
-    pub fn watch_recursive<S: FileEventSink>(
-        path: PathBuf,
-        sink: Arc<S>,
-    ) -> Result<Self, FileMonitorError> {
-        let (tx, rx) = mpsc::channel();
-        let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-        watcher.watch(&path, RecursiveMode::Recursive)?;
+    /// Snapshot of event counters.
+    ///
+    /// Takes the same lock the worker uses for every event, so avoid calling it in tight loops.
+    pub fn stats(&self) -> MonitorStats {
+        self.shared
+            .stats
+            .lock()
+            .map(|recorder| recorder.stats.clone())
+            .unwrap_or_default()
+    }
 
-        let worker_sink = sink.clone();
-        let worker = thread::spawn(move || {
-            for res in rx {
-                match res {
-                    Ok(event) => {
-                        if let Some(normalized) = normalize_event(event) {
-                            worker_sink.handle(normalized);
-                        }
+    /// Re-read the configured ignore file and apply it to subsequent events.
+    ///
+    /// Returns the number of patterns loaded (0 when no ignore file is configured).
+    pub fn reload_ignore_file(&mut self) -> Result<usize, FileMonitorError> {
+        let Some(path) = &self.ignore_file else {
+            return Ok(0);
+        };
+        let ignore = load_ignore_file(path)?;
+        let count = (ignore.num_ignores() + ignore.num_whitelists()) as usize;
+        if let Ok(mut current) = self.shared.ignore.write() {
+            *current = Some(ignore);
+        }
+        Ok(count)
+    }
+
+    /// Stop watching and flush already-buffered events to the sink before returning.
+    ///
+    /// Watchers are dropped first so no new OS events are accepted. Buffered events are then
+    /// delivered until `timeout` elapses. Returns the events that were not delivered: ones the
+    /// sink rejected (e.g., a `ChannelSink` whose receiver is gone) and ones still queued at the
+    /// deadline. If the sink itself blocks past the deadline, nothing can be recovered and an
+    /// empty list is returned.
+    pub fn drain_and_stop(self, timeout: Duration) -> Vec<FileEvent> {
+        let FileMonitor {
+            watchers,
+            done,
+            shared,
+            ..
+        } = self;
+        if let Ok(mut deadline) = shared.drain_deadline.lock() {
+            *deadline = Some(Instant::now() + timeout);
+        }
+        drop(watchers);
+        done.recv_timeout(timeout + DRAIN_GRACE).unwrap_or_default()
+    }
+}
+
+fn load_ignore_file(path: &Path) -> Result<Gitignore, FileMonitorError> {
+    let root = path.parent().unwrap_or(Path::new("/"));
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(err) = builder.add(path) {
+        return Err(err.into());
+    }
+    Ok(builder.build()?)
+}
+
+fn is_ignored(ignore: &Gitignore, path: &Path) -> bool {
+    let is_dir = path.is_dir();
+    // Walking parents only works (and is only meaningful) under the pattern root.
+    if path.starts_with(ignore.path()) {
+        ignore.matched_path_or_any_parents(path, is_dir).is_ignore()
+    } else {
+        ignore.matched(path, is_dir).is_ignore()
+    }
+}
+
+/// Feed a synthetic `Created` event for every file directly inside `paths` (or the path itself
+/// when it is a file), spreading directories across up to `concurrency` threads.
+fn initial_scan(paths: &[PathBuf], concurrency: usize, tx: &mpsc::SyncSender<BackendEvent>) {
+    let scan = |group: &[PathBuf]| {
+        for path in group {
+            for file in list_files(path) {
+                let event = Event::new(EventKind::Create(CreateKind::File)).add_path(file);
+                let _ = tx.send(Ok(event));
+            }
+        }
+    };
+
+    if concurrency < 2 || paths.len() < 2 {
+        scan(paths);
+        return;
+    }
+    let per_thread = paths.len().div_ceil(concurrency);
+    thread::scope(|scope| {
+        for group in paths.chunks(per_thread) {
+            scope.spawn(move || scan(group));
+        }
+    });
+}
+
+/// Files directly inside `path`, or `path` itself when it is a file.
+fn list_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Backend event handler that feeds the bounded worker channel.
+fn forward_to(tx: mpsc::SyncSender<BackendEvent>) -> impl notify::EventHandler {
+    move |res: BackendEvent| {
+        let _ = tx.send(res);
+    }
+}
+
+/// Worker loop state: normalizes backend events and hands them to the sink until the backend
+/// closes the channel or a drain deadline passes.
+struct Worker<'a, S: FileEventSink> {
+    sink: Arc<S>,
+    shared: &'a WorkerShared,
+    watchers: Weak<Mutex<WatcherSet>>,
+    /// Events not delivered while draining; returned to `drain_and_stop`.
+    undelivered: Vec<FileEvent>,
+    /// Latest event per path within an open coalescing window, with when the window opened.
+    coalescing: HashMap<PathBuf, (FileEvent, Instant)>,
+    /// Watched paths waiting for their next reconnect attempt.
+    reconnecting: HashMap<PathBuf, PendingReconnect>,
+    #[cfg(feature = "inode_tracking")]
+    inodes: inode::InodeTracker,
+}
+
+/// Retry state for a watch that failed; see `ReconnectPolicy`.
+struct PendingReconnect {
+    attempts_left: u32,
+    delay: Duration,
+    due: Instant,
+}
+
+impl<S: FileEventSink> Worker<'_, S> {
+    fn run(mut self, rx: mpsc::Receiver<BackendEvent>) -> Vec<FileEvent> {
+        loop {
+            let drain = self.shared.deadline();
+            let wake = match (drain, self.next_wake()) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let received = match wake {
+                None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            };
+            match received {
+                Ok(Ok(event)) => self.accept(event),
+                Ok(Err(watch_err)) => {
+                    if !self.schedule_reconnect(&watch_err) {
+                        self.record(|recorder| recorder.record_dropped());
+                        break;
+                    }
+                    self.record(|recorder| recorder.record_watch_error());
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if drain.is_some_and(|d| Instant::now() >= d) {
+                        // Out of time: hand back whatever is still held or queued.
+                        let coalesced = self.take_coalesced(None);
+                        self.undelivered.extend(coalesced);
+                        let queued: Vec<FileEvent> = self
+                            .take_held()
+                            .into_iter()
+                            .chain(rx.try_iter().filter_map(Result::ok))
+                            .filter_map(|event| self.shared.normalize(event))
+                            .collect();
+                        self.undelivered.extend(queued);
+                        return self.undelivered;
                     }
-                    Err(_recv_err) => break,
                 }
             }
-        });
+            if !self.retry_reconnects() {
+                break;
+            }
+            self.flush_expired(false);
+        }
+        self.flush_expired(true);
+        self.undelivered
+    }
 
-        Ok(Self {
-            _watchers: vec![watcher],
-            _worker: worker,
-        })
+    /// Queue the watchers affected by `err` for reconnection under the reconnect policy.
+    /// Returns false if the worker should stop instead.
+    fn schedule_reconnect(&mut self, err: &notify::Error) -> bool {
+        let Some(policy) = self.shared.reconnect_policy else {
+            return false;
+        };
+        if policy.max_attempts == 0 {
+            return false;
+        }
+        let roots = match self.watchers.upgrade() {
+            Some(set) => match set.lock() {
+                Ok(set) => set.affected(&err.paths),
+                Err(_) => return false,
+            },
+            None => return false,
+        };
+        let due = Instant::now() + policy.backoff;
+        for root in roots {
+            self.reconnecting.entry(root).or_insert(PendingReconnect {
+                attempts_left: policy.max_attempts,
+                delay: policy.backoff,
+                due,
+            });
+        }
+        true
+    }
+
+    /// Try every reconnect that is due, emitting `Other` for each watch that comes back.
+    /// Returns false once a watch runs out of attempts, or the watchers are gone.
+    fn retry_reconnects(&mut self) -> bool {
+        if self.shared.is_draining() {
+            self.reconnecting.clear();
+            return true;
+        }
+        let now = Instant::now();
+        let due: Vec<PathBuf> = self
+            .reconnecting
+            .iter()
+            .filter(|(_, pending)| pending.due <= now)
+            .map(|(root, _)| root.clone())
+            .collect();
+        for root in due {
+            let Some(set) = self.watchers.upgrade() else {
+                return false;
+            };
+            let reconnected = match set.lock() {
+                Ok(mut set) => set.reconnect(&root).is_ok(),
+                Err(_) => return false,
+            };
+            if reconnected {
+                self.reconnecting.remove(&root);
+                self.record(|recorder| recorder.record_reconnected());
+                self.emit(FileEvent {
+                    path: root,
+                    kind: FileChangeKind::Other,
+                    occurred_at: SystemTime::now(),
+                });
+                continue;
+            }
+            let Some(pending) = self.reconnecting.get_mut(&root) else {
+                continue;
+            };
+            pending.attempts_left -= 1;
+            if pending.attempts_left == 0 {
+                return false;
+            }
+            pending.delay = pending.delay.saturating_mul(2);
+            pending.due = Instant::now() + pending.delay;
+        }
+        true
+    }
+
+    /// Earliest time a held-back event must be released or a watch retried, if any.
+    fn next_wake(&self) -> Option<Instant> {
+        #[cfg(feature = "inode_tracking")]
+        let held = self.inodes.next_deadline();
+        #[cfg(not(feature = "inode_tracking"))]
+        let held: Option<Instant> = None;
+        let window = self.shared.coalesce_window.unwrap_or_default();
+        let coalesced = self
+            .coalescing
+            .values()
+            .map(|(_, opened)| *opened + window)
+            .min();
+        let reconnect = self.reconnecting.values().map(|pending| pending.due).min();
+        [held, coalesced, reconnect].into_iter().flatten().min()
+    }
+
+    /// Remove coalesced events whose window closed by `now` (all of them for `None`), oldest
+    /// window first.
+    fn take_coalesced(&mut self, now: Option<Instant>) -> Vec<FileEvent> {
+        let window = self.shared.coalesce_window.unwrap_or_default();
+        let closed: Vec<PathBuf> = self
+            .coalescing
+            .iter()
+            .filter(|(_, (_, opened))| now.is_none_or(|now| *opened + window <= now))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut events: Vec<(FileEvent, Instant)> = closed
+            .iter()
+            .filter_map(|path| self.coalescing.remove(path))
+            .collect();
+        events.sort_by_key(|(_, opened)| *opened);
+        events.into_iter().map(|(event, _)| event).collect()
+    }
+
+    /// Events held back by the worker that have not been delivered yet.
+    fn take_held(&mut self) -> Vec<Event> {
+        #[cfg(feature = "inode_tracking")]
+        {
+            self.inodes.expire(Instant::now(), true)
+        }
+        #[cfg(not(feature = "inode_tracking"))]
+        {
+            Vec::new()
+        }
+    }
+
+    fn accept(&mut self, event: Event) {
+        #[cfg(feature = "inode_tracking")]
+        {
+            for ready in self.inodes.process(event) {
+                self.deliver(ready);
+            }
+            // Events merged into a rename were received but never delivered on their own.
+            for _ in 0..self.inodes.take_absorbed() {
+                self.record(|recorder| recorder.record_dropped());
+            }
+        }
+        #[cfg(not(feature = "inode_tracking"))]
+        self.deliver(event);
+    }
+
+    fn flush_expired(&mut self, all: bool) {
+        #[cfg(feature = "inode_tracking")]
+        for ready in self.inodes.expire(Instant::now(), all) {
+            self.deliver(ready);
+        }
+        for event in self.take_coalesced((!all).then(Instant::now)) {
+            self.emit(event);
+        }
+    }
+
+    fn deliver(&mut self, event: Event) {
+        let Some(normalized) = self.shared.normalize(event) else {
+            self.record(|recorder| recorder.record_dropped());
+            return;
+        };
+        let Some(window) = self.shared.coalesce_window else {
+            self.emit(normalized);
+            return;
+        };
+        let now = Instant::now();
+        match self.coalescing.get_mut(&normalized.path) {
+            Some((pending, opened)) if now < *opened + window => {
+                // Superseded within the window: received, but never delivered on its own.
+                *pending = normalized;
+                self.record(|recorder| recorder.record_dropped());
+            }
+            _ => {
+                let path = normalized.path.clone();
+                if let Some((expired, _)) = self.coalescing.insert(path, (normalized, now)) {
+                    self.emit(expired);
+                }
+            }
+        }
+    }
+
+    fn emit(&mut self, normalized: FileEvent) {
+        match self.sink.try_handle(normalized.clone()) {
+            Ok(()) => self.record(|recorder| recorder.record_delivered(&normalized)),
+            Err(rejected) => {
+                // Re-check: a drain may have started while the sink was busy.
+                if self.shared.is_draining() {
+                    self.undelivered.push(rejected);
+                }
+                self.record(|recorder| recorder.record_dropped());
+            }
+        }
+    }
+
+    fn record(&self, update: impl FnOnce(&mut StatsRecorder)) {
+        if let Ok(mut recorder) = self.shared.stats.lock() {
+            update(&mut recorder);
+        }
     }
 }
 
-fn normalize_event(event: Event) -> Option<FileEvent> {
+/// Classify a backend event; returns `None` for paths matched by the ignore patterns, or for
+/// events that do not touch `only_file` when one is given.
+fn normalize_event(
+    event: Event,
+    ignore: Option<&Gitignore>,
+    only_file: Option<&Path>,
+) -> Option<FileEvent> {
+    if only_file.is_some_and(|file| !event.paths.iter().any(|p| p == file)) {
+        return None;
+    }
     // Many backends emit multiple paths; we derive a primary path and classify.
     let occurred_at = SystemTime::now();
     let kind = match &event.kind {
@@ -138,7 +876,10 @@ fn normalize_event(event: Event) -> Option<FileEvent> {
             | ModifyKind::Other
             | ModifyKind::Name(RenameMode::Both),
         ) => FileChangeKind::Modified,
-        EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)) => FileChangeKind::Metadata,
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)) => {
+            FileChangeKind::PermissionsChanged
+        }
+        EventKind::Modify(ModifyKind::Metadata(_)) => FileChangeKind::AttributesChanged,
         EventKind::Remove(RemoveKind::File | RemoveKind::Any | RemoveKind::Other) => {
             FileChangeKind::Removed
         }
@@ -156,7 +897,10 @@ fn normalize_event(event: Event) -> Option<FileEvent> {
         _ => FileChangeKind::Other,
     };
 
-    let path = event.paths.get(0).cloned().unwrap_or_else(PathBuf::new);
+    let path = event.paths.first().cloned().unwrap_or_else(PathBuf::new);
+    if ignore.is_some_and(|gi| is_ignored(gi, &path)) {
+        return None;
+    }
     Some(FileEvent {
         path,
         kind,
@@ -164,6 +908,183 @@ fn normalize_event(event: Event) -> Option<FileEvent> {
     })
 }
 
+/// Inode bookkeeping that turns a removal plus a creation of the same file into one rename,
+/// including moves between separately watched directories.
+#[cfg(feature = "inode_tracking")]
+mod inode {
+    use super::*;
+
+    /// A removal held back until `expires`, in case the inode reappears elsewhere.
+    struct Departure {
+        inode: u64,
+        event: Event,
+        expires: Instant,
+    }
+
+    pub(super) struct InodeTracker {
+        /// Last known path for each inode seen under the watched paths.
+        inodes: HashMap<u64, PathBuf>,
+        /// Reverse of `inodes`, for removals whose path can no longer be stat'ed.
+        paths: HashMap<PathBuf, u64>,
+        window: Duration,
+        pending: Vec<Departure>,
+        /// Renames already reported, so the backend's own follow-up events can be suppressed.
+        reported: Vec<(PathBuf, PathBuf, Instant)>,
+        absorbed: u64,
+    }
+
+    impl InodeTracker {
+        /// Tracker seeded with the files currently inside `paths` (non-recursively).
+        pub(super) fn primed(paths: &[PathBuf], window: Duration) -> Self {
+            let mut tracker = Self {
+                inodes: HashMap::new(),
+                paths: HashMap::new(),
+                window,
+                pending: Vec::new(),
+                reported: Vec::new(),
+                absorbed: 0,
+            };
+            for file in paths.iter().flat_map(|path| list_files(path)) {
+                if let Some(inode) = inode_of(&file) {
+                    tracker.remember(inode, file);
+                }
+            }
+            tracker
+        }
+
+        /// Record `path` as the current location of `inode`; returns its previous path.
+        fn remember(&mut self, inode: u64, path: PathBuf) -> Option<PathBuf> {
+            if let Some(stale) = self.paths.insert(path.clone(), inode) {
+                if stale != inode {
+                    self.inodes.remove(&stale);
+                }
+            }
+            let previous = self.inodes.insert(inode, path.clone());
+            if let Some(old) = previous.as_ref().filter(|old| **old != path) {
+                self.paths.remove(old);
+            }
+            previous
+        }
+
+        fn forget(&mut self, inode: u64) {
+            if let Some(path) = self.inodes.remove(&inode) {
+                self.paths.remove(&path);
+            }
+        }
+
+        pub(super) fn next_deadline(&self) -> Option<Instant> {
+            self.pending.iter().map(|departure| departure.expires).min()
+        }
+
+        /// Number of backend events folded into a rename since the last call.
+        pub(super) fn take_absorbed(&mut self) -> u64 {
+            std::mem::take(&mut self.absorbed)
+        }
+
+        /// Feed one backend event; returns the events ready for delivery.
+        pub(super) fn process(&mut self, event: Event) -> Vec<Event> {
+            let now = Instant::now();
+            self.reported.retain(|(_, _, at)| now < *at);
+
+            match (&event.kind, event.paths.as_slice()) {
+                (
+                    EventKind::Remove(RemoveKind::File | RemoveKind::Any | RemoveKind::Other)
+                    | EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                    [path],
+                ) => {
+                    if self.reported.iter().any(|(from, _, _)| from == path) {
+                        self.absorbed += 1;
+                        return Vec::new();
+                    }
+                    match self.paths.get(path).copied() {
+                        Some(inode) => {
+                            self.pending.push(Departure {
+                                inode,
+                                event,
+                                expires: now + self.window,
+                            });
+                            Vec::new()
+                        }
+                        None => vec![event],
+                    }
+                }
+                (
+                    EventKind::Create(CreateKind::File | CreateKind::Any | CreateKind::Other)
+                    | EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+                    [path],
+                ) => {
+                    let Some(inode) = inode_of(path) else {
+                        return vec![event];
+                    };
+                    let previous = self.remember(inode, path.clone());
+                    let from = match self.pending.iter().position(|d| d.inode == inode) {
+                        Some(index) => {
+                            self.pending.remove(index);
+                            self.absorbed += 1;
+                            previous
+                        }
+                        // The removal may still be on its way from another watcher.
+                        None => previous.filter(|old| old != path && !old.exists()),
+                    };
+                    match from {
+                        Some(from) => {
+                            self.reported.push((from.clone(), path.clone(), now + self.window));
+                            vec![rename_event(from, path.clone())]
+                        }
+                        None => vec![event],
+                    }
+                }
+                (EventKind::Modify(ModifyKind::Name(_)), [from, to]) => {
+                    let index = self
+                        .reported
+                        .iter()
+                        .position(|(f, t, _)| f == from && t == to);
+                    if let Some(index) = index {
+                        self.reported.remove(index);
+                        self.absorbed += 1;
+                        return Vec::new();
+                    }
+                    if let Some(inode) = inode_of(to) {
+                        self.remember(inode, to.clone());
+                    }
+                    vec![event]
+                }
+                _ => vec![event],
+            }
+        }
+
+        /// Release departures whose window has passed (or all of them), forgetting their inodes.
+        pub(super) fn expire(&mut self, now: Instant, all: bool) -> Vec<Event> {
+            let (ready, waiting) = std::mem::take(&mut self.pending)
+                .into_iter()
+                .partition(|departure| all || departure.expires <= now);
+            self.pending = waiting;
+            let ready: Vec<Departure> = ready;
+            for departure in &ready {
+                self.forget(departure.inode);
+            }
+            ready.into_iter().map(|departure| departure.event).collect()
+        }
+    }
+
+    fn rename_event(from: PathBuf, to: PathBuf) -> Event {
+        Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::To)))
+            .add_path(from)
+            .add_path(to)
+    }
+
+    #[cfg(unix)]
+    fn inode_of(path: &Path) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::symlink_metadata(path).ok().map(|meta| meta.ino())
+    }
+
+    #[cfg(not(unix))]
+    fn inode_of(_path: &Path) -> Option<u64> {
+        None
+    }
+}
+
 /// Example sink useful for tests or hooking into the sync layer.
 pub struct ChannelSink {
     pub sender: mpsc::Sender<FileEvent>,
@@ -173,4 +1094,735 @@ impl FileEventSink for ChannelSink {
     fn handle(&self, event: FileEvent) {
         let _ = self.sender.send(event);
     }
+
+    fn try_handle(&self, event: FileEvent) -> Result<(), FileEvent> {
+        self.sender.send(event).map_err(|err| err.0)
+    }
+}
+
+/// Sink adaptor that buffers events and passes them to `inner` once per window, one
+/// `handle_batch` call per path. Events still buffered when it is dropped are flushed then.
+pub struct BatchSink<S: FileEventSink> {
+    inner: Arc<S>,
+    window: Duration,
+    buffer: Arc<Mutex<HashMap<PathBuf, Vec<FileEvent>>>>,
+}
+
+/// Wrap `inner` in a `BatchSink` flushed every `window` by a background thread, which exits
+/// once the sink is dropped.
+pub fn batch_sink<S: FileEventSink>(inner: Arc<S>, window: Duration) -> BatchSink<S> {
+    let buffer = Arc::new(Mutex::new(HashMap::new()));
+    let pending = Arc::downgrade(&buffer);
+    let target = inner.clone();
+    thread::spawn(move || loop {
+        thread::sleep(window);
+        let Some(buffer) = pending.upgrade() else {
+            break;
+        };
+        flush_batches(target.as_ref(), &buffer);
+    });
+    BatchSink {
+        inner,
+        window,
+        buffer,
+    }
+}
+
+impl<S: FileEventSink> BatchSink<S> {
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Pass everything buffered so far to `inner` now, without waiting for the window.
+    pub fn flush(&self) {
+        flush_batches(self.inner.as_ref(), &self.buffer);
+    }
+}
+
+impl<S: FileEventSink> FileEventSink for BatchSink<S> {
+    fn handle(&self, event: FileEvent) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.entry(event.path.clone()).or_default().push(event);
+        }
+    }
+}
+
+impl<S: FileEventSink> Drop for BatchSink<S> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Hand every buffered group to `inner`, oldest group first.
+fn flush_batches<S: FileEventSink>(inner: &S, buffer: &Mutex<HashMap<PathBuf, Vec<FileEvent>>>) {
+    let Ok(mut buffer) = buffer.lock() else {
+        return;
+    };
+    let mut groups: Vec<Vec<FileEvent>> = std::mem::take(&mut *buffer).into_values().collect();
+    drop(buffer);
+    groups.sort_by_key(|group| group.first().map(|e| e.occurred_at));
+    for group in groups {
+        inner.handle_batch(group);
+    }
+}
+
+/// Sink adaptor that forwards events to `inner` while recording how long after
+/// `occurred_at` each one arrived, keeping the most recent `capacity` samples.
+pub struct LatencyTrackingSink<S: FileEventSink> {
+    inner: Arc<S>,
+    capacity: usize,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+/// Wrap `sink` in a `LatencyTrackingSink` keeping the last `buffer_size` latencies (e.g. 1000).
+pub fn with_latency_tracking<S: FileEventSink>(
+    sink: Arc<S>,
+    buffer_size: usize,
+) -> LatencyTrackingSink<S> {
+    LatencyTrackingSink {
+        inner: sink,
+        capacity: buffer_size,
+        latencies: Mutex::new(VecDeque::with_capacity(buffer_size)),
+    }
+}
+
+impl<S: FileEventSink> LatencyTrackingSink<S> {
+    /// Median delivery delay over the buffered samples; zero before any event.
+    pub fn p50_latency(&self) -> Duration {
+        self.percentile(50)
+    }
+
+    /// 99th-percentile delivery delay over the buffered samples; zero before any event.
+    pub fn p99_latency(&self) -> Duration {
+        self.percentile(99)
+    }
+
+    /// Nearest-rank percentile.
+    fn percentile(&self, p: usize) -> Duration {
+        let Ok(latencies) = self.latencies.lock() else {
+            return Duration::ZERO;
+        };
+        if latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (p * sorted.len()).div_ceil(100).max(1);
+        sorted[rank - 1]
+    }
+
+    fn latency(event: &FileEvent) -> Duration {
+        SystemTime::now()
+            .duration_since(event.occurred_at)
+            .unwrap_or_default()
+    }
+
+    fn record(&self, latency: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut latencies) = self.latencies.lock() {
+            if latencies.len() == self.capacity {
+                latencies.pop_front();
+            }
+            latencies.push_back(latency);
+        }
+    }
+}
+
+impl<S: FileEventSink> FileEventSink for LatencyTrackingSink<S> {
+    fn handle(&self, event: FileEvent) {
+        self.record(Self::latency(&event));
+        self.inner.handle(event);
+    }
+
+    /// Records a sample only if `inner` accepts the event; a handed-back event was not delivered.
+    fn try_handle(&self, event: FileEvent) -> Result<(), FileEvent> {
+        let latency = Self::latency(&event);
+        self.inner.try_handle(event)?;
+        self.record(latency);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("atrius-monitor-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Collect events until none arrive for `quiet`, or `limit` elapses.
+    fn collect_events(
+        rx: &mpsc::Receiver<FileEvent>,
+        quiet: Duration,
+        limit: Duration,
+    ) -> Vec<FileEvent> {
+        let deadline = Instant::now() + limit;
+        let mut events = Vec::new();
+        while Instant::now() < deadline {
+            match rx.recv_timeout(quiet) {
+                Ok(event) => events.push(event),
+                Err(_) => break,
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn stats_count_received_events() {
+        let dir = temp_dir();
+        let (tx, rx) = mpsc::channel();
+        let monitor =
+            FileMonitor::watch(vec![dir.clone()], Arc::new(ChannelSink { sender: tx })).unwrap();
+
+        let file = dir.join("asset.bin");
+        for i in 0..3 {
+            fs::write(&file, vec![i; 16]).unwrap();
+        }
+        let delivered = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        assert!(!delivered.is_empty());
+
+        let stats = monitor.stats();
+        assert_eq!(
+            stats.events_received,
+            delivered.len() as u64 + stats.events_dropped
+        );
+        assert_eq!(
+            stats.events_by_kind.values().sum::<u64>(),
+            delivered.len() as u64
+        );
+        assert_eq!(stats.busiest_path, Some(file));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// Delays every event so a backlog builds up in the worker channel.
+    struct SlowSink {
+        inner: ChannelSink,
+        delay: Duration,
+    }
+
+    impl FileEventSink for SlowSink {
+        fn handle(&self, event: FileEvent) {
+            let _ = self.try_handle(event);
+        }
+
+        fn try_handle(&self, event: FileEvent) -> Result<(), FileEvent> {
+            thread::sleep(self.delay);
+            self.inner.try_handle(event)
+        }
+    }
+
+    #[test]
+    fn drain_and_stop_flushes_buffered_events() {
+        let dir = temp_dir();
+        let (tx, rx) = mpsc::channel();
+        let sink = Arc::new(SlowSink {
+            inner: ChannelSink { sender: tx },
+            delay: Duration::from_millis(50),
+        });
+        let monitor = FileMonitor::watch(vec![dir.clone()], sink).unwrap();
+
+        let file = dir.join("late.bin");
+        fs::write(&file, b"data").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let undelivered = monitor.drain_and_stop(Duration::from_secs(5));
+
+        assert!(undelivered.is_empty());
+        let delivered: Vec<FileEvent> = rx.try_iter().collect();
+        assert!(delivered
+            .iter()
+            .any(|e| e.path == file && e.kind == FileChangeKind::Created));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn drain_and_stop_returns_rejected_events() {
+        let dir = temp_dir();
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        let sink = Arc::new(SlowSink {
+            inner: ChannelSink { sender: tx },
+            delay: Duration::from_millis(50),
+        });
+        let monitor = FileMonitor::watch(vec![dir.clone()], sink).unwrap();
+
+        let file = dir.join("orphan.bin");
+        fs::write(&file, b"data").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let undelivered = monitor.drain_and_stop(Duration::from_secs(5));
+
+        assert!(undelivered.iter().any(|e| e.path == file));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn initial_scan_reports_existing_files() {
+        let dir_a = temp_dir();
+        let dir_b = temp_dir();
+        let existing = [
+            dir_a.join("one.bin"),
+            dir_a.join("two.bin"),
+            dir_b.join("three.bin"),
+        ];
+        for file in &existing {
+            fs::write(file, b"data").unwrap();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let _monitor = FileMonitor::with_initial_scan(
+            vec![dir_a.clone(), dir_b.clone()],
+            Arc::new(ChannelSink { sender: tx }),
+        )
+        .unwrap();
+        let events = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+
+        for file in &existing {
+            assert!(events
+                .iter()
+                .any(|e| &e.path == file && e.kind == FileChangeKind::Created));
+        }
+
+        let _ = fs::remove_dir_all(dir_a);
+        let _ = fs::remove_dir_all(dir_b);
+    }
+
+    #[test]
+    fn ignore_file_suppresses_matching_paths() {
+        let dir = temp_dir();
+        let ignore_path = dir.join(".syncignore");
+        fs::write(&ignore_path, "*.tmp\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let config = FileMonitorConfig {
+            paths: vec![dir.clone()],
+            ignore_file: Some(ignore_path.clone()),
+            ..FileMonitorConfig::default()
+        };
+        let mut monitor =
+            FileMonitor::with_config(config, Arc::new(ChannelSink { sender: tx })).unwrap();
+
+        let scratch = dir.join("autosave.tmp");
+        let asset = dir.join("hero.fbx");
+        fs::write(&scratch, b"x").unwrap();
+        fs::write(&asset, b"x").unwrap();
+        let events = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        assert!(events.iter().all(|e| e.path != scratch));
+        assert!(events.iter().any(|e| e.path == asset));
+
+        fs::write(&ignore_path, "*.tmp\n*.fbx\n").unwrap();
+        assert_eq!(monitor.reload_ignore_file().unwrap(), 2);
+        let _ = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        fs::write(&asset, b"y").unwrap();
+        let events = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        assert!(events.iter().all(|e| e.path != asset));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(feature = "inode_tracking")]
+    #[test]
+    fn rename_across_watched_directories_is_single_event() {
+        let dir_a = temp_dir();
+        let dir_b = temp_dir();
+        let from = dir_a.join("level.umap");
+        let to = dir_b.join("level.umap");
+        fs::write(&from, b"map").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _monitor = FileMonitor::watch(
+            vec![dir_a.clone(), dir_b.clone()],
+            Arc::new(ChannelSink { sender: tx }),
+        )
+        .unwrap();
+
+        fs::rename(&from, &to).unwrap();
+        let events = collect_events(&rx, Duration::from_millis(500), Duration::from_secs(5));
+        assert_eq!(events.len(), 1, "{events:?}");
+        assert_eq!(events[0].kind, FileChangeKind::Renamed { from, to });
+
+        let _ = fs::remove_dir_all(dir_a);
+        let _ = fs::remove_dir_all(dir_b);
+    }
+
+    #[test]
+    fn single_file_watch_ignores_siblings() {
+        let dir = temp_dir();
+        let tracked = dir.join("settings.toml");
+        let sibling = dir.join("other.toml");
+        fs::write(&tracked, b"a = 1").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _monitor =
+            FileMonitor::watch_single_file(tracked.clone(), Arc::new(ChannelSink { sender: tx }))
+                .unwrap();
+
+        fs::write(&sibling, b"b = 2").unwrap();
+        let events = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        assert!(events.is_empty(), "{events:?}");
+
+        fs::write(&tracked, b"a = 2").unwrap();
+        let events = collect_events(&rx, Duration::from_millis(300), Duration::from_secs(5));
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|e| e.path == tracked));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn metadata_changes_are_split() {
+        let path = PathBuf::from("/tmp/asset.bin");
+        let kind_of = |kind: MetadataKind| {
+            let event =
+                Event::new(EventKind::Modify(ModifyKind::Metadata(kind))).add_path(path.clone());
+            normalize_event(event, None, None).unwrap().kind
+        };
+        assert_eq!(
+            kind_of(MetadataKind::Permissions),
+            FileChangeKind::PermissionsChanged
+        );
+        assert_eq!(
+            kind_of(MetadataKind::WriteTime),
+            FileChangeKind::AttributesChanged
+        );
+        assert_eq!(
+            kind_of(MetadataKind::Any),
+            FileChangeKind::AttributesChanged
+        );
+    }
+
+    #[test]
+    fn ignore_attribute_changes_drops_touches() {
+        let shared = WorkerShared {
+            ignore_attribute_changes: true,
+            ..WorkerShared::default()
+        };
+        let event = |kind: ModifyKind| {
+            Event::new(EventKind::Modify(kind)).add_path(PathBuf::from("/tmp/scene.blend"))
+        };
+        assert!(shared
+            .normalize(event(ModifyKind::Metadata(MetadataKind::WriteTime)))
+            .is_none());
+        let chmod = shared.normalize(event(ModifyKind::Metadata(MetadataKind::Permissions)));
+        assert_eq!(chmod.unwrap().kind, FileChangeKind::PermissionsChanged);
+        let write = shared.normalize(event(ModifyKind::Data(notify::event::DataChange::Content)));
+        assert_eq!(write.unwrap().kind, FileChangeKind::Modified);
+    }
+
+    #[test]
+    fn coalesces_rapid_events_per_path() {
+        let shared = WorkerShared {
+            coalesce_window: Some(Duration::from_millis(50)),
+            ..WorkerShared::default()
+        };
+        let (backend, rx) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        let (tx, delivered) = mpsc::channel();
+        let watchers = WatcherSet {
+            watchers: Vec::new(),
+            tx: backend.clone(),
+            mode: RecursiveMode::NonRecursive,
+            connect: Box::new(connect_recommended),
+        };
+        let monitor = FileMonitor::start(
+            watchers,
+            rx,
+            Arc::new(ChannelSink { sender: tx }),
+            shared,
+            #[cfg(feature = "inode_tracking")]
+            inode::InodeTracker::primed(&[], Duration::from_millis(200)),
+        );
+
+        let (a, b) = (PathBuf::from("/tmp/a.psd"), PathBuf::from("/tmp/b.psd"));
+        let send = |kind: EventKind, path: &PathBuf| {
+            backend
+                .send(Ok(Event::new(kind).add_path(path.clone())))
+                .unwrap();
+        };
+        send(EventKind::Create(CreateKind::File), &a);
+        send(EventKind::Modify(ModifyKind::Any), &a);
+        send(EventKind::Modify(ModifyKind::Any), &b);
+
+        let events = collect_events(
+            &delivered,
+            Duration::from_millis(300),
+            Duration::from_secs(5),
+        );
+        assert_eq!(events.len(), 2, "{events:?}");
+        assert_eq!(
+            (&events[0].path, &events[0].kind),
+            (&a, &FileChangeKind::Modified)
+        );
+        assert_eq!(events[1].path, b);
+        let stats = monitor.stats();
+        assert_eq!(stats.events_received, 3);
+        assert_eq!(stats.events_dropped, 1);
+    }
+
+    struct BatchRecorder {
+        sender: mpsc::Sender<Vec<FileEvent>>,
+    }
+
+    impl FileEventSink for BatchRecorder {
+        fn handle(&self, event: FileEvent) {
+            self.handle_batch(vec![event]);
+        }
+
+        fn handle_batch(&self, events: Vec<FileEvent>) {
+            let _ = self.sender.send(events);
+        }
+    }
+
+    #[test]
+    fn batch_sink_groups_events_per_window() {
+        let (tx, rx) = mpsc::channel();
+        // The window never elapses during the test; batches are cut with explicit flushes.
+        let sink = batch_sink(
+            Arc::new(BatchRecorder { sender: tx.clone() }),
+            Duration::from_secs(3600),
+        );
+        let event = |path: &str, kind: FileChangeKind| FileEvent {
+            path: PathBuf::from(path),
+            kind,
+            occurred_at: SystemTime::now(),
+        };
+        sink.handle(event("/tmp/a", FileChangeKind::Created));
+        sink.handle(event("/tmp/b", FileChangeKind::Created));
+        sink.handle(event("/tmp/a", FileChangeKind::Modified));
+        assert!(rx.try_recv().is_err());
+
+        sink.flush();
+        let first = rx.try_recv().unwrap();
+        let second = rx.try_recv().unwrap();
+        let kinds = |batch: &[FileEvent]| batch.iter().map(|e| e.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(first[0].path, PathBuf::from("/tmp/a"));
+        assert_eq!(
+            kinds(&first),
+            vec![FileChangeKind::Created, FileChangeKind::Modified]
+        );
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].path, PathBuf::from("/tmp/b"));
+
+        // Arrives after the first flush, so it lands in a later batch.
+        sink.handle(event("/tmp/a", FileChangeKind::Removed));
+        sink.flush();
+        let third = rx.try_recv().unwrap();
+        assert_eq!(kinds(&third), vec![FileChangeKind::Removed]);
+
+        sink.handle(event("/tmp/c", FileChangeKind::Created));
+        drop(sink);
+        let flushed = rx.try_recv().unwrap();
+        assert_eq!(flushed[0].path, PathBuf::from("/tmp/c"));
+
+        // The background thread flushes once the window elapses.
+        let timed = batch_sink(
+            Arc::new(BatchRecorder { sender: tx }),
+            Duration::from_millis(10),
+        );
+        timed.handle(event("/tmp/d", FileChangeKind::Created));
+        let batch = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(batch[0].path, PathBuf::from("/tmp/d"));
+    }
+
+    #[test]
+    fn latency_tracking_reports_percentiles() {
+        let (tx, rx) = mpsc::channel();
+        let sink = with_latency_tracking(Arc::new(ChannelSink { sender: tx }), 1000);
+        assert_eq!(sink.p50_latency(), Duration::ZERO);
+
+        let aged = |secs: u64| FileEvent {
+            path: PathBuf::from("/tmp/a"),
+            kind: FileChangeKind::Modified,
+            occurred_at: SystemTime::now() - Duration::from_secs(secs),
+        };
+        for secs in (1..=100).rev() {
+            sink.handle(aged(secs));
+        }
+        assert_eq!(rx.try_iter().count(), 100);
+        // Each latency is its age plus a little delivery time.
+        let within = |latency: Duration, secs: u64| {
+            latency >= Duration::from_secs(secs) && latency < Duration::from_secs(secs + 1)
+        };
+        assert!(within(sink.p50_latency(), 50), "{:?}", sink.p50_latency());
+        assert!(within(sink.p99_latency(), 99), "{:?}", sink.p99_latency());
+
+        // Only the most recent samples are kept.
+        let small = with_latency_tracking(
+            Arc::new(ChannelSink {
+                sender: mpsc::channel().0,
+            }),
+            10,
+        );
+        for secs in 1..=100 {
+            small.handle(aged(secs));
+        }
+        assert!(within(small.p50_latency(), 95), "{:?}", small.p50_latency());
+        assert!(
+            within(small.p99_latency(), 100),
+            "{:?}",
+            small.p99_latency()
+        );
+
+        // Events the inner sink hands back are not counted as delivered.
+        let rejecting = with_latency_tracking(
+            Arc::new(ChannelSink {
+                sender: mpsc::channel().0,
+            }),
+            10,
+        );
+        assert!(rejecting.try_handle(aged(5)).is_err());
+        assert_eq!(rejecting.p50_latency(), Duration::ZERO);
+    }
+
+    /// Stands in for a platform watcher; events are injected through the connector's sender.
+    struct FakeWatcher;
+
+    impl Watcher for FakeWatcher {
+        fn new<F: notify::EventHandler>(_handler: F, _config: Config) -> notify::Result<Self> {
+            Ok(FakeWatcher)
+        }
+
+        fn watch(&mut self, _path: &Path, _mode: RecursiveMode) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn unwatch(&mut self, _path: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn kind() -> notify::WatcherKind {
+            notify::WatcherKind::NullWatcher
+        }
+    }
+
+    /// Connector producing `FakeWatcher`s, failing the calls (numbered from 1) for which `fails`
+    /// holds. Also returns the number of calls so far and the sender handed to the first one.
+    #[allow(clippy::type_complexity)]
+    fn fake_connect(
+        fails: impl Fn(usize) -> bool + Send + Sync + 'static,
+    ) -> (
+        Box<Connect>,
+        Arc<Mutex<usize>>,
+        Arc<Mutex<Option<mpsc::SyncSender<BackendEvent>>>>,
+    ) {
+        let calls = Arc::new(Mutex::new(0));
+        let sender = Arc::new(Mutex::new(None));
+        let (calls_seen, sender_seen) = (calls.clone(), sender.clone());
+        let connect = move |_: &Path, _: RecursiveMode, tx: mpsc::SyncSender<BackendEvent>| {
+            let mut calls = calls_seen.lock().unwrap();
+            *calls += 1;
+            sender_seen.lock().unwrap().get_or_insert(tx);
+            if fails(*calls) {
+                return Err(notify::Error::generic("mount unavailable"));
+            }
+            Ok(Box::new(FakeWatcher) as Box<dyn Watcher + Send>)
+        };
+        (Box::new(connect), calls, sender)
+    }
+
+    fn monitor_with_fake(
+        connect: Box<Connect>,
+        policy: Option<ReconnectPolicy>,
+    ) -> (FileMonitor, mpsc::Receiver<FileEvent>) {
+        let (tx, rx) = mpsc::channel();
+        let config = FileMonitorConfig {
+            paths: vec![PathBuf::from("/mnt/share")],
+            reconnect_policy: policy,
+            ..FileMonitorConfig::default()
+        };
+        let sink = Arc::new(ChannelSink { sender: tx });
+        let monitor = FileMonitor::build(config, None, sink, connect).unwrap();
+        (monitor, rx)
+    }
+
+    #[test]
+    fn reconnects_after_watch_error() {
+        // Initial watch succeeds, the first retry fails, the second succeeds.
+        let (connect, calls, sender) = fake_connect(|call| call == 2);
+        let policy = ReconnectPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(5),
+        };
+        let (monitor, rx) = monitor_with_fake(connect, Some(policy));
+
+        let lost = notify::Error::generic("stale handle").add_path("/mnt/share/a.txt".into());
+        let tx = sender.lock().unwrap().clone().unwrap();
+        tx.send(Err(lost)).unwrap();
+
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.path, PathBuf::from("/mnt/share"));
+        assert_eq!(event.kind, FileChangeKind::Other);
+        assert_eq!(*calls.lock().unwrap(), 3);
+
+        // The worker keeps running after reconnecting.
+        let write = Event::new(EventKind::Create(CreateKind::File)).add_path("/mnt/share/b".into());
+        tx.send(Ok(write)).unwrap();
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.kind, FileChangeKind::Created);
+        let stats = monitor.stats();
+        assert_eq!(stats.reconnects, 1);
+        assert_eq!(stats.events_dropped, 0);
+        drop(monitor);
+    }
+
+    #[test]
+    fn delivers_events_while_waiting_to_reconnect() {
+        let (connect, calls, sender) = fake_connect(|_| false);
+        let policy = ReconnectPolicy {
+            max_attempts: 1,
+            backoff: Duration::from_secs(60),
+        };
+        let (monitor, rx) = monitor_with_fake(connect, Some(policy));
+
+        let tx = sender.lock().unwrap().clone().unwrap();
+        tx.send(Err(notify::Error::generic("stale handle")))
+            .unwrap();
+        let write = Event::new(EventKind::Create(CreateKind::File)).add_path("/mnt/share/b".into());
+        tx.send(Ok(write)).unwrap();
+
+        // The backoff has not elapsed, yet the worker is not stuck behind it.
+        let event = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(event.kind, FileChangeKind::Created);
+        assert_eq!(*calls.lock().unwrap(), 1);
+        assert_eq!(monitor.stats().reconnects, 0);
+    }
+
+    #[test]
+    fn gives_up_after_max_reconnect_attempts() {
+        let (connect, calls, sender) = fake_connect(|call| call > 1);
+        let policy = ReconnectPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_millis(5),
+        };
+        let (_monitor, rx) = monitor_with_fake(connect, Some(policy));
+
+        let tx = sender.lock().unwrap().clone().unwrap();
+        tx.send(Err(notify::Error::generic("gone"))).unwrap();
+        // The worker stops, dropping the sink, so the receiver disconnects without an event.
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(*calls.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn stops_on_watch_error_without_policy() {
+        let (connect, calls, sender) = fake_connect(|_| false);
+        let (_monitor, rx) = monitor_with_fake(connect, None);
+
+        let tx = sender.lock().unwrap().clone().unwrap();
+        tx.send(Err(notify::Error::generic("gone"))).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
 }
@@ -1,21 +1,66 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use ulid::Ulid;
 
 use crate::{
-    ChunkRef, DeviceId, FileId, TransferDirection, TransferSession, TransferSessionId,
-    TransferStatus, VersionId,
+    ChunkRef, ContentEncoding, DeviceId, FileId, TransferDirection, TransferSession,
+    TransferSessionId, TransferStatus, VersionId,
 };
 
 /// Plan of chunks to send or fetch. Derived from a VersionRecord's chunk list.
+///
+/// Build plans with `TransferPlan::new`; after editing `chunks` directly, pass the plan through
+/// `TransferPlan::with_index` so `has_chunk_at_offset` stays a hash lookup.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StoredPlan")]
 pub struct TransferPlan {
     pub file_id: FileId,
     pub version_id: VersionId,
     pub direction: TransferDirection,
+    /// Encoding of the chunk payloads; the receiver decodes after reassembly.
+    #[serde(default)]
+    pub encoding: ContentEncoding,
     pub chunks: Vec<ChunkRef>,
+    /// `compute_plan_checksum` at send time, so the receiver can detect a corrupted plan.
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Chunk position by offset. Not serialized; rebuilt on deserializing.
+    #[serde(skip)]
+    chunk_index: HashMap<u64, usize>,
+}
+
+/// Serialized form of `TransferPlan`: everything but the offset index.
+#[derive(Deserialize)]
+struct StoredPlan {
+    file_id: FileId,
+    version_id: VersionId,
+    direction: TransferDirection,
+    #[serde(default)]
+    encoding: ContentEncoding,
+    chunks: Vec<ChunkRef>,
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+impl From<StoredPlan> for TransferPlan {
+    fn from(stored: StoredPlan) -> Self {
+        let mut plan = TransferPlan::new(
+            stored.file_id,
+            stored.version_id,
+            stored.direction,
+            stored.encoding,
+            stored.chunks,
+        );
+        plan.checksum = stored.checksum;
+        plan
+    }
 }
 
 /// Tracks in-flight or completed chunks for resumable transfer.
@@ -25,13 +70,33 @@ pub struct TransferProgress {
     pub started_at: SystemTime,
     pub completed_chunks: HashSet<u64>, // keyed by chunk offset
     pub failed_chunks: HashSet<u64>,    // for retry bookkeeping
+    /// Chunks given up on after repeated failures; never retried.
+    #[serde(default)]
+    pub abandoned_chunks: HashSet<u64>,
+    /// Bytes actually moved over the wire for completed chunks, including encryption overhead.
+    #[serde(default)]
+    pub bytes_transferred: u64,
+    /// Last time a chunk was marked done (or the start, if none yet); used to detect stalls.
+    #[serde(default = "SystemTime::now")]
+    pub last_progress_at: SystemTime,
+}
+
+/// A set of per-file plans transferred together (e.g., syncing a folder's worth of files).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferBatch {
+    pub plans: Vec<TransferPlan>,
+    pub progresses: HashMap<FileId, TransferProgress>,
 }
 
 /// Retry policy for interrupted or failed chunks.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RetryPolicy {
     pub max_attempts: u32,
+    /// Wait before the first retry; the base for exponential backoff.
     pub backoff: Duration,
+    /// Double the wait on every attempt instead of using `backoff` each time.
+    #[serde(default)]
+    pub exponential: bool,
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -42,22 +107,33 @@ pub enum TransferError {
     MaxRetries(u64),
     #[error("transfer already completed")]
     Completed,
+    #[error("cannot load transfer checkpoint: {0}")]
+    Checkpoint(String),
+    #[error("plan checksum {expected} does not match chunk list ({actual})")]
+    ChecksumMismatch { expected: String, actual: String },
 }
 
 impl TransferProgress {
     pub fn new(session_id: TransferSessionId) -> Self {
+        let now = SystemTime::now();
         Self {
             session_id,
-            started_at: SystemTime::now(),
+            started_at: now,
             completed_chunks: HashSet::new(),
             failed_chunks: HashSet::new(),
+            abandoned_chunks: HashSet::new(),
+            bytes_transferred: 0,
+            last_progress_at: now,
         }
     }
 
-    /// Mark a chunk as done. Idempotent.
-    pub fn mark_done(&mut self, offset: u64) {
+    /// Mark a chunk as done after moving `bytes` over the wire. Completion is idempotent, but
+    /// `bytes` always counts towards `bytes_transferred` since a resend still uses bandwidth.
+    pub fn mark_done(&mut self, offset: u64, bytes: u64) {
+        self.bytes_transferred = self.bytes_transferred.saturating_add(bytes);
         self.completed_chunks.insert(offset);
         self.failed_chunks.remove(&offset);
+        self.last_progress_at = SystemTime::now();
     }
 
     /// Mark a chunk failure for retry tracking.
@@ -67,34 +143,312 @@ impl TransferProgress {
         }
     }
 
+    /// Stop retrying a chunk: moves it from `failed_chunks` to `abandoned_chunks`. Completed
+    /// chunks are left alone.
+    pub fn abandon_chunk(&mut self, offset: u64) {
+        if !self.completed_chunks.contains(&offset) {
+            self.failed_chunks.remove(&offset);
+            self.abandoned_chunks.insert(offset);
+        }
+    }
+
     pub fn is_complete(&self, plan: &TransferPlan) -> bool {
         plan.chunks
             .iter()
             .all(|c| self.completed_chunks.contains(&c.offset))
     }
+
+    /// Combine progress from two devices so one can resume where the other left off.
+    ///
+    /// A chunk completed on either side is done; a chunk counts as failed only if both sides
+    /// failed it, and as abandoned if either side abandoned it without completing it. Bytes
+    /// transferred by both sides are added up. Keeps `primary`'s session id, the earlier start
+    /// time, and the later progress time.
+    pub fn merge(primary: TransferProgress, secondary: TransferProgress) -> TransferProgress {
+        let failed_chunks = primary
+            .failed_chunks
+            .intersection(&secondary.failed_chunks)
+            .copied()
+            .collect();
+        let completed_chunks = &primary.completed_chunks | &secondary.completed_chunks;
+        let abandoned_chunks =
+            &(&primary.abandoned_chunks | &secondary.abandoned_chunks) - &completed_chunks;
+        TransferProgress {
+            session_id: primary.session_id,
+            started_at: primary.started_at.min(secondary.started_at),
+            completed_chunks,
+            failed_chunks,
+            abandoned_chunks,
+            bytes_transferred: primary
+                .bytes_transferred
+                .saturating_add(secondary.bytes_transferred),
+            last_progress_at: primary.last_progress_at.max(secondary.last_progress_at),
+        }
+    }
+}
+
+/// Persist progress so a crashed transfer can resume. Writes a sibling temp file, syncs it,
+/// then renames it over `path`, so readers see either the old or the new checkpoint.
+pub fn checkpoint_to_path(progress: &TransferProgress, path: &Path) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let mut file = fs::File::create(&tmp)?;
+    serde_json::to_writer(&mut file, progress)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Load progress written by `checkpoint_to_path`.
+pub fn resume_from_path(path: &Path) -> Result<TransferProgress, TransferError> {
+    let bytes = fs::read(path).map_err(|err| TransferError::Checkpoint(err.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|err| TransferError::Checkpoint(err.to_string()))
+}
+
+impl TransferPlan {
+    /// Plan for `chunks` without a checksum, with its offset index built.
+    pub fn new(
+        file_id: FileId,
+        version_id: VersionId,
+        direction: TransferDirection,
+        encoding: ContentEncoding,
+        chunks: Vec<ChunkRef>,
+    ) -> TransferPlan {
+        TransferPlan::with_index(TransferPlan {
+            file_id,
+            version_id,
+            direction,
+            encoding,
+            chunks,
+            checksum: None,
+            chunk_index: HashMap::new(),
+        })
+    }
+
+    /// Rebuild the offset index from `chunks`, so `has_chunk_at_offset` is a hash lookup.
+    pub fn with_index(mut plan: TransferPlan) -> TransferPlan {
+        plan.chunk_index = plan
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.offset, i))
+            .collect();
+        plan
+    }
+
+    /// Reorder chunks so those lying entirely inside a `(start_offset, end_offset)` range come
+    /// first, grouped by range in the order given and by offset within each range; the rest
+    /// follow in offset order. Lets `next_chunk` stream e.g. the start of a video first.
+    pub fn priority_order_chunks(
+        mut plan: TransferPlan,
+        priority_ranges: &[(u64, u64)],
+    ) -> TransferPlan {
+        plan.chunks.sort_by_key(|c| {
+            let range = priority_ranges
+                .iter()
+                .position(|&(start, end)| c.offset >= start && c.offset + c.length <= end)
+                .unwrap_or(priority_ranges.len());
+            (range, c.offset)
+        });
+        if plan.checksum.is_some() {
+            plan.checksum = Some(compute_plan_checksum(&plan));
+        }
+        TransferPlan::with_index(plan)
+    }
 }
 
-/// Compute the next chunk to send/fetch, skipping completed items.
+/// Hex SHA-256 over each chunk's big-endian offset and length followed by its hash, in plan
+/// order.
+pub fn compute_plan_checksum(plan: &TransferPlan) -> String {
+    let mut hasher = Sha256::new();
+    for chunk in &plan.chunks {
+        hasher.update(chunk.offset.to_be_bytes());
+        hasher.update(chunk.length.to_be_bytes());
+        hasher.update(chunk.hash.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Check the plan's chunk list against its `checksum`. Plans without a checksum pass.
+pub fn verify_plan_checksum(plan: &TransferPlan) -> Result<(), TransferError> {
+    let Some(expected) = &plan.checksum else {
+        return Ok(());
+    };
+    let actual = compute_plan_checksum(plan);
+    if *expected == actual {
+        Ok(())
+    } else {
+        Err(TransferError::ChecksumMismatch {
+            expected: expected.clone(),
+            actual,
+        })
+    }
+}
+
+/// Sum of chunk lengths in the plan.
+pub fn total_bytes(plan: &TransferPlan) -> u64 {
+    plan.chunks.iter().map(|c| c.length).sum()
+}
+
+pub fn chunk_count(plan: &TransferPlan) -> usize {
+    plan.chunks.len()
+}
+
+/// Whether the plan has a chunk starting at `offset`. A hash lookup while the offset index
+/// covers `chunks`; scans the list if chunks were added or removed without `with_index`.
+pub fn has_chunk_at_offset(plan: &TransferPlan, offset: u64) -> bool {
+    if plan.chunk_index.len() != plan.chunks.len() {
+        return plan.chunks.iter().any(|c| c.offset == offset);
+    }
+    plan.chunk_index
+        .get(&offset)
+        .is_some_and(|&i| plan.chunks.get(i).is_some_and(|c| c.offset == offset))
+}
+
+impl TransferBatch {
+    /// Start a batch with fresh progress (and a new session id) for every plan.
+    pub fn new(plans: Vec<TransferPlan>) -> TransferBatch {
+        let progresses = plans
+            .iter()
+            .map(|p| (p.file_id, TransferProgress::new(Ulid::new())))
+            .collect();
+        TransferBatch { plans, progresses }
+    }
+}
+
+/// Average completion across all plans, as a percentage in `0.0..=100.0`.
+///
+/// Plans without chunks count as complete; an empty batch reports 100.
+pub fn overall_progress(batch: &TransferBatch) -> f32 {
+    if batch.plans.is_empty() {
+        return 100.0;
+    }
+    let total: f32 = batch
+        .plans
+        .iter()
+        .map(|plan| {
+            if plan.chunks.is_empty() {
+                return 100.0;
+            }
+            let done = batch.progresses.get(&plan.file_id).map_or(0, |progress| {
+                plan.chunks
+                    .iter()
+                    .filter(|c| progress.completed_chunks.contains(&c.offset))
+                    .count()
+            });
+            done as f32 / plan.chunks.len() as f32 * 100.0
+        })
+        .sum();
+    total / batch.plans.len() as f32
+}
+
+/// Files whose every chunk has been transferred.
+pub fn completed_files(batch: &TransferBatch) -> Vec<FileId> {
+    batch
+        .plans
+        .iter()
+        .filter(|plan| {
+            batch
+                .progresses
+                .get(&plan.file_id)
+                .is_some_and(|progress| progress.is_complete(plan))
+        })
+        .map(|plan| plan.file_id)
+        .collect()
+}
+
+/// Files with at least one chunk still marked failed.
+pub fn failed_files(batch: &TransferBatch) -> Vec<FileId> {
+    batch
+        .plans
+        .iter()
+        .filter(|plan| {
+            batch
+                .progresses
+                .get(&plan.file_id)
+                .is_some_and(|progress| !progress.failed_chunks.is_empty())
+        })
+        .map(|plan| plan.file_id)
+        .collect()
+}
+
+/// Compute the next chunk to send/fetch, skipping completed and abandoned items.
 pub fn next_chunk(plan: &TransferPlan, progress: &TransferProgress) -> Option<ChunkRef> {
     plan.chunks
         .iter()
-        .find(|c| !progress.completed_chunks.contains(&c.offset))
+        .find(|c| {
+            !progress.completed_chunks.contains(&c.offset)
+                && !progress.abandoned_chunks.contains(&c.offset)
+        })
         .cloned()
 }
 
-/// Decide if a chunk can be retried under the policy.
+/// Bytes moved over the wire so far; same as `bytes_transferred`.
+pub fn bandwidth_used(progress: &TransferProgress) -> u64 {
+    progress.bytes_transferred
+}
+
+/// True if any chunk has been abandoned.
+pub fn has_abandoned(progress: &TransferProgress) -> bool {
+    !progress.abandoned_chunks.is_empty()
+}
+
+/// Terminal status once nothing is left to transfer: `Completed` if every chunk is done,
+/// `PartiallyComplete` (abandoned offsets in plan order) if every remaining chunk was
+/// abandoned. `None` while chunks remain to be tried.
+pub fn settled_status(plan: &TransferPlan, progress: &TransferProgress) -> Option<TransferStatus> {
+    if progress.is_complete(plan) {
+        return Some(TransferStatus::Completed);
+    }
+    if next_chunk(plan, progress).is_some() {
+        return None;
+    }
+    let abandoned = plan
+        .chunks
+        .iter()
+        .map(|c| c.offset)
+        .filter(|offset| !progress.completed_chunks.contains(offset))
+        .collect();
+    Some(TransferStatus::PartiallyComplete { abandoned })
+}
+
+/// Decide if a chunk can be retried under the policy; returns how long to wait before
+/// retrying. With exponential backoff, attempt `n` waits `backoff * 2^n` (saturating).
 pub fn can_retry(
     offset: u64,
     attempt: u32,
     policy: &RetryPolicy,
-) -> Result<(), TransferError> {
+) -> Result<Duration, TransferError> {
     if attempt >= policy.max_attempts {
         return Err(TransferError::MaxRetries(offset));
     }
-    Ok(())
+    if !policy.exponential {
+        return Ok(policy.backoff);
+    }
+    Ok(policy.backoff.saturating_mul(2u32.saturating_pow(attempt)))
+}
+
+/// True once a session can make no further progress (`Completed`, `PartiallyComplete`, or
+/// `Failed`).
+pub fn is_terminal(status: &TransferStatus) -> bool {
+    matches!(
+        status,
+        TransferStatus::Completed
+            | TransferStatus::PartiallyComplete { .. }
+            | TransferStatus::Failed(_)
+    )
+}
+
+/// True only for `Paused` sessions, which keep their progress and can pick up where they left off.
+pub fn can_resume(status: &TransferStatus) -> bool {
+    matches!(status, TransferStatus::Paused { .. })
 }
 
 /// Create a TransferSession view from a plan/progress/status.
+///
+/// A `Paused` session is reported as-is; callers should not enqueue new chunk work for it
+/// until it is resumed.
 pub fn to_session(
     plan: &TransferPlan,
     progress: &TransferProgress,
@@ -111,9 +465,29 @@ pub fn to_session(
         active_chunks: plan.chunks.clone(),
         retry_count: progress.failed_chunks.len() as u32,
         status,
+        started_at: progress.started_at,
     }
 }
 
+/// Time since the session started; zero if `now` is earlier than the start.
+pub fn elapsed(session: &TransferSession, now: SystemTime) -> Duration {
+    now.duration_since(session.started_at).unwrap_or_default()
+}
+
+/// True if an in-progress session has not completed a chunk for at least `stall_threshold`.
+/// Paused and terminal sessions are never stalled.
+pub fn is_stalled(
+    session: &TransferSession,
+    progress: &TransferProgress,
+    stall_threshold: Duration,
+    now: SystemTime,
+) -> bool {
+    session.status == TransferStatus::InProgress
+        && now
+            .duration_since(progress.last_progress_at)
+            .is_ok_and(|idle| idle >= stall_threshold)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,23 +497,26 @@ mod tests {
     }
 
     fn plan() -> TransferPlan {
-        TransferPlan {
-            file_id: ulid(),
-            version_id: ulid(),
-            direction: TransferDirection::Push,
-            chunks: vec![
+        TransferPlan::new(
+            ulid(),
+            ulid(),
+            TransferDirection::Push,
+            ContentEncoding::Raw,
+            vec![
                 ChunkRef {
                     offset: 0,
                     length: 10,
                     hash: "h0".into(),
+                    sequence: 0,
                 },
                 ChunkRef {
                     offset: 10,
                     length: 10,
                     hash: "h1".into(),
+                    sequence: 1,
                 },
             ],
-        }
+        )
     }
 
     #[test]
@@ -148,10 +525,10 @@ mod tests {
         let mut progress = TransferProgress::new(ulid());
         let c1 = next_chunk(&plan, &progress).unwrap();
         assert_eq!(c1.offset, 0);
-        progress.mark_done(c1.offset);
+        progress.mark_done(c1.offset, c1.length);
         let c2 = next_chunk(&plan, &progress).unwrap();
         assert_eq!(c2.offset, 10);
-        progress.mark_done(c2.offset);
+        progress.mark_done(c2.offset, c2.length);
         assert!(next_chunk(&plan, &progress).is_none());
         assert!(progress.is_complete(&plan));
     }
@@ -161,12 +538,27 @@ mod tests {
         let policy = RetryPolicy {
             max_attempts: 3,
             backoff: Duration::from_secs(1),
+            exponential: false,
         };
-        assert!(can_retry(0, 0, &policy).is_ok());
-        assert!(can_retry(0, 2, &policy).is_ok());
+        assert_eq!(can_retry(0, 0, &policy), Ok(Duration::from_secs(1)));
+        assert_eq!(can_retry(0, 2, &policy), Ok(Duration::from_secs(1)));
         assert!(can_retry(0, 3, &policy).is_err());
     }
 
+    #[test]
+    fn exponential_backoff_doubles_per_attempt() {
+        let base = Duration::from_millis(250);
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: base,
+            exponential: true,
+        };
+        assert_eq!(can_retry(7, 0, &policy), Ok(base));
+        assert_eq!(can_retry(7, 1, &policy), Ok(base * 2));
+        assert_eq!(can_retry(7, 2, &policy), Ok(base * 4));
+        assert_eq!(can_retry(7, 3, &policy), Err(TransferError::MaxRetries(7)));
+    }
+
     #[test]
     fn session_view_is_composed() {
         let plan = plan();
@@ -181,4 +573,258 @@ mod tests {
         assert_eq!(session.file_id, plan.file_id);
         assert_eq!(session.active_chunks.len(), 2);
     }
+
+    #[test]
+    fn merge_unions_completed_and_intersects_failed() {
+        let mut primary = TransferProgress::new(ulid());
+        let mut secondary = TransferProgress::new(ulid());
+        secondary.started_at = primary.started_at - Duration::from_secs(60);
+        primary.mark_done(0, 10);
+        primary.mark_failed(10);
+        secondary.mark_failed(10);
+        secondary.mark_done(20, 10);
+        secondary.mark_failed(30);
+
+        let merged = TransferProgress::merge(primary.clone(), secondary.clone());
+        assert_eq!(merged.session_id, primary.session_id);
+        assert_eq!(merged.started_at, secondary.started_at);
+        assert_eq!(merged.completed_chunks, HashSet::from([0, 20]));
+        assert_eq!(merged.failed_chunks, HashSet::from([10]));
+        assert_eq!(merged.bytes_transferred, 20);
+    }
+
+    #[test]
+    fn plan_size_accessors_and_offset_lookup() {
+        let plan = plan();
+        assert_eq!(total_bytes(&plan), 20);
+        assert_eq!(chunk_count(&plan), 2);
+        assert_eq!(plan.chunk_index.get(&10), Some(&1));
+        assert!(has_chunk_at_offset(&plan, 10));
+        assert!(!has_chunk_at_offset(&plan, 5));
+
+        // The index is rebuilt after a round trip through serde.
+        let json = serde_json::to_string(&plan).unwrap();
+        let restored: TransferPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, plan);
+        assert!(has_chunk_at_offset(&restored, 10));
+
+        let empty = TransferPlan::with_index(TransferPlan {
+            chunks: Vec::new(),
+            ..plan
+        });
+        assert_eq!(total_bytes(&empty), 0);
+        assert_eq!(chunk_count(&empty), 0);
+        assert!(!has_chunk_at_offset(&empty, 0));
+    }
+
+    #[test]
+    fn detects_stalled_sessions() {
+        let plan = plan();
+        let threshold = Duration::from_secs(30);
+        let now = SystemTime::now();
+        let mut progress = TransferProgress::new(ulid());
+        progress.started_at = now - threshold * 3;
+        progress.last_progress_at = now - threshold * 2;
+        let session = to_session(&plan, &progress, ulid(), ulid(), TransferStatus::InProgress);
+        assert_eq!(elapsed(&session, now), threshold * 3);
+        assert!(is_stalled(&session, &progress, threshold, now));
+
+        progress.mark_done(0, 10);
+        assert!(!is_stalled(
+            &session,
+            &progress,
+            threshold,
+            SystemTime::now()
+        ));
+        let paused = TransferSession {
+            status: TransferStatus::Paused {
+                reason: "metered".into(),
+            },
+            ..session
+        };
+        assert!(!is_stalled(
+            &paused,
+            &progress,
+            threshold,
+            now + threshold * 4
+        ));
+    }
+
+    #[test]
+    fn terminal_and_resumable_statuses() {
+        let paused = TransferStatus::Paused {
+            reason: "waiting for wi-fi".into(),
+        };
+        assert!(!is_terminal(&TransferStatus::InProgress));
+        assert!(!is_terminal(&paused));
+        assert!(is_terminal(&TransferStatus::Completed));
+        assert!(is_terminal(&TransferStatus::Failed("io".into())));
+        assert!(is_terminal(&TransferStatus::PartiallyComplete {
+            abandoned: vec![0]
+        }));
+
+        assert!(!can_resume(&TransferStatus::InProgress));
+        assert!(can_resume(&paused));
+        assert!(!can_resume(&TransferStatus::Completed));
+        assert!(!can_resume(&TransferStatus::Failed("io".into())));
+    }
+
+    #[test]
+    fn batch_reports_progress_and_outcomes() {
+        let (a, b) = (plan(), plan());
+        let (a_id, b_id) = (a.file_id, b.file_id);
+        let mut batch = TransferBatch::new(vec![a.clone(), b.clone()]);
+        assert_eq!(overall_progress(&batch), 0.0);
+
+        let progress_a = batch.progresses.get_mut(&a_id).unwrap();
+        progress_a.mark_done(0, 10);
+        progress_a.mark_done(10, 10);
+        batch.progresses.get_mut(&b_id).unwrap().mark_failed(10);
+        assert_eq!(overall_progress(&batch), 50.0);
+        assert_eq!(completed_files(&batch), vec![a_id]);
+        assert_eq!(failed_files(&batch), vec![b_id]);
+
+        let progress_b = batch.progresses.get_mut(&b_id).unwrap();
+        progress_b.mark_done(0, 10);
+        progress_b.mark_done(10, 10);
+        assert_eq!(overall_progress(&batch), 100.0);
+        assert_eq!(completed_files(&batch), vec![a_id, b_id]);
+        assert!(failed_files(&batch).is_empty());
+    }
+
+    #[test]
+    fn priority_ranges_are_fetched_first() {
+        let mut plan = plan();
+        // Chunks arrive in hash order rather than offset order.
+        plan.chunks = [2048u64, 0, 1536, 512, 1024]
+            .into_iter()
+            .map(|offset| ChunkRef {
+                offset,
+                length: 512,
+                hash: format!("h{offset}"),
+                sequence: (offset / 512) as u32,
+            })
+            .collect();
+        let plan = TransferPlan::priority_order_chunks(plan, &[(0, 1024)]);
+
+        let mut progress = TransferProgress::new(ulid());
+        let mut order = Vec::new();
+        while let Some(chunk) = next_chunk(&plan, &progress) {
+            order.push(chunk.offset);
+            progress.mark_done(chunk.offset, chunk.length);
+        }
+        assert_eq!(order, vec![0, 512, 1024, 1536, 2048]);
+        assert_eq!(plan.chunk_index.get(&0), Some(&0));
+        assert_eq!(plan.chunk_index.get(&2048), Some(&4));
+
+        let plan = TransferPlan::priority_order_chunks(plan, &[(1024, 2048)]);
+        let offsets: Vec<u64> = plan.chunks.iter().map(|c| c.offset).collect();
+        assert_eq!(offsets, vec![1024, 1536, 0, 512, 2048]);
+    }
+
+    #[test]
+    fn checkpoint_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer.checkpoint");
+        let mut progress = TransferProgress::new(ulid());
+        progress.mark_done(0, 10);
+        progress.mark_failed(10);
+        checkpoint_to_path(&progress, &path).unwrap();
+        progress.mark_done(10, 10);
+        checkpoint_to_path(&progress, &path).unwrap();
+
+        // A fresh process only has the file to go on.
+        let restored = resume_from_path(&path).unwrap();
+        assert_eq!(restored, progress);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(matches!(
+            resume_from_path(&dir.path().join("missing")),
+            Err(TransferError::Checkpoint(_))
+        ));
+        std::fs::write(&path, b"{").unwrap();
+        assert!(matches!(
+            resume_from_path(&path),
+            Err(TransferError::Checkpoint(_))
+        ));
+    }
+
+    #[test]
+    fn checkpoints_from_older_builds_still_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer.checkpoint");
+        let mut progress = TransferProgress::new(ulid());
+        progress.mark_done(0, 10);
+        let mut json = serde_json::to_value(&progress).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("abandoned_chunks");
+        fields.remove("bytes_transferred");
+        std::fs::write(&path, json.to_string()).unwrap();
+
+        let restored = resume_from_path(&path).unwrap();
+        assert!(restored.abandoned_chunks.is_empty());
+        assert_eq!(restored.bytes_transferred, 0);
+        assert_eq!(restored.completed_chunks, progress.completed_chunks);
+    }
+
+    #[test]
+    fn abandoned_chunks_are_skipped_but_not_complete() {
+        let plan = plan();
+        let mut progress = TransferProgress::new(ulid());
+        progress.mark_done(0, 10);
+        progress.mark_failed(10);
+        assert!(!has_abandoned(&progress));
+        assert_eq!(settled_status(&plan, &progress), None);
+
+        progress.abandon_chunk(10);
+        assert!(has_abandoned(&progress));
+        assert!(progress.failed_chunks.is_empty());
+        assert!(!progress.is_complete(&plan));
+        assert_eq!(next_chunk(&plan, &progress), None);
+        assert_eq!(
+            settled_status(&plan, &progress),
+            Some(TransferStatus::PartiallyComplete {
+                abandoned: vec![10]
+            })
+        );
+
+        progress.abandon_chunk(0);
+        assert!(!progress.abandoned_chunks.contains(&0));
+    }
+
+    #[test]
+    fn plan_checksum_detects_mutated_chunks() {
+        let mut plan = plan();
+        verify_plan_checksum(&plan).unwrap();
+        plan.checksum = Some(compute_plan_checksum(&plan));
+        verify_plan_checksum(&plan).unwrap();
+
+        let sent = plan.clone();
+        plan.chunks[1].length += 1;
+        assert!(matches!(
+            verify_plan_checksum(&plan),
+            Err(TransferError::ChecksumMismatch { .. })
+        ));
+        plan = sent.clone();
+        plan.chunks[0].hash = "tampered".into();
+        assert!(verify_plan_checksum(&plan).is_err());
+
+        // Reordering on the sending side keeps the checksum current.
+        let reordered = TransferPlan::priority_order_chunks(sent, &[(10, 20)]);
+        assert_eq!(reordered.chunks[0].offset, 10);
+        verify_plan_checksum(&reordered).unwrap();
+    }
+
+    #[test]
+    fn accumulates_bytes_transferred() {
+        let mut progress = TransferProgress::new(ulid());
+        assert_eq!(bandwidth_used(&progress), 0);
+        progress.mark_done(0, 28);
+        progress.mark_done(10, 26);
+        assert_eq!(progress.bytes_transferred, 54);
+        // A resent chunk is already complete but still cost bandwidth.
+        progress.mark_done(0, 28);
+        assert_eq!(bandwidth_used(&progress), 82);
+        assert_eq!(progress.completed_chunks.len(), 2);
+    }
 }
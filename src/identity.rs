@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::time::{Duration, SystemTime};
 
@@ -5,19 +7,45 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use ulid::Ulid;
 
-use crate::model::DeviceId;
+use crate::model::{DeviceId, FileRecord};
 
 pub type UserId = Ulid;
 pub type SessionId = Ulid;
 
 /// Device-authenticated identity. Keys are represented generically to avoid
 /// binding to a crypto library here.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Equality and hashing use `device_id` only: re-attestation or key rotation yields a new
+/// record for the same device, and sets of identities should hold one entry per device.
+/// Compare fields explicitly when the key or attestation time matters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceIdentity {
     pub device_id: DeviceId,
     pub user_id: UserId,
     pub device_public_key: Vec<u8>, // e.g., Ed25519 public key bytes
     pub attested_at: SystemTime,
+    /// Encryption `key_id`s this device is authorized to use.
+    #[serde(default)]
+    pub trusted_key_ids: Vec<String>,
+}
+
+/// True if `identity` holds the key `file` is encrypted with.
+pub fn is_trusted_for_file(identity: &DeviceIdentity, file: &FileRecord) -> bool {
+    identity.trusted_key_ids.contains(&file.encryption.key_id)
+}
+
+impl PartialEq for DeviceIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.device_id == other.device_id
+    }
+}
+
+impl Eq for DeviceIdentity {}
+
+impl Hash for DeviceIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.device_id.hash(state);
+    }
 }
 
 /// User authentication token (opaque bearer or signed proof).
@@ -38,8 +66,15 @@ pub struct PeerAdvertisement {
     pub addresses: Vec<SocketAddr>, // preferred: direct P2P (LAN/public)
     pub relays: Vec<RelayHint>,     // fallback relays
     pub advertised_at: SystemTime,
+    /// Wire protocol the advertising peer speaks. Peers predating protocol versioning omit
+    /// it and read as version 0.
+    #[serde(default)]
+    pub protocol_version: u32,
 }
 
+/// Wire protocol version this build speaks and advertises.
+pub const CURRENT_PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RelayHint {
     pub relay_id: Ulid,
@@ -48,8 +83,33 @@ pub struct RelayHint {
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ConnectionPath {
-    PeerToPeer(SocketAddr),
-    Relay { relay: RelayHint, via: SocketAddr },
+    PeerToPeer {
+        addr: SocketAddr,
+        /// Measured round-trip time, if known; used by `rank_paths`.
+        latency_hint: Option<Duration>,
+    },
+    Relay {
+        relay: RelayHint,
+        via: SocketAddr,
+        latency_hint: Option<Duration>,
+    },
+}
+
+impl ConnectionPath {
+    pub fn latency_hint(&self) -> Option<Duration> {
+        match self {
+            ConnectionPath::PeerToPeer { latency_hint, .. }
+            | ConnectionPath::Relay { latency_hint, .. } => *latency_hint,
+        }
+    }
+}
+
+/// Order paths by `latency_hint`, fastest first; paths without a hint go last. Ties keep
+/// their input order.
+pub fn rank_paths(paths: &[ConnectionPath]) -> Vec<&ConnectionPath> {
+    let mut ranked: Vec<&ConnectionPath> = paths.iter().collect();
+    ranked.sort_by_key(|path| (path.latency_hint().is_none(), path.latency_hint()));
+    ranked
 }
 
 /// Result of attempting to resolve the best path to a peer.
@@ -66,6 +126,17 @@ pub struct DiscoveryConfig {
     pub prefer_p2p: bool,
     pub relay_timeout: Duration,
     pub max_advert_age: Duration,
+    /// Upper bound on peers tracked by a `PeerSet`.
+    pub max_peers: usize,
+    /// Oldest peer protocol version `choose_path` will connect to.
+    pub min_protocol_version: u32,
+}
+
+/// Known peer advertisements, one per device, bounded by `DiscoveryConfig::max_peers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerSet {
+    adverts: Vec<PeerAdvertisement>,
+    config: DiscoveryConfig,
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -74,6 +145,26 @@ pub enum IdentityError {
     AuthExpired,
     #[error("no viable path to peer")]
     NoPath,
+    #[error("peer advertisement is older than the configured maximum age")]
+    AdvertExpired,
+    #[error("peer speaks protocol {peer_version}, need at least {required}")]
+    IncompatibleProtocol { peer_version: u32, required: u32 },
+}
+
+/// Sessions that have been revoked (logout, device removal); anything tied to them is void.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationList {
+    sessions: HashSet<SessionId>,
+}
+
+impl RevocationList {
+    pub fn revoke(&mut self, session_id: SessionId) {
+        self.sessions.insert(session_id);
+    }
+
+    pub fn is_revoked(&self, session_id: SessionId) -> bool {
+        self.sessions.contains(&session_id)
+    }
 }
 
 impl UserAuthToken {
@@ -85,50 +176,114 @@ impl UserAuthToken {
     }
 }
 
+impl PeerSet {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            adverts: Vec::new(),
+            config,
+        }
+    }
+
+    /// Insert or refresh a peer's advertisement.
+    ///
+    /// When the set is full, a newer advertisement displaces the stalest one; returns false
+    /// if the new advertisement is no fresher than every tracked peer.
+    pub fn add_or_update(&mut self, advert: PeerAdvertisement) -> bool {
+        if let Some(existing) = self
+            .adverts
+            .iter_mut()
+            .find(|a| a.device_id == advert.device_id)
+        {
+            *existing = advert;
+            return true;
+        }
+        if self.adverts.len() < self.config.max_peers {
+            self.adverts.push(advert);
+            return true;
+        }
+        match self.adverts.iter_mut().min_by_key(|a| a.advertised_at) {
+            Some(stalest) if stalest.advertised_at < advert.advertised_at => {
+                *stalest = advert;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove(&mut self, device_id: DeviceId) {
+        self.adverts.retain(|a| a.device_id != device_id);
+    }
+
+    /// Drop advertisements older than `max_advert_age` as of `now`.
+    pub fn purge_stale(&mut self, now: SystemTime) {
+        let config = &self.config;
+        self.adverts.retain(|a| is_advert_fresh(a, config, now));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PeerAdvertisement> {
+        self.adverts.iter()
+    }
+}
+
+/// True if the advertisement is no older than `max_advert_age` at `now`. Advertisements
+/// stamped in the future (clock skew) count as fresh.
+pub fn is_advert_fresh(
+    advert: &PeerAdvertisement,
+    config: &DiscoveryConfig,
+    now: SystemTime,
+) -> bool {
+    now.duration_since(advert.advertised_at)
+        .map_or(true, |age| age <= config.max_advert_age)
+}
+
 /// Select a preferred connection path given a peer advertisement and a config.
-/// Preference: direct P2P addresses first; if none, fall back to relays.
+/// Preference: direct P2P addresses first; if none, fall back to relays. Every candidate is
+/// returned in `attempted`, ranked by `rank_paths`.
+/// Stale advertisements (older than `max_advert_age`) and peers older than
+/// `min_protocol_version` are rejected.
 pub fn choose_path(
     advert: &PeerAdvertisement,
     config: &DiscoveryConfig,
 ) -> Result<PathSelection, IdentityError> {
-    let mut attempted = Vec::new();
-
+    if !is_advert_fresh(advert, config, SystemTime::now()) {
+        return Err(IdentityError::AdvertExpired);
+    }
+    if advert.protocol_version < config.min_protocol_version {
+        return Err(IdentityError::IncompatibleProtocol {
+            peer_version: advert.protocol_version,
+            required: config.min_protocol_version,
+        });
+    }
+    let mut candidates = Vec::new();
     if config.prefer_p2p {
-        if let Some(addr) = advert.addresses.first() {
-            let path = ConnectionPath::PeerToPeer(*addr);
-            attempted.push(path.clone());
-            return Ok(PathSelection {
-                target: advert.device_id,
-                chosen: Some(path),
-                attempted,
-            });
-        }
+        candidates.extend(
+            advert
+                .addresses
+                .iter()
+                .map(|addr| ConnectionPath::PeerToPeer {
+                    addr: *addr,
+                    latency_hint: None,
+                }),
+        );
     }
+    let via = advert
+        .addresses
+        .first()
+        .copied()
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+    candidates.extend(advert.relays.iter().map(|relay| ConnectionPath::Relay {
+        relay: relay.clone(),
+        via,
+        latency_hint: None,
+    }));
 
-    if let Some(relay) = advert.relays.first() {
-        if let Some(addr) = advert.addresses.first() {
-            let path = ConnectionPath::Relay {
-                relay: relay.clone(),
-                via: *addr,
-            };
-            attempted.push(path.clone());
-            return Ok(PathSelection {
-                target: advert.device_id,
-                chosen: Some(path),
-                attempted,
-            });
-        } else {
-            let path = ConnectionPath::Relay {
-                relay: relay.clone(),
-                via: "0.0.0.0:0".parse().unwrap_or_else(|_| "127.0.0.1:0".parse().unwrap()),
-            };
-            attempted.push(path.clone());
-            return Ok(PathSelection {
-                target: advert.device_id,
-                chosen: Some(path),
-                attempted,
-            });
-        }
+    let attempted: Vec<ConnectionPath> = rank_paths(&candidates).into_iter().cloned().collect();
+    if let Some(chosen) = attempted.first().cloned() {
+        return Ok(PathSelection {
+            target: advert.device_id,
+            chosen: Some(chosen),
+            attempted,
+        });
     }
 
     Err(IdentityError::NoPath)
@@ -165,14 +320,21 @@ mod tests {
                 url: "wss://relay.example.com".into(),
             }],
             advertised_at: SystemTime::now(),
+            protocol_version: CURRENT_PROTOCOL_VERSION,
         };
         let cfg = DiscoveryConfig {
             prefer_p2p: true,
             relay_timeout: Duration::from_secs(5),
             max_advert_age: Duration::from_secs(60),
+            max_peers: 16,
+            min_protocol_version: CURRENT_PROTOCOL_VERSION,
         };
         let path = choose_path(&advert, &cfg).unwrap();
-        matches!(path.chosen, Some(ConnectionPath::PeerToPeer(_)));
+        assert!(matches!(
+            path.chosen,
+            Some(ConnectionPath::PeerToPeer { .. })
+        ));
+        assert_eq!(path.attempted.len(), 2);
     }
 
     #[test]
@@ -187,13 +349,177 @@ mod tests {
                 url: "wss://relay.example.com".into(),
             }],
             advertised_at: SystemTime::now(),
+            protocol_version: CURRENT_PROTOCOL_VERSION,
         };
         let cfg = DiscoveryConfig {
             prefer_p2p: true,
             relay_timeout: Duration::from_secs(5),
             max_advert_age: Duration::from_secs(60),
+            max_peers: 16,
+            min_protocol_version: CURRENT_PROTOCOL_VERSION,
         };
         let path = choose_path(&advert, &cfg).unwrap();
-        matches!(path.chosen, Some(ConnectionPath::Relay { .. }));
+        assert!(matches!(path.chosen, Some(ConnectionPath::Relay { .. })));
+    }
+
+    fn advert_at(advertised_at: SystemTime) -> PeerAdvertisement {
+        PeerAdvertisement {
+            device_id: Ulid::new(),
+            user_id: Ulid::new(),
+            session_id: Ulid::new(),
+            addresses: vec![],
+            relays: vec![],
+            advertised_at,
+            protocol_version: CURRENT_PROTOCOL_VERSION,
+        }
+    }
+
+    #[test]
+    fn peer_set_caps_and_purges() {
+        let now = SystemTime::now();
+        let mut peers = PeerSet::new(DiscoveryConfig {
+            prefer_p2p: true,
+            relay_timeout: Duration::from_secs(5),
+            max_advert_age: Duration::from_secs(60),
+            max_peers: 2,
+            min_protocol_version: CURRENT_PROTOCOL_VERSION,
+        });
+        let old = advert_at(now - Duration::from_secs(120));
+        let recent = advert_at(now - Duration::from_secs(10));
+        assert!(peers.add_or_update(old.clone()));
+        assert!(peers.add_or_update(recent.clone()));
+        assert!(!peers.add_or_update(advert_at(now - Duration::from_secs(300))));
+
+        let fresh = advert_at(now);
+        assert!(peers.add_or_update(fresh.clone()));
+        let ids: Vec<DeviceId> = peers.iter().map(|a| a.device_id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(!ids.contains(&old.device_id));
+
+        // Refreshing a tracked peer never counts against the cap.
+        assert!(peers.add_or_update(PeerAdvertisement {
+            advertised_at: now - Duration::from_secs(90),
+            ..recent.clone()
+        }));
+        peers.purge_stale(now);
+        let ids: Vec<DeviceId> = peers.iter().map(|a| a.device_id).collect();
+        assert_eq!(ids, vec![fresh.device_id]);
+
+        peers.remove(fresh.device_id);
+        assert_eq!(peers.iter().count(), 0);
+    }
+
+    #[test]
+    fn adverts_without_protocol_version_are_too_old() {
+        let mut json = serde_json::to_value(advert_at(SystemTime::now())).unwrap();
+        json.as_object_mut().unwrap().remove("protocol_version");
+        let advert: PeerAdvertisement = serde_json::from_value(json).unwrap();
+        assert_eq!(advert.protocol_version, 0);
+
+        let cfg = DiscoveryConfig {
+            prefer_p2p: true,
+            relay_timeout: Duration::from_secs(5),
+            max_advert_age: Duration::from_secs(60),
+            max_peers: 16,
+            min_protocol_version: CURRENT_PROTOCOL_VERSION,
+        };
+        assert_eq!(
+            choose_path(&advert, &cfg),
+            Err(IdentityError::IncompatibleProtocol {
+                peer_version: 0,
+                required: CURRENT_PROTOCOL_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn stale_adverts_are_rejected() {
+        let cfg = DiscoveryConfig {
+            prefer_p2p: true,
+            relay_timeout: Duration::from_secs(5),
+            max_advert_age: Duration::from_secs(60),
+            max_peers: 16,
+            min_protocol_version: CURRENT_PROTOCOL_VERSION,
+        };
+        let now = SystemTime::now();
+        let mut advert = advert_at(now - Duration::from_secs(30));
+        advert.addresses = vec!["10.0.0.2:7777".parse().unwrap()];
+        assert!(is_advert_fresh(&advert, &cfg, now));
+        assert!(choose_path(&advert, &cfg).is_ok());
+
+        advert.advertised_at = now - Duration::from_secs(120);
+        assert!(!is_advert_fresh(&advert, &cfg, now));
+        assert_eq!(
+            choose_path(&advert, &cfg),
+            Err(IdentityError::AdvertExpired)
+        );
+    }
+
+    #[test]
+    fn identities_dedupe_by_device_id() {
+        let device_id = Ulid::new();
+        let original = DeviceIdentity {
+            device_id,
+            user_id: Ulid::new(),
+            device_public_key: vec![1; 32],
+            attested_at: SystemTime::now(),
+            trusted_key_ids: Vec::new(),
+        };
+        let rotated = DeviceIdentity {
+            device_public_key: vec![2; 32],
+            attested_at: original.attested_at + Duration::from_secs(60),
+            ..original.clone()
+        };
+        assert_eq!(original, rotated);
+
+        let set: std::collections::HashSet<DeviceIdentity> =
+            [original, rotated].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn protocol_version_gates_paths() {
+        let cfg = DiscoveryConfig {
+            prefer_p2p: true,
+            relay_timeout: Duration::from_secs(5),
+            max_advert_age: Duration::from_secs(60),
+            max_peers: 16,
+            min_protocol_version: 2,
+        };
+        let mut advert = advert_at(SystemTime::now());
+        advert.addresses = vec!["10.0.0.2:7777".parse().unwrap()];
+        assert_eq!(
+            choose_path(&advert, &cfg),
+            Err(IdentityError::IncompatibleProtocol {
+                peer_version: CURRENT_PROTOCOL_VERSION,
+                required: 2,
+            })
+        );
+        for version in [2, 3] {
+            advert.protocol_version = version;
+            assert!(choose_path(&advert, &cfg).is_ok());
+        }
+    }
+
+    #[test]
+    fn ranks_paths_by_latency_hint() {
+        let relay = ConnectionPath::Relay {
+            relay: RelayHint {
+                relay_id: Ulid::new(),
+                url: "wss://relay.example.com".into(),
+            },
+            via: "127.0.0.1:4000".parse().unwrap(),
+            latency_hint: Some(Duration::from_millis(10)),
+        };
+        let p2p = ConnectionPath::PeerToPeer {
+            addr: "10.0.0.2:4000".parse().unwrap(),
+            latency_hint: Some(Duration::from_millis(100)),
+        };
+        let unmeasured = ConnectionPath::PeerToPeer {
+            addr: "10.0.0.3:4000".parse().unwrap(),
+            latency_hint: None,
+        };
+        let paths = [unmeasured.clone(), p2p.clone(), relay.clone()];
+        assert_eq!(rank_paths(&paths), vec![&relay, &p2p, &unmeasured]);
     }
 }
@@ -11,6 +11,8 @@ pub mod identity;
 pub mod file_transfer;
 pub mod lock;
 pub mod versioning;
+pub mod state_machine;
+pub mod monitored_store;
 
 pub use model::*;
 pub use local_store::*;
@@ -18,4 +20,6 @@ pub use file_monitor::*;
 pub use identity::*;
 pub use file_transfer::*;
 pub use lock::*;
-pub use versioning::*;
\ No newline at end of file
+pub use versioning::*;
+pub use state_machine::*;
+pub use monitored_store::*;
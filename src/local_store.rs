@@ -1,11 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
-    assert_file_invariants, AutoLockPreference, Consent, DeviceFileState, FileId, FileRecord,
-    Hydration, LocalRegistryEntry, ModelError, PathBinding, VersionId,
+    apply_retention, assert_file_invariants, assert_registry_invariants, force_release_lock,
+    is_sync_allowed, pinned_versions, release_lock, resolve_conflict, restore_tombstone,
+    valid_transition, validate_metadata_key, validate_path, verify_merkle_root, AutoLockPreference,
+    ConflictRecord, ConflictResolutionStrategy, Consent, DeviceFileState, DeviceFileStateKind,
+    DeviceId, DeviceIdentity, DeviceStatePolicy, FileId, FileRecord, Hydration, LocalRegistryEntry,
+    LockError, ModelError, PathBinding, PinPreference, TombstoneRecord, TransferDirection,
+    TransferPlan, TransferSession, TransferSessionId, TransferStatus, VersionId, VersionRecord,
+    VersionRetention, VersioningError, DEFAULT_SYNC_PRIORITY, MAX_LOCK_HISTORY,
 };
 
 /// In-memory local metadata store. This tracks file identities, shared metadata snapshots,
@@ -13,20 +22,302 @@ use crate::{
 ///
 /// Persistence is intentionally abstracted; callers can serialize/deserialize the store or
 /// rehydrate from a DB of their choice (e.g., SQLite) using the public accessors.
-#[derive(Default, Debug)]
+///
+/// Every successful mutation is also appended to a numbered event log so historical views
+/// can be rebuilt with `snapshot_at`. Once the log holds more than `max_log_events` events, the
+/// oldest are folded into a base state and snapshots from before them are no longer available.
+///
+/// The path, key and display-name indexes are not serialized; deserializing a store rebuilds
+/// them from the records.
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(from = "StoredMetadata")]
 pub struct LocalMetadataStore {
     files: HashMap<FileId, FileRecord>,
     registry: HashMap<FileId, LocalRegistryEntry>,
+    log: Vec<LoggedEvent>,
+    /// Files and registry entries before the oldest event in `log`.
+    log_base: Option<LogState>,
+    /// `seq` of the next logged event.
+    next_seq: u64,
+    /// Most events kept in `log`; `None` keeps `DEFAULT_MAX_LOG_EVENTS`.
+    max_log_events: Option<usize>,
+    /// Per file, the version ids the log already holds, so file events carry only new versions.
+    #[serde(skip)]
+    logged_versions: HashMap<FileId, HashSet<VersionId>>,
+    /// Lowercased bound path -> owning file, for O(1) conflict checks and lookups.
+    #[serde(skip)]
+    path_index: HashMap<String, FileId>,
+    /// Unresolved version conflicts, in the order they were applied.
+    #[serde(default)]
+    conflicts: Vec<ConflictRecord>,
+    /// Transfer sessions registered by the transfer engine, including finished ones.
+    #[serde(default)]
+    sessions: HashMap<TransferSessionId, TransferSession>,
+    /// Encryption key id -> files encrypted with it, for key-rotation lookups.
+    #[serde(skip)]
+    key_index: HashMap<String, Vec<FileId>>,
+    /// Most released locks kept per file; `None` keeps `MAX_LOCK_HISTORY`.
+    #[serde(default)]
+    lock_history_cap: Option<usize>,
+    /// Lowercased display name -> files using it, ordered for prefix search. New names are
+    /// checked for uniqueness, but every named file is indexed even if older data repeats one.
+    #[serde(skip)]
+    display_name_index: BTreeMap<String, Vec<FileId>>,
+}
+
+/// Serialized form of `LocalMetadataStore`: everything but the indexes.
+#[derive(Deserialize)]
+struct StoredMetadata {
+    files: HashMap<FileId, FileRecord>,
+    registry: HashMap<FileId, LocalRegistryEntry>,
+    log: Vec<LoggedEvent>,
+    #[serde(default)]
+    log_base: Option<LogState>,
+    #[serde(default)]
+    next_seq: u64,
+    #[serde(default)]
+    max_log_events: Option<usize>,
+    #[serde(default)]
+    conflicts: Vec<ConflictRecord>,
+    #[serde(default)]
+    sessions: HashMap<TransferSessionId, TransferSession>,
+    #[serde(default)]
+    lock_history_cap: Option<usize>,
+}
+
+impl From<StoredMetadata> for LocalMetadataStore {
+    fn from(stored: StoredMetadata) -> Self {
+        let next_seq = stored.log.last().map_or(0, |e| e.seq + 1);
+        let mut store = LocalMetadataStore {
+            files: stored.files,
+            registry: stored.registry,
+            log: stored.log,
+            log_base: stored.log_base,
+            next_seq: stored.next_seq.max(next_seq),
+            max_log_events: stored.max_log_events,
+            conflicts: stored.conflicts,
+            sessions: stored.sessions,
+            lock_history_cap: stored.lock_history_cap,
+            ..LocalMetadataStore::default()
+        };
+        store.rebuild_indexes();
+        store
+    }
+}
+
+/// Default for `LocalMetadataStore::max_log_events`.
+pub const DEFAULT_MAX_LOG_EVENTS: usize = 10_000;
+
+/// One entry in the store's event log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    /// Position in the store's history; strictly increasing.
+    pub seq: u64,
+    /// When the event was logged; never earlier than the previous event, even if the clock
+    /// steps back.
+    pub at: DateTime<Utc>,
+    pub event: StoreEvent,
+}
+
+/// Store mutation, as replayed by `snapshot_at`. Registry entries are logged as the full
+/// post-mutation value; path changes are logged individually.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StoreEvent {
+    /// A file record was inserted or changed. `record.versions` holds only the versions the log
+    /// did not already have for this file; `version_ids` lists every version in order.
+    FileRecordUpserted {
+        record: Box<FileRecord>,
+        version_ids: Vec<VersionId>,
+    },
+    RegistryEntryUpserted(LocalRegistryEntry),
+    PathBound {
+        file_id: FileId,
+        path: String,
+        writable: bool,
+        #[serde(default)]
+        mount_point: Option<String>,
+    },
+    PathUnbound {
+        file_id: FileId,
+        path: String,
+    },
+    /// File record, its registry entry and its conflicts were dropped from the store.
+    FileRemoved {
+        file_id: FileId,
+    },
+    /// A conflict was tracked, replacing any record for the same pair of versions.
+    ConflictApplied(ConflictRecord),
+    /// Every conflict tracked for the file was resolved.
+    ConflictsCleared {
+        file_id: FileId,
+    },
+}
+
+/// Files and registry entries rebuilt from the event log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct LogState {
+    /// Time of the last event folded into this state; earlier states cannot be rebuilt.
+    folded_through: DateTime<Utc>,
+    files: HashMap<FileId, FileRecord>,
+    registry: HashMap<FileId, LocalRegistryEntry>,
+    #[serde(default)]
+    conflicts: Vec<ConflictRecord>,
+}
+
+impl LogState {
+    fn apply(&mut self, logged: &LoggedEvent) {
+        match &logged.event {
+            StoreEvent::FileRecordUpserted {
+                record,
+                version_ids,
+            } => {
+                let mut versions: HashMap<VersionId, VersionRecord> = self
+                    .files
+                    .remove(&record.file_id)
+                    .map(|f| f.versions.into_iter().map(|v| (v.version_id, v)).collect())
+                    .unwrap_or_default();
+                versions.extend(record.versions.iter().map(|v| (v.version_id, v.clone())));
+                let mut record = record.as_ref().clone();
+                record.versions = version_ids
+                    .iter()
+                    .filter_map(|id| versions.remove(id))
+                    .collect();
+                self.files.insert(record.file_id, record);
+            }
+            StoreEvent::RegistryEntryUpserted(entry) => {
+                self.registry.insert(entry.file_id, entry.clone());
+            }
+            StoreEvent::PathBound {
+                file_id,
+                path,
+                writable,
+                mount_point,
+            } => {
+                // Validated when first applied; a miss here means the entry was never logged.
+                let Some(entry) = self.registry.get_mut(file_id) else {
+                    return;
+                };
+                if let Some(existing) = entry.paths.iter_mut().find(|p| p.path == *path) {
+                    existing.last_seen_at = logged.at;
+                    existing.writable = *writable;
+                    existing.mount_point = mount_point.clone();
+                } else {
+                    entry.paths.push(PathBinding {
+                        path: path.clone(),
+                        last_seen_at: logged.at,
+                        writable: *writable,
+                        mount_point: mount_point.clone(),
+                    });
+                }
+            }
+            StoreEvent::PathUnbound { file_id, path } => {
+                if let Some(entry) = self.registry.get_mut(file_id) {
+                    entry.paths.retain(|p| p.path != *path);
+                }
+            }
+            StoreEvent::FileRemoved { file_id } => {
+                self.files.remove(file_id);
+                self.registry.remove(file_id);
+                self.conflicts.retain(|c| c.file_id != *file_id);
+            }
+            StoreEvent::ConflictApplied(record) => {
+                insert_conflict(&mut self.conflicts, record.clone());
+            }
+            StoreEvent::ConflictsCleared { file_id } => {
+                self.conflicts.retain(|c| c.file_id != *file_id);
+            }
+        }
+    }
+}
+
+/// Append `record`, replacing any conflict for the same file and pair of versions.
+fn insert_conflict(conflicts: &mut Vec<ConflictRecord>, record: ConflictRecord) {
+    conflicts.retain(|c| {
+        !(c.file_id == record.file_id
+            && c.local_version_id == record.local_version_id
+            && c.remote_version_id == record.remote_version_id)
+    });
+    conflicts.push(record);
+}
+
+/// Registry entry for a file learned from a peer: unbound, not present and awaiting consent.
+fn pending_registry_entry(file_id: FileId, submitted_at: DateTime<Utc>) -> LocalRegistryEntry {
+    LocalRegistryEntry {
+        file_id,
+        paths: Vec::new(),
+        local_version_id: None,
+        hydration: Hydration::None,
+        consent: Consent::PendingReview {
+            submitted_at,
+            reviewer_id: None,
+        },
+        pin: PinPreference::None,
+        auto_lock_preference: AutoLockPreference::Manual,
+        last_error: None,
+        sync_group: None,
+        priority: DEFAULT_SYNC_PRIORITY,
+        starred: false,
+        local_size_bytes: None,
+        sync_paused: false,
+    }
+}
+
+/// Slice of a store relevant to one device, exchanged between peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceStoreExport {
+    pub device_id: DeviceId,
+    /// Records the device has state for, with every other device's state removed.
+    pub files: Vec<FileRecord>,
+    /// Registry entries for those records. Paths, consent and hydration are local to the
+    /// exporting device, so `import_from_device` does not copy them.
+    pub registry: Vec<LocalRegistryEntry>,
 }
 
+/// Longest display name accepted by `set_display_name`, in characters.
+pub const MAX_DISPLAY_NAME_LEN: usize = 255;
+
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum LocalMetadataError {
     #[error("file {0} not found")]
     NotFound(FileId),
     #[error("path already bound to file {0}")]
     PathAlreadyBound(FileId),
+    #[error("path {0} is not bound to this file")]
+    PathNotBound(String),
+    #[error("version {version_id} not found in file {file_id}")]
+    VersionNotFound {
+        file_id: FileId,
+        version_id: VersionId,
+    },
+    #[error("invalid device state transition {from:?} -> {to:?}")]
+    InvalidStateTransition {
+        from: DeviceFileStateKind,
+        to: DeviceFileStateKind,
+    },
+    #[error("display name {name:?} is already used by file {file_id}")]
+    DuplicateDisplayName { name: String, file_id: FileId },
+    #[error("invalid display name {0:?}: must be 1-{MAX_DISPLAY_NAME_LEN} characters")]
+    InvalidDisplayName(String),
+    #[error("transfer session {0} not found")]
+    SessionNotFound(TransferSessionId),
+    #[error("device {device_id} has no state for file {file_id}")]
+    DeviceStateNotFound {
+        file_id: FileId,
+        device_id: DeviceId,
+    },
+    #[error("export was made for device {actual}, not source device {expected}")]
+    ExportDeviceMismatch {
+        expected: DeviceId,
+        actual: DeviceId,
+    },
     #[error(transparent)]
     Model(#[from] ModelError),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error(transparent)]
+    Versioning(#[from] VersioningError),
+    #[error("{} operations failed", .0.len())]
+    BulkErrors(Vec<LocalMetadataError>),
 }
 
 impl LocalMetadataStore {
@@ -35,57 +326,247 @@ impl LocalMetadataStore {
     }
 
     /// Insert or replace a `FileRecord` after validating invariants.
+    /// Fails with `DuplicateDisplayName` if another file already uses the display name,
+    /// compared case-insensitively.
     pub fn upsert_file_record(&mut self, record: FileRecord) -> Result<(), LocalMetadataError> {
         assert_file_invariants(&record)?;
-        self.files.insert(record.file_id, record);
+        self.verify_new_merkle_roots(&record)?;
+        self.check_display_name(&record)?;
+        let file_id = record.file_id;
+        self.insert_file(record);
+        self.log_file_record(file_id);
         Ok(())
     }
 
+    /// Files encrypted with `key_id`, e.g. to re-encrypt them after the key is compromised.
+    pub fn files_for_key_id<'a>(&'a self, key_id: &str) -> impl Iterator<Item = &'a FileRecord> {
+        self.key_index
+            .get(key_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|file_id| self.files.get(file_id))
+    }
+
+    fn rebuild_indexes(&mut self) {
+        self.rebuild_path_index();
+        self.rebuild_key_index();
+        self.rebuild_display_name_index();
+        // Every stored version reached the log when it was stored.
+        self.logged_versions = self
+            .files
+            .values()
+            .map(|f| (f.file_id, f.versions.iter().map(|v| v.version_id).collect()))
+            .collect();
+    }
+
+    fn rebuild_key_index(&mut self) {
+        self.key_index.clear();
+        let mut records: Vec<&FileRecord> = self.files.values().collect();
+        records.sort_by_key(|record| record.file_id);
+        for record in records {
+            self.key_index
+                .entry(record.encryption.key_id.clone())
+                .or_default()
+                .push(record.file_id);
+        }
+    }
+
+    fn rebuild_display_name_index(&mut self) {
+        self.display_name_index.clear();
+        let mut records: Vec<&FileRecord> = self.files.values().collect();
+        records.sort_by_key(|record| record.file_id);
+        for record in records {
+            if let Some(name) = &record.display_name {
+                self.display_name_index
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(record.file_id);
+            }
+        }
+    }
+
+    /// Fail with `DuplicateDisplayName` if another file already uses `record`'s display name,
+    /// compared case-insensitively.
+    fn check_display_name(&self, record: &FileRecord) -> Result<(), LocalMetadataError> {
+        let Some(name) = &record.display_name else {
+            return Ok(());
+        };
+        let holder = self
+            .display_name_index
+            .get(&name.to_lowercase())
+            .into_iter()
+            .flatten()
+            .find(|id| **id != record.file_id);
+        match holder {
+            Some(holder) => Err(LocalMetadataError::DuplicateDisplayName {
+                name: name.clone(),
+                file_id: *holder,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the Merkle roots of versions in `record` that are new or differ from the stored
+    /// copy; stored versions were checked when they arrived.
+    fn verify_new_merkle_roots(&self, record: &FileRecord) -> Result<(), ModelError> {
+        let stored: HashMap<VersionId, &VersionRecord> = self
+            .files
+            .get(&record.file_id)
+            .map(|f| f.versions.iter().map(|v| (v.version_id, v)).collect())
+            .unwrap_or_default();
+        record
+            .versions
+            .iter()
+            .filter(|v| stored.get(&v.version_id) != Some(v))
+            .try_for_each(verify_merkle_root)
+    }
+
+    fn insert_file(&mut self, record: FileRecord) {
+        let file_id = record.file_id;
+        let key_id = record.encryption.key_id.clone();
+        // A version replaced under the same id must be logged again.
+        if let (Some(stored), Some(logged)) = (
+            self.files.get(&file_id),
+            self.logged_versions.get_mut(&file_id),
+        ) {
+            for version in &record.versions {
+                if stored
+                    .versions
+                    .iter()
+                    .any(|v| v.version_id == version.version_id && v != version)
+                {
+                    logged.remove(&version.version_id);
+                }
+            }
+        }
+        self.remove_file(&file_id);
+        if let Some(name) = &record.display_name {
+            self.display_name_index
+                .entry(name.to_lowercase())
+                .or_default()
+                .push(file_id);
+        }
+        self.files.insert(file_id, record);
+        self.key_index.entry(key_id).or_default().push(file_id);
+    }
+
+    fn remove_file(&mut self, file_id: &FileId) {
+        let Some(record) = self.files.remove(file_id) else {
+            return;
+        };
+        if let Some(name) = &record.display_name {
+            let key = name.to_lowercase();
+            if let Some(ids) = self.display_name_index.get_mut(&key) {
+                ids.retain(|id| id != file_id);
+                if ids.is_empty() {
+                    self.display_name_index.remove(&key);
+                }
+            }
+        }
+        if let Some(ids) = self.key_index.get_mut(&record.encryption.key_id) {
+            ids.retain(|id| id != file_id);
+            if ids.is_empty() {
+                self.key_index.remove(&record.encryption.key_id);
+            }
+        }
+    }
+
+    /// Insert or replace many records in order, stopping at the first invalid one. On error,
+    /// returns it with the number of records inserted before it; those stay inserted.
+    pub fn upsert_file_records(
+        &mut self,
+        records: Vec<FileRecord>,
+    ) -> Result<usize, (LocalMetadataError, usize)> {
+        let total = records.len();
+        for (inserted, record) in records.into_iter().enumerate() {
+            self.upsert_file_record(record)
+                .map_err(|err| (err, inserted))?;
+        }
+        Ok(total)
+    }
+
+    /// Insert or replace many records, skipping invalid ones. Returns the number inserted and
+    /// the error for each skipped record.
+    pub fn upsert_file_records_lenient(
+        &mut self,
+        records: Vec<FileRecord>,
+    ) -> (usize, Vec<(FileId, LocalMetadataError)>) {
+        let mut inserted = 0;
+        let mut skipped = Vec::new();
+        for record in records {
+            let file_id = record.file_id;
+            match self.upsert_file_record(record) {
+                Ok(()) => inserted += 1,
+                Err(err) => skipped.push((file_id, err)),
+            }
+        }
+        (inserted, skipped)
+    }
+
     /// Insert or replace the local registry entry for a file.
     pub fn upsert_registry_entry(
         &mut self,
         entry: LocalRegistryEntry,
     ) -> Result<(), LocalMetadataError> {
-        self.registry.insert(entry.file_id, entry);
+        assert_registry_invariants(&entry)?;
+        self.record_event(StoreEvent::RegistryEntryUpserted(entry.clone()));
+        self.insert_registry(entry);
         Ok(())
     }
 
-    /// Bind or update a path for a file without changing identity.
+    /// Bind or update a path for a file without changing identity. `mount_point` names the
+    /// filesystem mount the path lives on, if known (see `all_paths_on_mount`).
     pub fn bind_path(
         &mut self,
         file_id: FileId,
         path: String,
         writable: bool,
+        mount_point: Option<String>,
     ) -> Result<(), LocalMetadataError> {
+        validate_path(&path)?;
         // Prevent binding the same path to multiple FileIds.
-        if let Some(conflict_id) = self.registry.iter().find_map(|(other_id, other_entry)| {
-            if *other_id != file_id
-                && other_entry
-                    .paths
-                    .iter()
-                    .any(|p| p.path.eq_ignore_ascii_case(&path))
-            {
-                Some(*other_id)
-            } else {
-                None
-            }
-        }) {
+        if let Some(conflict_id) = self.file_id_for_path(&path).filter(|id| *id != file_id) {
             return Err(LocalMetadataError::PathAlreadyBound(conflict_id));
         }
 
+        let now = self.next_log_time();
+        self.apply_bind(file_id, path.clone(), writable, mount_point.clone(), now)?;
+        self.push_event(
+            now,
+            StoreEvent::PathBound {
+                file_id,
+                path,
+                writable,
+                mount_point,
+            },
+        );
+        Ok(())
+    }
+
+    fn apply_bind(
+        &mut self,
+        file_id: FileId,
+        path: String,
+        writable: bool,
+        mount_point: Option<String>,
+        seen_at: DateTime<Utc>,
+    ) -> Result<(), LocalMetadataError> {
         let entry = self
             .registry
             .get_mut(&file_id)
             .ok_or(LocalMetadataError::NotFound(file_id))?;
 
         if let Some(existing) = entry.paths.iter_mut().find(|p| p.path == path) {
-            existing.last_seen_at = Utc::now();
+            existing.last_seen_at = seen_at;
             existing.writable = writable;
+            existing.mount_point = mount_point;
         } else {
+            self.path_index.insert(path.to_lowercase(), file_id);
             entry.paths.push(PathBinding {
                 path,
-                last_seen_at: Utc::now(),
+                last_seen_at: seen_at,
                 writable,
+                mount_point,
             });
         }
         Ok(())
@@ -98,10 +579,149 @@ impl LocalMetadataStore {
             .get_mut(&file_id)
             .ok_or(LocalMetadataError::NotFound(file_id))?;
         entry.paths.retain(|p| p.path != path);
+        self.unindex_path(file_id, path);
+        self.record_event(StoreEvent::PathUnbound {
+            file_id,
+            path: path.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Move a binding from `from` to `to`, keeping its writability and mount point. If the file
+    /// already has another binding for `to` (compared case-insensitively), the `from` binding is
+    /// dropped instead.
+    pub fn rename_path(
+        &mut self,
+        file_id: FileId,
+        from: &str,
+        to: String,
+    ) -> Result<(), LocalMetadataError> {
+        validate_path(&to)?;
+        if let Some(conflict_id) = self.file_id_for_path(&to).filter(|id| *id != file_id) {
+            return Err(LocalMetadataError::PathAlreadyBound(conflict_id));
+        }
+        let entry = self
+            .registry
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        let index = entry
+            .paths
+            .iter()
+            .position(|p| p.path == from)
+            .ok_or_else(|| LocalMetadataError::PathNotBound(from.to_string()))?;
+        let key = to.to_lowercase();
+        let already_bound = entry
+            .paths
+            .iter()
+            .enumerate()
+            .any(|(i, p)| i != index && p.path.to_lowercase() == key);
+        if already_bound {
+            entry.paths.remove(index);
+        } else {
+            let binding = &mut entry.paths[index];
+            binding.path = to;
+            binding.last_seen_at = Utc::now();
+        }
+        self.unindex_path(file_id, from);
+        self.path_index.insert(key, file_id);
+        self.log_registry_entry(file_id);
         Ok(())
     }
 
-    /// Update local hydration/consent/auto-lock knobs.
+    /// File bound to `path`, compared case-insensitively.
+    pub fn file_id_for_path(&self, path: &str) -> Option<FileId> {
+        self.path_index.get(&path.to_lowercase()).copied()
+    }
+
+    fn rebuild_path_index(&mut self) {
+        self.path_index.clear();
+        let mut entries: Vec<&LocalRegistryEntry> = self.registry.values().collect();
+        entries.sort_by_key(|entry| entry.file_id);
+        for entry in entries {
+            for binding in &entry.paths {
+                self.path_index
+                    .entry(binding.path.to_lowercase())
+                    .or_insert(entry.file_id);
+            }
+        }
+    }
+
+    /// Cross-check the path index against the registry, describing each discrepancy.
+    pub fn verify_path_index(&self) -> Result<(), Vec<String>> {
+        let holds = |file_id: &FileId, key: &str| {
+            self.registry
+                .get(file_id)
+                .is_some_and(|entry| entry.paths.iter().any(|p| p.path.to_lowercase() == key))
+        };
+        let mut problems = Vec::new();
+        for (key, file_id) in &self.path_index {
+            if !holds(file_id, key) {
+                problems.push(format!(
+                    "index maps {key} to {file_id}, which does not bind it"
+                ));
+            }
+        }
+        for entry in self.registry.values() {
+            for binding in &entry.paths {
+                if !self.path_index.contains_key(&binding.path.to_lowercase()) {
+                    problems.push(format!(
+                        "{} bound to {} is missing from the index",
+                        binding.path, entry.file_id
+                    ));
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            problems.sort();
+            Err(problems)
+        }
+    }
+
+    /// Re-point the index entry for `path` after `file_id` may have released it. Registry
+    /// upserts are not conflict-checked, so another file can still hold the same path.
+    fn unindex_path(&mut self, file_id: FileId, path: &str) {
+        let key = path.to_lowercase();
+        if self.path_index.get(&key) != Some(&file_id) {
+            return;
+        }
+        let holder = self
+            .registry
+            .get(&file_id)
+            .into_iter()
+            .chain(self.registry.values())
+            .find(|entry| entry.paths.iter().any(|p| p.path.to_lowercase() == key))
+            .map(|entry| entry.file_id);
+        match holder {
+            Some(holder) => self.path_index.insert(key, holder),
+            None => self.path_index.remove(&key),
+        };
+    }
+
+    fn insert_registry(&mut self, entry: LocalRegistryEntry) {
+        let file_id = entry.file_id;
+        self.remove_registry(&file_id);
+        for binding in &entry.paths {
+            self.path_index
+                .entry(binding.path.to_lowercase())
+                .or_insert(file_id);
+        }
+        self.registry.insert(file_id, entry);
+    }
+
+    fn remove_registry(&mut self, file_id: &FileId) {
+        let Some(entry) = self.registry.remove(file_id) else {
+            return;
+        };
+        for binding in &entry.paths {
+            self.unindex_path(*file_id, &binding.path);
+        }
+    }
+
+    /// Update local hydration/consent/auto-lock knobs. Setting `Hydration::Partial` also sets
+    /// `local_size_bytes` to its `bytes_present`; leaving `Partial` clears it, since the partial
+    /// count no longer describes the file.
     pub fn set_local_preferences(
         &mut self,
         file_id: FileId,
@@ -114,6 +734,11 @@ impl LocalMetadataStore {
             .get_mut(&file_id)
             .ok_or(LocalMetadataError::NotFound(file_id))?;
         if let Some(h) = hydration {
+            if let Hydration::Partial { bytes_present, .. } = h {
+                entry.local_size_bytes = Some(bytes_present);
+            } else if matches!(entry.hydration, Hydration::Partial { .. }) {
+                entry.local_size_bytes = None;
+            }
             entry.hydration = h;
         }
         if let Some(c) = consent {
@@ -122,14 +747,60 @@ impl LocalMetadataStore {
         if let Some(a) = auto_lock {
             entry.auto_lock_preference = a;
         }
+        self.log_registry_entry(file_id);
+        Ok(())
+    }
+
+    /// Heartbeat for a device's state on one file; see `FileRecord::touch_device_state`.
+    pub fn touch_device_state_for_file(
+        &mut self,
+        file_id: FileId,
+        device_id: DeviceId,
+        at: DateTime<Utc>,
+    ) -> Result<(), LocalMetadataError> {
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        if !record.touch_device_state(device_id, at) {
+            return Err(LocalMetadataError::DeviceStateNotFound { file_id, device_id });
+        }
+        self.log_file_record(file_id);
         Ok(())
     }
 
+    /// Set the same hydration on many registry entries (e.g., `Hydration::None` when a device
+    /// goes offline). Entries that exist are updated even if others fail; every failure is
+    /// reported together in `BulkErrors`.
+    pub fn set_bulk_hydration(
+        &mut self,
+        file_ids: &[FileId],
+        hydration: Hydration,
+    ) -> Result<usize, LocalMetadataError> {
+        let mut updated = 0;
+        let mut errors = Vec::new();
+        for file_id in file_ids {
+            match self.set_local_preferences(*file_id, Some(hydration.clone()), None, None) {
+                Ok(()) => updated += 1,
+                Err(err) => errors.push(err),
+            }
+        }
+        if errors.is_empty() {
+            Ok(updated)
+        } else {
+            Err(LocalMetadataError::BulkErrors(errors))
+        }
+    }
+
     /// Add or update a device state in the shared record.
+    ///
+    /// Under `DeviceStatePolicy::Strict`, an update to an existing device state must be a
+    /// `valid_transition`; a device's first state is always accepted.
     pub fn upsert_device_state(
         &mut self,
         file_id: FileId,
         device_state: DeviceFileState,
+        policy: DeviceStatePolicy,
     ) -> Result<(), LocalMetadataError> {
         let record = self
             .files
@@ -141,11 +812,20 @@ impl LocalMetadataStore {
             .iter_mut()
             .find(|d| d.device_id == device_state.device_id)
         {
+            if policy == DeviceStatePolicy::Strict
+                && !valid_transition(&existing.state, &device_state.state)
+            {
+                return Err(LocalMetadataError::InvalidStateTransition {
+                    from: existing.state.clone(),
+                    to: device_state.state,
+                });
+            }
             *existing = device_state;
         } else {
             record.device_states.push(device_state);
         }
         assert_file_invariants(record)?;
+        self.log_file_record(file_id);
         Ok(())
     }
 
@@ -154,17 +834,20 @@ impl LocalMetadataStore {
         &mut self,
         file_id: FileId,
         version_id: VersionId,
-        version_record: crate::VersionRecord,
+        version_record: VersionRecord,
     ) -> Result<(), LocalMetadataError> {
         let record = self
             .files
             .get_mut(&file_id)
             .ok_or(LocalMetadataError::NotFound(file_id))?;
+        verify_merkle_root(&version_record)?;
         record.head_version_id = version_id;
         record.versions.push(version_record);
         assert_file_invariants(record)?;
+        self.log_file_record(file_id);
         if let Some(entry) = self.registry.get_mut(&file_id) {
             entry.local_version_id = Some(version_id);
+            self.log_registry_entry(file_id);
         }
         Ok(())
     }
@@ -181,98 +864,1204 @@ impl LocalMetadataStore {
             .ok_or(LocalMetadataError::NotFound(file_id))?;
         record.lock = lock;
         assert_file_invariants(record)?;
+        self.log_file_record(file_id);
         Ok(())
     }
 
-    /// Update local last error for visibility without affecting shared metadata.
-    pub fn set_local_error(
+    /// Star or unstar a registered file for quick access; `NotFound` if it is not registered.
+    pub fn set_starred(
         &mut self,
         file_id: FileId,
-        message: Option<String>,
+        starred: bool,
     ) -> Result<(), LocalMetadataError> {
         let entry = self
             .registry
             .get_mut(&file_id)
             .ok_or(LocalMetadataError::NotFound(file_id))?;
-        entry.last_error = message;
+        entry.starred = starred;
+        self.log_registry_entry(file_id);
         Ok(())
     }
 
-    /// Getters for persistence/export.
-    pub fn file_record(&self, file_id: &FileId) -> Option<&FileRecord> {
-        self.files.get(file_id)
+    /// Suspend or resume sync for one file without unregistering it.
+    pub fn set_sync_paused(
+        &mut self,
+        file_id: FileId,
+        paused: bool,
+    ) -> Result<(), LocalMetadataError> {
+        let entry = self
+            .registry
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        entry.sync_paused = paused;
+        self.log_registry_entry(file_id);
+        Ok(())
     }
 
-    pub fn registry_entry(&self, file_id: &FileId) -> Option<&LocalRegistryEntry> {
-        self.registry.get(file_id)
+    /// Files with sync paused, in `FileId` order.
+    pub fn paused_files(&self) -> Vec<FileId> {
+        let mut ids: Vec<FileId> = self
+            .registry
+            .values()
+            .filter(|entry| entry.sync_paused)
+            .map(|entry| entry.file_id)
+            .collect();
+        ids.sort();
+        ids
     }
 
-    pub fn files(&self) -> impl Iterator<Item = &FileRecord> {
-        self.files.values()
+    /// Remove path bindings that are lexically equivalent to an earlier binding of the same
+    /// file (e.g., `/foo/./bar` after `/foo/bar`). Purely lexical: no filesystem access, so
+    /// `..` and symlinks are left alone. Returns the number of bindings removed.
+    pub fn deduplicate_paths(&mut self) -> usize {
+        let mut removed = 0;
+        let mut changed = Vec::new();
+        for entry in self.registry.values_mut() {
+            let before = entry.paths.len();
+            let mut seen = HashSet::new();
+            entry
+                .paths
+                .retain(|p| seen.insert(Path::new(&p.path).components().collect::<PathBuf>()));
+            if entry.paths.len() < before {
+                removed += before - entry.paths.len();
+                changed.push(entry.file_id);
+            }
+        }
+        for file_id in changed {
+            if let Some(entry) = self.registry.get(&file_id).cloned() {
+                self.insert_registry(entry);
+            }
+            self.log_registry_entry(file_id);
+        }
+        removed
     }
 
-    pub fn registry_entries(&self) -> impl Iterator<Item = &LocalRegistryEntry> {
-        self.registry.values()
+    /// Keep only files for which `predicate` returns true, dropping the others together with
+    /// their registry entries and conflicts. Returns the number of files removed.
+    pub fn retain_files<F>(&mut self, predicate: F) -> usize
+    where
+        F: Fn(&FileRecord, Option<&LocalRegistryEntry>) -> bool,
+    {
+        let mut removed: Vec<FileId> = self
+            .files
+            .values()
+            .filter(|f| !predicate(f, self.registry.get(&f.file_id)))
+            .map(|f| f.file_id)
+            .collect();
+        removed.sort();
+        for file_id in &removed {
+            self.remove_file(file_id);
+            self.remove_registry(file_id);
+            self.logged_versions.remove(file_id);
+            self.conflicts.retain(|c| c.file_id != *file_id);
+            self.record_event(StoreEvent::FileRemoved { file_id: *file_id });
+        }
+        removed.len()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        ChunkRef, DeviceFileStateKind, EncryptionInfo, LockMode, LockRecord, VersionRecord,
-    };
-    use chrono::Duration;
+    /// Record how many bytes of the file are on local disk. Must match `bytes_present` while
+    /// the entry is partially hydrated.
+    pub fn set_local_size_bytes(
+        &mut self,
+        file_id: FileId,
+        bytes: Option<u64>,
+    ) -> Result<(), LocalMetadataError> {
+        let entry = self
+            .registry
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        let previous = std::mem::replace(&mut entry.local_size_bytes, bytes);
+        if let Err(err) = assert_registry_invariants(entry) {
+            entry.local_size_bytes = previous;
+            return Err(err.into());
+        }
+        self.log_registry_entry(file_id);
+        Ok(())
+    }
 
-    fn ulid() -> crate::FileId {
-        ulid::Ulid::new()
+    /// Sum of `local_size_bytes` over all registry entries; unknown sizes count as zero.
+    pub fn total_local_disk_usage(&self) -> u64 {
+        self.registry
+            .values()
+            .map(|entry| entry.local_size_bytes.unwrap_or(0))
+            .sum()
     }
 
-    fn sample_file_record() -> FileRecord {
-        let file_id = ulid();
-        let version_id = ulid();
-        FileRecord {
-            file_id,
-            origin_device_id: ulid(),
-            created_at: Utc::now(),
-            head_version_id: version_id,
-            versions: vec![VersionRecord {
-                version_id,
-                file_id,
-                parent_version_id: None,
-                origin_device_id: ulid(),
-                timestamp: Utc::now(),
-                content_hash: "hash".into(),
-                size_bytes: 10,
-                chunks: vec![ChunkRef {
-                    offset: 0,
-                    length: 10,
-                    hash: "hash".into(),
-                }],
-            }],
-            lock: None,
-            device_states: vec![DeviceFileState {
-                device_id: ulid(),
-                state: DeviceFileStateKind::Ready,
-                known_head_version_id: Some(version_id),
-                last_seen_at: Utc::now(),
-                last_error: None,
-            }],
-            encryption: EncryptionInfo {
-                key_id: "k1".into(),
-                algo: "AES-256-GCM".into(),
-                iv_salt: None,
-            },
+    /// Track a version conflict after checking that both versions belong to the file. Replaces
+    /// an existing record for the same pair of versions.
+    pub fn apply_conflict_record(
+        &mut self,
+        record: ConflictRecord,
+    ) -> Result<(), LocalMetadataError> {
+        let file = self
+            .files
+            .get(&record.file_id)
+            .ok_or(LocalMetadataError::NotFound(record.file_id))?;
+        for version_id in [record.local_version_id, record.remote_version_id] {
+            if !file.versions.iter().any(|v| v.version_id == version_id) {
+                return Err(LocalMetadataError::VersionNotFound {
+                    file_id: record.file_id,
+                    version_id,
+                });
+            }
         }
+        self.record_event(StoreEvent::ConflictApplied(record.clone()));
+        insert_conflict(&mut self.conflicts, record);
+        Ok(())
     }
 
-    fn sample_registry_entry(file_id: FileId) -> LocalRegistryEntry {
+    /// Drop every conflict tracked for the file, logging it if there were any.
+    fn clear_conflicts(&mut self, file_id: FileId) {
+        let before = self.conflicts.len();
+        self.conflicts.retain(|c| c.file_id != file_id);
+        if self.conflicts.len() != before {
+            self.record_event(StoreEvent::ConflictsCleared { file_id });
+        }
+    }
+
+    /// Unresolved conflicts that `device_id` is a side of.
+    pub fn pending_conflicts_for_device(&self, device_id: DeviceId) -> Vec<&ConflictRecord> {
+        self.conflicts
+            .iter()
+            .filter(|c| c.involves(device_id))
+            .collect()
+    }
+
+    /// Settle a file's conflict with `resolve_conflict` and drop its conflict records.
+    pub fn resolve_conflict(
+        &mut self,
+        file_id: FileId,
+        strategy: ConflictResolutionStrategy,
+        caller_device: DeviceId,
+    ) -> Result<(), LocalMetadataError> {
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        resolve_conflict(record, strategy, caller_device)?;
+        self.log_file_record(file_id);
+        self.clear_conflicts(file_id);
+        Ok(())
+    }
+
+    /// Update local last error for visibility without affecting shared metadata.
+    pub fn set_local_error(
+        &mut self,
+        file_id: FileId,
+        message: Option<String>,
+    ) -> Result<(), LocalMetadataError> {
+        let entry = self
+            .registry
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        entry.last_error = message;
+        self.log_registry_entry(file_id);
+        Ok(())
+    }
+
+    /// Clear `last_error` on every local registry entry.
+    pub fn clear_all_local_errors(&mut self) {
+        let cleared: Vec<FileId> = self
+            .registry
+            .values_mut()
+            .filter_map(|entry| entry.last_error.take().map(|_| entry.file_id))
+            .collect();
+        for file_id in cleared {
+            self.log_registry_entry(file_id);
+        }
+    }
+
+    /// Clear `last_error` on one device's state in the shared record.
+    pub fn clear_device_errors(
+        &mut self,
+        file_id: FileId,
+        device_id: DeviceId,
+    ) -> Result<(), LocalMetadataError> {
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        let state = record
+            .device_states
+            .iter_mut()
+            .find(|d| d.device_id == device_id)
+            .ok_or(LocalMetadataError::DeviceStateNotFound { file_id, device_id })?;
+        if state.last_error.take().is_some() {
+            self.log_file_record(file_id);
+        }
+        Ok(())
+    }
+
+    /// Files with a local registry error or an error on any device state, in `FileId` order.
+    pub fn files_with_errors(&self) -> Vec<FileId> {
+        let mut ids: Vec<FileId> = self
+            .files
+            .keys()
+            .chain(self.registry.keys())
+            .copied()
+            .filter(|id| {
+                self.registry
+                    .get(id)
+                    .is_some_and(|entry| entry.last_error.is_some())
+                    || self
+                        .files
+                        .get(id)
+                        .is_some_and(|f| f.device_states.iter().any(|d| d.last_error.is_some()))
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Files that currently hold a lock, expired or not.
+    pub fn locked_files(&self) -> Vec<&FileRecord> {
+        self.files.values().filter(|f| f.lock.is_some()).collect()
+    }
+
+    /// Most released locks kept in each file's `lock_history`.
+    pub fn lock_history_cap(&self) -> usize {
+        self.lock_history_cap.unwrap_or(MAX_LOCK_HISTORY)
+    }
+
+    /// Limit how many released locks each file keeps from now on; values above
+    /// `MAX_LOCK_HISTORY` are clamped when history is recorded.
+    pub fn set_lock_history_cap(&mut self, cap: usize) {
+        self.lock_history_cap = Some(cap);
+    }
+
+    /// Release `device_id`'s lock on the file, keeping it in `lock_history` under the store's
+    /// cap. No-op if another device holds the lock or there is none.
+    pub fn release_lock(
+        &mut self,
+        file_id: FileId,
+        device_id: DeviceId,
+    ) -> Result<(), LocalMetadataError> {
+        let history_cap = self.lock_history_cap();
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        release_lock(record, device_id, history_cap)?;
+        self.log_file_record(file_id);
+        Ok(())
+    }
+
+    /// Clear every lock that has expired as of `now`; returns the affected files in `FileId` order.
+    pub fn release_expired_locks(&mut self, now: DateTime<Utc>) -> Vec<FileId> {
+        let history_cap = self.lock_history_cap();
+        let mut released: Vec<FileId> = self
+            .files
+            .values_mut()
+            .filter(|f| f.lock.as_ref().is_some_and(|lock| lock.is_expired(now)))
+            .map(|f| {
+                force_release_lock(f, history_cap);
+                f.file_id
+            })
+            .collect();
+        released.sort();
+        for file_id in &released {
+            self.log_file_record(*file_id);
+        }
+        released
+    }
+
+    /// Soft-delete a file. Rejected while the file is locked; release the lock first.
+    pub fn tombstone_file(
+        &mut self,
+        file_id: FileId,
+        device_id: DeviceId,
+        user_id: String,
+    ) -> Result<(), LocalMetadataError> {
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        if record.lock.is_some() {
+            return Err(ModelError::TombstonedWithActiveLock.into());
+        }
+        record.tombstone = Some(TombstoneRecord {
+            deleted_at: Utc::now(),
+            deleted_by_device: device_id,
+            deleted_by_user: user_id,
+        });
+        self.log_file_record(file_id);
+        Ok(())
+    }
+
+    /// Undo a soft-delete; no-op if the file is not tombstoned.
+    pub fn restore_file(&mut self, file_id: FileId) -> Result<(), LocalMetadataError> {
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        restore_tombstone(record)?;
+        self.log_file_record(file_id);
+        Ok(())
+    }
+
+    /// Set a custom metadata key on the shared record, replacing any previous value.
+    pub fn set_metadata_key(
+        &mut self,
+        file_id: FileId,
+        key: String,
+        value: String,
+    ) -> Result<(), LocalMetadataError> {
+        validate_metadata_key(&key)?;
+        let record = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        record.custom_metadata.insert(key, value);
+        self.log_file_record(file_id);
+        Ok(())
+    }
+
+    pub fn get_metadata_key(&self, file_id: &FileId, key: &str) -> Option<&str> {
+        self.files
+            .get(file_id)?
+            .custom_metadata
+            .get(key)
+            .map(String::as_str)
+    }
+
+    /// Set a file's display name. Names must be non-empty, at most `MAX_DISPLAY_NAME_LEN`
+    /// characters, and unique across the store ignoring case.
+    pub fn set_display_name(
+        &mut self,
+        file_id: FileId,
+        name: String,
+    ) -> Result<(), LocalMetadataError> {
+        if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_LEN {
+            return Err(LocalMetadataError::InvalidDisplayName(name));
+        }
+        let mut record = self
+            .files
+            .get(&file_id)
+            .cloned()
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        record.display_name = Some(name);
+        self.upsert_file_record(record)
+    }
+
+    /// Files whose display name starts with `query`, ignoring case, sorted by `FileId`.
+    pub fn find_by_display_name(&self, query: &str) -> Vec<FileId> {
+        let prefix = query.to_lowercase();
+        let mut hits: Vec<FileId> = self
+            .display_name_index
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        hits.sort();
+        hits
+    }
+
+    /// Files with neither a display name nor any bound path; these need naming.
+    pub fn files_without_display_name(&self) -> Vec<FileId> {
+        self.files
+            .values()
+            .filter(|f| f.display_name.is_none())
+            .filter(|f| {
+                self.registry
+                    .get(&f.file_id)
+                    .is_none_or(|entry| entry.paths.is_empty())
+            })
+            .map(|f| f.file_id)
+            .collect()
+    }
+
+    /// All versions of a file authored by the given user, in stored order.
+    pub fn find_versions_by_author(&self, file_id: &FileId, user_id: &str) -> Vec<&VersionRecord> {
+        self.files
+            .get(file_id)
+            .map(|f| {
+                f.versions
+                    .iter()
+                    .filter(|v| v.author_user_id.as_deref() == Some(user_id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Files whose shared head is not yet known to `device_id`, with their registry priority.
+    ///
+    /// Tombstoned files, files without local sync consent, and paused files are skipped. Files
+    /// without a registry entry get the lowest priority. Results are ordered by priority, then
+    /// `FileId`.
+    pub fn files_needing_sync(&self, device_id: DeviceId) -> Vec<(FileId, u8)> {
+        let mut ids: Vec<(FileId, u8)> = self
+            .files
+            .values()
+            .filter(|f| f.tombstone.is_none())
+            .filter(|f| {
+                self.registry
+                    .get(&f.file_id)
+                    .is_none_or(|entry| is_sync_allowed(&entry.consent) && !entry.sync_paused)
+            })
+            .filter(|f| {
+                f.device_states
+                    .iter()
+                    .find(|d| d.device_id == device_id)
+                    .and_then(|d| d.known_head_version_id)
+                    != Some(f.head_version_id)
+            })
+            .map(|f| {
+                let priority = self
+                    .registry
+                    .get(&f.file_id)
+                    .map_or(u8::MAX, |entry| entry.priority);
+                (f.file_id, priority)
+            })
+            .collect();
+        ids.sort_by_key(|(id, priority)| (*priority, *id));
+        ids
+    }
+
+    /// Files `device_id` should be pushing, in `FileId` order: those it reports as `Pushing`,
+    /// and those whose head is on local disk while another device knows an older head (or
+    /// none). Skips the same files as `files_needing_sync`: tombstoned, without local sync
+    /// consent, or paused.
+    pub fn files_pending_push(&self, device_id: DeviceId) -> Vec<&FileRecord> {
+        let mut files: Vec<&FileRecord> = self
+            .files
+            .values()
+            .filter(|f| f.tombstone.is_none())
+            .filter(|f| {
+                self.registry
+                    .get(&f.file_id)
+                    .is_none_or(|entry| is_sync_allowed(&entry.consent) && !entry.sync_paused)
+            })
+            .filter(|f| {
+                let pushing = f
+                    .device_states
+                    .iter()
+                    .any(|d| d.device_id == device_id && d.state == DeviceFileStateKind::Pushing);
+                let head_on_disk = self
+                    .registry
+                    .get(&f.file_id)
+                    .is_some_and(|entry| entry.local_version_id == Some(f.head_version_id));
+                let peer_behind = f.device_states.iter().any(|d| {
+                    d.device_id != device_id && d.known_head_version_id != Some(f.head_version_id)
+                });
+                pushing || (head_on_disk && peer_behind)
+            })
+            .collect();
+        files.sort_by_key(|f| f.file_id);
+        files
+    }
+
+    /// `(file, device, direction)` for every device state that is `Pushing` or `Pulling`,
+    /// ordered by file then device. Derived from device states; see `active_sessions` for
+    /// tracked transfer sessions.
+    pub fn transfer_sessions_in_progress(&self) -> Vec<(FileId, DeviceId, TransferDirection)> {
+        let mut transfers: Vec<(FileId, DeviceId, TransferDirection)> = self
+            .files
+            .values()
+            .flat_map(|f| {
+                f.device_states.iter().filter_map(move |d| match d.state {
+                    DeviceFileStateKind::Pushing => {
+                        Some((f.file_id, d.device_id, TransferDirection::Push))
+                    }
+                    DeviceFileStateKind::Pulling => {
+                        Some((f.file_id, d.device_id, TransferDirection::Pull))
+                    }
+                    _ => None,
+                })
+            })
+            .collect();
+        transfers.sort_by_key(|(file_id, device_id, _)| (*file_id, *device_id));
+        transfers
+    }
+
+    /// Insert a transfer session, replacing any session with the same id.
+    pub fn upsert_session(&mut self, session: TransferSession) {
+        self.sessions.insert(session.transfer_session_id, session);
+    }
+
+    pub fn session_by_id(&self, id: TransferSessionId) -> Option<&TransferSession> {
+        self.sessions.get(&id)
+    }
+
+    /// Registered sessions whose status is the same kind as `status`, oldest first. Payloads
+    /// are ignored, so `Failed(_)` matches every failed session whatever the message.
+    pub fn sessions_by_status(&self, status: TransferStatus) -> Vec<&TransferSession> {
+        let kind = std::mem::discriminant(&status);
+        let mut sessions: Vec<&TransferSession> = self
+            .sessions
+            .values()
+            .filter(|s| std::mem::discriminant(&s.status) == kind)
+            .collect();
+        sessions.sort_by_key(|s| (s.started_at, s.transfer_session_id));
+        sessions
+    }
+
+    /// Record a session's final status. The session stays queryable via `sessions_for_file`.
+    pub fn complete_session(
+        &mut self,
+        session_id: TransferSessionId,
+        status: TransferStatus,
+    ) -> Result<(), LocalMetadataError> {
+        let session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or(LocalMetadataError::SessionNotFound(session_id))?;
+        session.status = status;
+        Ok(())
+    }
+
+    /// Registered sessions that are `InProgress` or `Paused`, oldest first.
+    pub fn active_sessions(&self) -> Vec<&TransferSession> {
+        let mut sessions: Vec<&TransferSession> = self
+            .sessions
+            .values()
+            .filter(|s| {
+                matches!(
+                    s.status,
+                    TransferStatus::InProgress | TransferStatus::Paused { .. }
+                )
+            })
+            .collect();
+        sessions.sort_by_key(|s| (s.started_at, s.transfer_session_id));
+        sessions
+    }
+
+    /// Every registered session for `file_id`, whatever its status, oldest first.
+    pub fn sessions_for_file(&self, file_id: FileId) -> Vec<&TransferSession> {
+        let mut sessions: Vec<&TransferSession> = self
+            .sessions
+            .values()
+            .filter(|s| s.file_id == file_id)
+            .collect();
+        sessions.sort_by_key(|s| (s.started_at, s.transfer_session_id));
+        sessions
+    }
+
+    /// Highest-priority (lowest number) file that `device_id` still needs.
+    pub fn next_file_to_sync(&self, device_id: DeviceId) -> Option<FileId> {
+        self.files_needing_sync(device_id)
+            .first()
+            .map(|(id, _)| *id)
+    }
+
+    /// Files whose registry entry belongs to `group`, in `FileId` order.
+    pub fn files_in_group(&self, group: &str) -> Vec<FileId> {
+        let mut ids: Vec<FileId> = self
+            .registry
+            .values()
+            .filter(|entry| entry.sync_group.as_deref() == Some(group))
+            .map(|entry| entry.file_id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Head-version transfer plans for every out-of-date file in `group`.
+    pub fn transfer_plans_for_group(
+        &self,
+        group: &str,
+        direction: TransferDirection,
+        device_id: DeviceId,
+    ) -> Vec<TransferPlan> {
+        let members = self.files_in_group(group);
+        self.files_needing_sync(device_id)
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| members.contains(id))
+            .filter_map(|id| {
+                let head = self.files.get(&id)?.head_version()?;
+                Some(TransferPlan::new(
+                    id,
+                    head.version_id,
+                    direction.clone(),
+                    head.encoding,
+                    head.chunks.clone(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Registry entries the user has starred, in no particular order.
+    pub fn starred_files(&self) -> Vec<&LocalRegistryEntry> {
+        self.registry
+            .values()
+            .filter(|entry| entry.starred)
+            .collect()
+    }
+
+    /// Files whose encryption key `device_id` is trusted with, in `FileId` order. Empty if
+    /// `identities` has no entry for the device.
+    pub fn files_accessible_to_device(
+        &self,
+        device_id: DeviceId,
+        identities: &[DeviceIdentity],
+    ) -> Vec<FileId> {
+        let Some(identity) = identities.iter().find(|i| i.device_id == device_id) else {
+            return Vec::new();
+        };
+        let mut ids: Vec<FileId> = identity
+            .trusted_key_ids
+            .iter()
+            .flat_map(|key_id| self.files_for_key_id(key_id))
+            .map(|f| f.file_id)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Number of path bindings per registered file, most first (ties in `FileId` order), for
+    /// spotting files that accumulate paths through renames and moves.
+    pub fn paths_per_file_report(&self) -> Vec<(FileId, usize)> {
+        let mut report: Vec<(FileId, usize)> = self
+            .registry
+            .values()
+            .map(|entry| (entry.file_id, entry.paths.len()))
+            .collect();
+        report.sort_by_key(|(file_id, count)| (std::cmp::Reverse(*count), *file_id));
+        report
+    }
+
+    /// Files bound at more than one path, in `FileId` order.
+    pub fn files_with_multiple_paths(&self) -> Vec<FileId> {
+        let mut ids: Vec<FileId> = self
+            .registry
+            .values()
+            .filter(|entry| entry.paths.len() > 1)
+            .map(|entry| entry.file_id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Most path bindings held by any one file; 0 for an empty registry.
+    pub fn max_path_count(&self) -> usize {
+        self.registry
+            .values()
+            .map(|entry| entry.paths.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Every path binding on `mount` across all files; used when a drive is ejected.
+    pub fn all_paths_on_mount(&self, mount: &str) -> Vec<(FileId, &PathBinding)> {
+        self.registry
+            .values()
+            .flat_map(|entry| {
+                crate::paths_on_mount(entry, mount)
+                    .into_iter()
+                    .map(move |p| (entry.file_id, p))
+            })
+            .collect()
+    }
+
+    /// Rebuild the store as it was at `at` by replaying logged events up to and including it.
+    /// `None` if `at` is earlier than the last event compacted away, as that history is gone.
+    pub fn snapshot_at(&self, at: DateTime<Utc>) -> Option<LocalMetadataStore> {
+        if self
+            .log_base
+            .as_ref()
+            .is_some_and(|base| at < base.folded_through)
+        {
+            return None;
+        }
+        let mut state = self.log_base.clone().unwrap_or_default();
+        let log: Vec<LoggedEvent> = self
+            .log
+            .iter()
+            .take_while(|logged| logged.at <= at)
+            .cloned()
+            .collect();
+        for logged in &log {
+            state.apply(logged);
+        }
+        let next_seq = log
+            .last()
+            .or(self.log.first())
+            .map_or(self.next_seq, |logged| logged.seq + 1);
+        let mut snapshot = LocalMetadataStore {
+            files: state.files,
+            registry: state.registry,
+            conflicts: state.conflicts,
+            log,
+            log_base: self.log_base.clone(),
+            next_seq,
+            max_log_events: self.max_log_events,
+            lock_history_cap: self.lock_history_cap,
+            ..LocalMetadataStore::default()
+        };
+        snapshot.rebuild_indexes();
+        Some(snapshot)
+    }
+
+    /// Files whose head version was created after `since`.
+    pub fn iter_files_modified_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> impl Iterator<Item = &FileRecord> {
+        self.files
+            .values()
+            .filter(move |f| f.head_version().is_some_and(|head| head.timestamp > since))
+    }
+
+    /// Registry entries with any path binding seen after `since`.
+    pub fn iter_registry_updated_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> impl Iterator<Item = &LocalRegistryEntry> {
+        self.registry
+            .values()
+            .filter(move |entry| entry.paths.iter().any(|p| p.last_seen_at > since))
+    }
+
+    /// Records that have a device state for `device_id`, in no particular order.
+    pub fn file_records_for_device(
+        &self,
+        device_id: DeviceId,
+    ) -> impl Iterator<Item = &FileRecord> {
+        self.files
+            .values()
+            .filter(move |f| f.device_states.iter().any(|d| d.device_id == device_id))
+    }
+
+    /// Files `device_id` reports as `Ready`, in `FileId` order.
+    pub fn files_synced_by_device(&self, device_id: DeviceId) -> Vec<FileId> {
+        let mut ids: Vec<FileId> = self
+            .files
+            .values()
+            .filter(|f| {
+                f.device_states
+                    .iter()
+                    .any(|d| d.device_id == device_id && d.state == DeviceFileStateKind::Ready)
+            })
+            .map(|f| f.file_id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Records (and their registry entries) that `device_id` has state for, keeping only that
+    /// device's state. Ordered by `FileId`.
+    pub fn export_for_device(&self, device_id: DeviceId) -> DeviceStoreExport {
+        let mut files: Vec<FileRecord> = self
+            .file_records_for_device(device_id)
+            .map(|f| f.clone_for_device(device_id))
+            .collect();
+        files.sort_by_key(|f| f.file_id);
+        let registry = files
+            .iter()
+            .filter_map(|f| self.registry.get(&f.file_id).cloned())
+            .collect();
+        DeviceStoreExport {
+            device_id,
+            files,
+            registry,
+        }
+    }
+
+    /// Merge a peer's export into this store.
+    ///
+    /// - Unknown records are inserted as-is, each with a fresh registry entry: no paths,
+    ///   `Hydration::None` and `Consent::PendingReview`, so nothing syncs until approved here.
+    /// - For known records, versions are unioned and only `source_device`'s state is taken
+    ///   from the export; the export's head is adopted only if it already includes our head.
+    /// - The export's registry entries are ignored and existing ones are never modified; they
+    ///   hold local-only fields.
+    ///
+    /// Nothing is applied if the export was made for a device other than `source_device`
+    /// (`ExportDeviceMismatch`), or if any merged record fails validation, including a display
+    /// name already used by another file.
+    pub fn import_from_device(
+        &mut self,
+        export: DeviceStoreExport,
+        source_device: DeviceId,
+    ) -> Result<(), LocalMetadataError> {
+        if export.device_id != source_device {
+            return Err(LocalMetadataError::ExportDeviceMismatch {
+                expected: source_device,
+                actual: export.device_id,
+            });
+        }
+        let now = self.next_log_time();
+        let mut merged = Vec::new();
+        let mut fresh_entries = Vec::new();
+        let mut merged_names: HashMap<String, FileId> = HashMap::new();
+        for incoming in export.files {
+            let record = match self.files.get(&incoming.file_id) {
+                None => {
+                    if !self.registry.contains_key(&incoming.file_id) {
+                        let entry = pending_registry_entry(incoming.file_id, now);
+                        assert_registry_invariants(&entry)?;
+                        fresh_entries.push(entry);
+                    }
+                    incoming
+                }
+                Some(local) => {
+                    let mut record = local.clone();
+                    if incoming
+                        .versions
+                        .iter()
+                        .any(|v| v.version_id == local.head_version_id)
+                    {
+                        record.head_version_id = incoming.head_version_id;
+                    }
+                    for version in incoming.versions {
+                        if !record
+                            .versions
+                            .iter()
+                            .any(|v| v.version_id == version.version_id)
+                        {
+                            record.versions.push(version);
+                        }
+                    }
+                    if let Some(state) = incoming
+                        .device_states
+                        .into_iter()
+                        .find(|d| d.device_id == source_device)
+                    {
+                        record
+                            .device_states
+                            .retain(|d| d.device_id != source_device);
+                        record.device_states.push(state);
+                    }
+                    record
+                }
+            };
+            assert_file_invariants(&record)?;
+            self.verify_new_merkle_roots(&record)?;
+            self.check_display_name(&record)?;
+            if let Some(name) = &record.display_name {
+                if let Some(holder) = merged_names.insert(name.to_lowercase(), record.file_id) {
+                    return Err(LocalMetadataError::DuplicateDisplayName {
+                        name: name.clone(),
+                        file_id: holder,
+                    });
+                }
+            }
+            merged.push(record);
+        }
+
+        for record in merged {
+            let file_id = record.file_id;
+            self.insert_file(record);
+            self.log_file_record(file_id);
+        }
+        for entry in fresh_entries {
+            let file_id = entry.file_id;
+            self.insert_registry(entry);
+            self.log_registry_entry(file_id);
+        }
+        Ok(())
+    }
+
+    /// Drop versions nothing points at, returning the removed ids per affected file (in stored
+    /// order). A version is kept if it is the head, a device's known head, the registry's
+    /// local version, pinned by the registry's `pin`, or a side of a pending conflict.
+    pub fn gc_orphaned_versions(&mut self) -> HashMap<FileId, Vec<VersionId>> {
+        let mut removed = HashMap::new();
+        for record in self.files.values_mut() {
+            let mut referenced: HashSet<VersionId> = record
+                .device_states
+                .iter()
+                .filter_map(|d| d.known_head_version_id)
+                .collect();
+            referenced.insert(record.head_version_id);
+            if let Some(entry) = self.registry.get(&record.file_id) {
+                referenced.extend(entry.local_version_id);
+                referenced.extend(pinned_versions(record, &entry.pin));
+            }
+            referenced.extend(
+                self.conflicts
+                    .iter()
+                    .filter(|c| c.file_id == record.file_id)
+                    .flat_map(|c| [c.local_version_id, c.remote_version_id]),
+            );
+
+            let orphaned: Vec<VersionId> = record
+                .versions
+                .iter()
+                .map(|v| v.version_id)
+                .filter(|id| !referenced.contains(id))
+                .collect();
+            if !orphaned.is_empty() {
+                record
+                    .versions
+                    .retain(|v| referenced.contains(&v.version_id));
+                removed.insert(record.file_id, orphaned);
+            }
+        }
+        for file_id in removed.keys() {
+            self.log_file_record(*file_id);
+        }
+        removed
+    }
+
+    /// Prune a file's versions under `policy`, honoring the registry entry's pin. Returns the
+    /// pruned version ids in their original order. If the registry's `local_version_id` was
+    /// pruned it is cleared. On error nothing changes.
+    pub fn apply_retention_policy(
+        &mut self,
+        policy: &VersionRetention,
+        file_id: FileId,
+        now: SystemTime,
+    ) -> Result<Vec<VersionId>, LocalMetadataError> {
+        let record = self
+            .files
+            .get(&file_id)
+            .ok_or(LocalMetadataError::NotFound(file_id))?;
+        let pin = self
+            .registry
+            .get(&file_id)
+            .map_or(PinPreference::None, |entry| entry.pin.clone());
+        let mut pruned_record = record.clone();
+        apply_retention(&mut pruned_record, policy, &pin, now)?;
+        // Versions a lagging device still reports as its head are kept, so the record stays
+        // valid under Strict invariants.
+        let kept: HashSet<VersionId> = pruned_record
+            .versions
+            .iter()
+            .map(|v| v.version_id)
+            .chain(
+                record
+                    .device_states
+                    .iter()
+                    .filter_map(|d| d.known_head_version_id),
+            )
+            .collect();
+        let pruned: Vec<VersionId> = record
+            .versions
+            .iter()
+            .map(|v| v.version_id)
+            .filter(|id| !kept.contains(id))
+            .collect();
+        if pruned.is_empty() {
+            return Ok(pruned);
+        }
+        pruned_record.versions = record
+            .versions
+            .iter()
+            .filter(|v| kept.contains(&v.version_id))
+            .cloned()
+            .collect();
+        assert_file_invariants(&pruned_record)?;
+
+        self.insert_file(pruned_record);
+        self.log_file_record(file_id);
+        if let Some(entry) = self.registry.get_mut(&file_id) {
+            if entry.local_version_id.is_some_and(|id| !kept.contains(&id)) {
+                entry.local_version_id = None;
+                self.log_registry_entry(file_id);
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Number of versions across all file records.
+    pub fn version_count(&self) -> usize {
+        self.files.values().map(|f| f.versions.len()).sum()
+    }
+
+    /// Sum of `size_bytes` over every version of every file.
+    pub fn total_version_bytes(&self) -> u64 {
+        self.files
+            .values()
+            .flat_map(|f| &f.versions)
+            .map(|v| v.size_bytes)
+            .sum()
+    }
+
+    /// Mean versions per file record; 0.0 for an empty store.
+    pub fn average_versions_per_file(&self) -> f64 {
+        if self.files.is_empty() {
+            return 0.0;
+        }
+        self.version_count() as f64 / self.files.len() as f64
+    }
+
+    /// Mutation history since the last compaction, oldest first.
+    pub fn event_log(&self) -> &[LoggedEvent] {
+        &self.log
+    }
+
+    /// Most events kept in the log before the oldest are compacted.
+    pub fn max_log_events(&self) -> usize {
+        self.max_log_events.unwrap_or(DEFAULT_MAX_LOG_EVENTS)
+    }
+
+    /// Keep at most `max` events (at least one) in the log, compacting older ones now and as
+    /// new events arrive.
+    pub fn set_max_log_events(&mut self, max: usize) {
+        self.max_log_events = Some(max.max(1));
+        self.compact_log();
+    }
+
+    /// Fold the oldest events into `log_base` until the log fits `max_log_events`.
+    fn compact_log(&mut self) {
+        let excess = self.log.len().saturating_sub(self.max_log_events());
+        if excess == 0 {
+            return;
+        }
+        let base = self.log_base.get_or_insert_with(LogState::default);
+        for logged in self.log.drain(..excess) {
+            base.apply(&logged);
+            base.folded_through = logged.at;
+        }
+    }
+
+    /// Now, or the last event's time if the clock has stepped back since.
+    fn next_log_time(&self) -> DateTime<Utc> {
+        let now = Utc::now();
+        self.log.last().map_or(now, |logged| logged.at.max(now))
+    }
+
+    fn push_event(&mut self, at: DateTime<Utc>, event: StoreEvent) {
+        self.log.push(LoggedEvent {
+            seq: self.next_seq,
+            at,
+            event,
+        });
+        self.next_seq += 1;
+        self.compact_log();
+    }
+
+    fn record_event(&mut self, event: StoreEvent) {
+        let at = self.next_log_time();
+        self.push_event(at, event);
+    }
+
+    /// Log `file_id`'s current record, carrying only versions the log does not have yet.
+    fn log_file_record(&mut self, file_id: FileId) {
+        let Some(record) = self.files.get_mut(&file_id) else {
+            return;
+        };
+        let versions = std::mem::take(&mut record.versions);
+        let mut logged_record = record.clone();
+        let logged = self.logged_versions.entry(file_id).or_default();
+        logged_record.versions = versions
+            .iter()
+            .filter(|v| !logged.contains(&v.version_id))
+            .cloned()
+            .collect();
+        let version_ids: Vec<VersionId> = versions.iter().map(|v| v.version_id).collect();
+        *logged = version_ids.iter().copied().collect();
+        record.versions = versions;
+        self.record_event(StoreEvent::FileRecordUpserted {
+            record: Box::new(logged_record),
+            version_ids,
+        });
+    }
+
+    fn log_registry_entry(&mut self, file_id: FileId) {
+        if let Some(entry) = self.registry.get(&file_id) {
+            let event = StoreEvent::RegistryEntryUpserted(entry.clone());
+            self.record_event(event);
+        }
+    }
+
+    /// Getters for persistence/export.
+    pub fn file_record(&self, file_id: &FileId) -> Option<&FileRecord> {
+        self.files.get(file_id)
+    }
+
+    pub fn versions_for_file(&self, file_id: &FileId) -> Option<&[VersionRecord]> {
+        self.files.get(file_id).map(|f| f.versions.as_slice())
+    }
+
+    pub fn version_by_id(
+        &self,
+        file_id: &FileId,
+        version_id: &VersionId,
+    ) -> Option<&VersionRecord> {
+        self.versions_for_file(file_id)?
+            .iter()
+            .find(|v| v.version_id == *version_id)
+    }
+
+    pub fn head_version_record(&self, file_id: &FileId) -> Option<&VersionRecord> {
+        self.files.get(file_id)?.head_version()
+    }
+
+    pub fn registry_entry(&self, file_id: &FileId) -> Option<&LocalRegistryEntry> {
+        self.registry.get(file_id)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FileRecord> {
+        self.files.values()
+    }
+
+    pub fn registry_entries(&self) -> impl Iterator<Item = &LocalRegistryEntry> {
+        self.registry.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        is_trusted_for_file, ChunkRef, ContentEncoding, DeviceFileStateKind, EncryptionInfo,
+        LockMode, LockRecord,
+    };
+    use chrono::Duration;
+
+    use std::collections::HashMap;
+
+    fn ulid() -> crate::FileId {
+        ulid::Ulid::new()
+    }
+
+    fn sample_file_record() -> FileRecord {
+        let file_id = ulid();
+        let version_id = ulid();
+        FileRecord {
+            file_id,
+            origin_device_id: ulid(),
+            created_at: Utc::now(),
+            head_version_id: version_id,
+            versions: vec![VersionRecord {
+                version_id,
+                file_id,
+                parent_version_id: None,
+                origin_device_id: ulid(),
+                timestamp: Utc::now(),
+                content_hash: "hash".into(),
+                size_bytes: 10,
+                chunks: vec![ChunkRef {
+                    offset: 0,
+                    length: 10,
+                    hash: "hash".into(),
+                    sequence: 0,
+                }],
+                author_user_id: None,
+                commit_message: None,
+                encoding: ContentEncoding::Raw,
+                merkle_root: None,
+                size_on_wire: None,
+            }],
+            lock: None,
+            device_states: vec![DeviceFileState {
+                device_id: ulid(),
+                state: DeviceFileStateKind::Ready,
+                known_head_version_id: Some(version_id),
+                last_seen_at: Utc::now(),
+                last_error: None,
+                known_chunk_hashes: HashSet::new(),
+            }],
+            encryption: EncryptionInfo::aes_256_gcm("k1".into(), None),
+            custom_metadata: HashMap::new(),
+            tombstone: None,
+            display_name: None,
+            lock_history: Vec::new(),
+        }
+    }
+
+    fn sample_registry_entry(file_id: FileId) -> LocalRegistryEntry {
         LocalRegistryEntry {
             file_id,
             paths: vec![PathBinding {
                 path: "/tmp/a".into(),
                 last_seen_at: Utc::now(),
                 writable: true,
+                mount_point: None,
             }],
             local_version_id: None,
             hydration: Hydration::FullyPresent,
@@ -280,152 +2069,1822 @@ mod tests {
             pin: crate::PinPreference::None,
             auto_lock_preference: AutoLockPreference::OnEdit,
             last_error: None,
+            sync_group: None,
+            priority: 128,
+            starred: false,
+            local_size_bytes: None,
+            sync_paused: false,
+        }
+    }
+
+    #[test]
+    fn upsert_and_bind_paths_without_changing_identity() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+
+        store
+            .bind_path(file_id, "/tmp/renamed".into(), true, None)
+            .unwrap();
+        let entry = store.registry_entry(&file_id).unwrap();
+        assert!(entry.paths.iter().any(|p| p.path == "/tmp/renamed"));
+    }
+
+    #[test]
+    fn prevents_path_alias_across_files() {
+        let mut store = LocalMetadataStore::new();
+        let r1 = sample_file_record();
+        let r2 = sample_file_record();
+        let f1 = r1.file_id;
+        let f2 = r2.file_id;
+        store.upsert_file_record(r1).unwrap();
+        store.upsert_file_record(r2).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(f1))
+            .unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(f2))
+            .unwrap();
+
+        let err = store
+            .bind_path(f2, "/tmp/a".into(), true, None)
+            .expect_err("should reject alias");
+        assert!(matches!(err, LocalMetadataError::PathAlreadyBound(id) if id == f1));
+    }
+
+    #[test]
+    fn path_index_follows_bind_unbind_and_rename() {
+        let mut store = LocalMetadataStore::new();
+        let (f1, f2) = (ulid(), ulid());
+        store
+            .upsert_registry_entry(sample_registry_entry(f1))
+            .unwrap();
+        assert_eq!(store.file_id_for_path("/TMP/A"), Some(f1));
+
+        store
+            .bind_path(f1, "/tmp/Docs/b".into(), true, None)
+            .unwrap();
+        assert_eq!(store.file_id_for_path("/tmp/docs/B"), Some(f1));
+
+        store
+            .rename_path(f1, "/tmp/Docs/b", "/tmp/Docs/c".into())
+            .unwrap();
+        assert_eq!(store.file_id_for_path("/tmp/docs/b"), None);
+        assert_eq!(store.file_id_for_path("/tmp/docs/c"), Some(f1));
+        let err = store
+            .rename_path(f1, "/tmp/missing", "/tmp/d".into())
+            .unwrap_err();
+        assert_eq!(err, LocalMetadataError::PathNotBound("/tmp/missing".into()));
+
+        // Renaming onto a path the file already has merges the two bindings.
+        store
+            .rename_path(f1, "/tmp/a", "/TMP/docs/C".into())
+            .unwrap();
+        let paths: Vec<&str> = store
+            .registry_entry(&f1)
+            .unwrap()
+            .paths
+            .iter()
+            .map(|p| p.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["/tmp/Docs/c"]);
+        assert_eq!(store.file_id_for_path("/tmp/a"), None);
+        assert_eq!(store.file_id_for_path("/tmp/docs/c"), Some(f1));
+        store.verify_path_index().unwrap();
+
+        store.unbind_path(f1, "/tmp/a").unwrap();
+        assert_eq!(store.file_id_for_path("/tmp/a"), None);
+
+        let mut entry = sample_registry_entry(f2);
+        entry.paths[0].path = "/tmp/other".into();
+        store.upsert_registry_entry(entry).unwrap();
+        assert!(matches!(
+            store.rename_path(f2, "/tmp/other", "/TMP/DOCS/C".into()),
+            Err(LocalMetadataError::PathAlreadyBound(id)) if id == f1
+        ));
+        store.bind_path(f2, "/tmp/a".into(), true, None).unwrap();
+        assert_eq!(store.file_id_for_path("/tmp/a"), Some(f2));
+    }
+
+    #[test]
+    fn updates_device_state_and_keeps_invariants() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        let device_id = record.device_states[0].device_id;
+        store.upsert_file_record(record.clone()).unwrap();
+
+        store
+            .upsert_device_state(
+                file_id,
+                DeviceFileState {
+                    device_id,
+                    state: DeviceFileStateKind::Pushing,
+                    known_head_version_id: record.device_states[0].known_head_version_id,
+                    last_seen_at: Utc::now() + Duration::seconds(1),
+                    last_error: None,
+                    known_chunk_hashes: HashSet::new(),
+                },
+                DeviceStatePolicy::Strict,
+            )
+            .unwrap();
+
+        let updated = store.file_record(&file_id).unwrap();
+        assert_eq!(
+            updated
+                .device_states
+                .iter()
+                .find(|d| d.device_id == device_id)
+                .unwrap()
+                .state,
+            DeviceFileStateKind::Pushing
+        );
+    }
+
+    #[test]
+    fn strict_policy_rejects_invalid_transition() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        let mut state = record.device_states[0].clone();
+        store.upsert_file_record(record).unwrap();
+
+        state.state = DeviceFileStateKind::Absent;
+        let err = store
+            .upsert_device_state(file_id, state.clone(), DeviceStatePolicy::Strict)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            LocalMetadataError::InvalidStateTransition {
+                from: DeviceFileStateKind::Ready,
+                to: DeviceFileStateKind::Absent,
+            }
+        );
+        store
+            .upsert_device_state(file_id, state, DeviceStatePolicy::Permissive)
+            .unwrap();
+    }
+
+    #[test]
+    fn sets_and_clears_lock() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+
+        store
+            .set_lock(
+                file_id,
+                Some(LockRecord {
+                    lock_id: ulid(),
+                    file_id,
+                    owner_device_id: ulid(),
+                    owner_user_id: "user".into(),
+                    mode: LockMode::Exclusive,
+                    acquired_at: Utc::now(),
+                    auto_lock: true,
+                    expires_at: None,
+                    session_id: None,
+                }),
+            )
+            .unwrap();
+
+        assert!(store.file_record(&file_id).unwrap().lock.is_some());
+        store.set_lock(file_id, None).unwrap();
+        assert!(store.file_record(&file_id).unwrap().lock.is_none());
+    }
+
+    #[test]
+    fn append_version_updates_head_and_registry() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+
+        let new_version_id = ulid();
+        store
+            .append_version(
+                file_id,
+                new_version_id,
+                VersionRecord {
+                    version_id: new_version_id,
+                    file_id,
+                    parent_version_id: None,
+                    origin_device_id: ulid(),
+                    timestamp: Utc::now(),
+                    content_hash: "hash2".into(),
+                    size_bytes: 20,
+                    chunks: vec![ChunkRef {
+                        offset: 0,
+                        length: 20,
+                        hash: "hash2".into(),
+                        sequence: 0,
+                    }],
+                    author_user_id: None,
+                    commit_message: None,
+                    encoding: ContentEncoding::Raw,
+                    merkle_root: None,
+                    size_on_wire: None,
+                },
+            )
+            .unwrap();
+
+        let updated = store.file_record(&file_id).unwrap();
+        assert_eq!(updated.head_version_id, new_version_id);
+        assert_eq!(updated.versions.len(), 2);
+        assert_eq!(
+            store.registry_entry(&file_id).unwrap().local_version_id,
+            Some(new_version_id)
+        );
+    }
+
+    #[test]
+    fn set_local_preferences_updates_flags() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+
+        store
+            .set_local_preferences(
+                file_id,
+                Some(Hydration::None),
+                Some(Consent::Revoked),
+                Some(AutoLockPreference::Manual),
+            )
+            .unwrap();
+
+        let entry = store.registry_entry(&file_id).unwrap();
+        assert!(matches!(entry.hydration, Hydration::None));
+        assert!(matches!(entry.consent, Consent::Revoked));
+        assert!(matches!(entry.auto_lock_preference, AutoLockPreference::Manual));
+    }
+
+    #[test]
+    fn set_local_preferences_accepts_pending_review() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+
+        store
+            .set_local_preferences(
+                file_id,
+                None,
+                Some(Consent::PendingReview {
+                    submitted_at: Utc::now(),
+                    reviewer_id: None,
+                }),
+                None,
+            )
+            .unwrap();
+
+        let entry = store.registry_entry(&file_id).unwrap();
+        assert!(matches!(entry.consent, Consent::PendingReview { .. }));
+        assert!(!crate::is_sync_allowed(&entry.consent));
+    }
+
+    #[test]
+    fn metadata_keys_round_trip_through_store() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+
+        store
+            .set_metadata_key(file_id, "classification".into(), "internal".into())
+            .unwrap();
+        assert_eq!(
+            store.get_metadata_key(&file_id, "classification"),
+            Some("internal")
+        );
+        assert_eq!(store.get_metadata_key(&file_id, "missing"), None);
+
+        let err = store
+            .set_metadata_key(file_id, String::new(), "v".into())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LocalMetadataError::Model(ModelError::InvalidMetadataKey(_))
+        ));
+    }
+
+    #[test]
+    fn tombstone_and_restore_file() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+
+        store
+            .tombstone_file(file_id, ulid(), "user".into())
+            .unwrap();
+        assert!(crate::is_tombstoned(store.file_record(&file_id).unwrap()));
+
+        store.restore_file(file_id).unwrap();
+        assert!(!crate::is_tombstoned(store.file_record(&file_id).unwrap()));
+    }
+
+    #[test]
+    fn tombstone_rejected_while_locked() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .set_lock(
+                file_id,
+                Some(LockRecord {
+                    lock_id: ulid(),
+                    file_id,
+                    owner_device_id: ulid(),
+                    owner_user_id: "user".into(),
+                    mode: LockMode::Exclusive,
+                    acquired_at: Utc::now(),
+                    auto_lock: false,
+                    expires_at: None,
+                    session_id: None,
+                }),
+            )
+            .unwrap();
+
+        let err = store
+            .tombstone_file(file_id, ulid(), "user".into())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            LocalMetadataError::Model(ModelError::TombstonedWithActiveLock)
+        );
+        assert!(store.file_record(&file_id).unwrap().tombstone.is_none());
+    }
+
+    #[test]
+    fn finds_by_display_name_case_insensitive() {
+        let mut store = LocalMetadataStore::new();
+        let mut named = sample_file_record();
+        named.display_name = Some("Hero_Character.fbx".into());
+        let named_id = named.file_id;
+        store.upsert_file_record(named).unwrap();
+        store.upsert_file_record(sample_file_record()).unwrap();
+
+        assert_eq!(store.find_by_display_name("HERO_"), vec![named_id]);
+        assert_eq!(
+            store.find_by_display_name("hero_character.FBX"),
+            vec![named_id]
+        );
+        assert!(store.find_by_display_name("terrain").is_empty());
+    }
+
+    #[test]
+    fn finds_by_display_name_prefix() {
+        let mut store = LocalMetadataStore::new();
+        let mut ids = Vec::new();
+        for name in ["rock_large", "Rock_small", "tree_rock"] {
+            let record = sample_file_record();
+            ids.push(record.file_id);
+            store.upsert_file_record(record).unwrap();
+            store
+                .set_display_name(*ids.last().unwrap(), name.into())
+                .unwrap();
+        }
+
+        let mut expected = vec![ids[0], ids[1]];
+        expected.sort();
+        assert_eq!(store.find_by_display_name("rock"), expected);
+        // Only prefixes match, not substrings.
+        assert!(store.find_by_display_name("_rock").is_empty());
+        assert_eq!(store.find_by_display_name("").len(), 3);
+
+        // Renaming drops the old name from the index.
+        store.set_display_name(ids[0], "boulder".into()).unwrap();
+        assert_eq!(store.find_by_display_name("rock"), vec![ids[1]]);
+        assert_eq!(store.find_by_display_name("boulder"), vec![ids[0]]);
+    }
+
+    #[test]
+    fn rejects_duplicate_and_invalid_display_names() {
+        let mut store = LocalMetadataStore::new();
+        let first = sample_file_record();
+        let first_id = first.file_id;
+        let second = sample_file_record();
+        let second_id = second.file_id;
+        store.upsert_file_record(first).unwrap();
+        store.upsert_file_record(second.clone()).unwrap();
+        store.set_display_name(first_id, "Hero.fbx".into()).unwrap();
+
+        assert_eq!(
+            store.set_display_name(second_id, "hero.FBX".into()),
+            Err(LocalMetadataError::DuplicateDisplayName {
+                name: "hero.FBX".into(),
+                file_id: first_id,
+            })
+        );
+        let mut renamed = second;
+        renamed.display_name = Some("HERO.fbx".into());
+        assert!(matches!(
+            store.upsert_file_record(renamed),
+            Err(LocalMetadataError::DuplicateDisplayName { .. })
+        ));
+        // Re-setting a file's own name, even with different case, is fine.
+        store.set_display_name(first_id, "HERO.FBX".into()).unwrap();
+
+        assert_eq!(
+            store.set_display_name(second_id, String::new()),
+            Err(LocalMetadataError::InvalidDisplayName(String::new()))
+        );
+        let long = "x".repeat(MAX_DISPLAY_NAME_LEN + 1);
+        assert_eq!(
+            store.set_display_name(second_id, long.clone()),
+            Err(LocalMetadataError::InvalidDisplayName(long))
+        );
+        store
+            .set_display_name(second_id, "x".repeat(MAX_DISPLAY_NAME_LEN))
+            .unwrap();
+        assert!(matches!(
+            store.set_display_name(ulid(), "ghost".into()),
+            Err(LocalMetadataError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn indexes_every_file_sharing_a_stored_display_name() {
+        let mut store = LocalMetadataStore::new();
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let record = sample_file_record();
+            ids.push(record.file_id);
+            store.upsert_file_record(record).unwrap();
+        }
+        ids.sort();
+
+        // Older data may repeat a name; both files must still be found and removable.
+        let mut json = serde_json::to_value(&store).unwrap();
+        for id in &ids {
+            json["files"][id.to_string()]["display_name"] = "Hero.fbx".into();
+        }
+        let mut restored: LocalMetadataStore = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.find_by_display_name("hero"), ids);
+        restored.retain_files(|record, _| record.file_id != ids[0]);
+        assert_eq!(restored.find_by_display_name("hero"), vec![ids[1]]);
+    }
+
+    #[test]
+    fn reports_files_needing_names() {
+        let mut store = LocalMetadataStore::new();
+        let orphan = sample_file_record();
+        let orphan_id = orphan.file_id;
+        let bound = sample_file_record();
+        let bound_id = bound.file_id;
+        let mut named = sample_file_record();
+        named.display_name = Some("named".into());
+        store.upsert_file_record(orphan).unwrap();
+        store.upsert_file_record(bound).unwrap();
+        store.upsert_file_record(named).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(bound_id))
+            .unwrap();
+
+        assert_eq!(store.files_without_display_name(), vec![orphan_id]);
+    }
+
+    #[test]
+    fn finds_versions_by_author() {
+        let mut store = LocalMetadataStore::new();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        record.versions[0].author_user_id = Some("alice".into());
+        store.upsert_file_record(record).unwrap();
+
+        let version_id = ulid();
+        store
+            .append_version(
+                file_id,
+                version_id,
+                VersionRecord {
+                    version_id,
+                    file_id,
+                    parent_version_id: None,
+                    origin_device_id: ulid(),
+                    timestamp: Utc::now(),
+                    content_hash: "hash2".into(),
+                    size_bytes: 20,
+                    chunks: vec![],
+                    author_user_id: Some("bob".into()),
+                    commit_message: Some("retopo".into()),
+                    encoding: ContentEncoding::Raw,
+                    merkle_root: None,
+                    size_on_wire: None,
+                },
+            )
+            .unwrap();
+
+        let by_bob = store.find_versions_by_author(&file_id, "bob");
+        assert_eq!(by_bob.len(), 1);
+        assert_eq!(by_bob[0].version_id, version_id);
+        assert_eq!(store.find_versions_by_author(&file_id, "alice").len(), 1);
+        assert!(store.find_versions_by_author(&file_id, "carol").is_empty());
+    }
+
+    #[test]
+    fn group_plans_cover_only_out_of_date_files() {
+        let mut store = LocalMetadataStore::new();
+        let device = ulid();
+
+        let stale = sample_file_record();
+        let stale_id = stale.file_id;
+        let mut current = sample_file_record();
+        let current_id = current.file_id;
+        current.device_states.push(DeviceFileState {
+            device_id: device,
+            state: DeviceFileStateKind::Ready,
+            known_head_version_id: Some(current.head_version_id),
+            last_seen_at: Utc::now(),
+            last_error: None,
+            known_chunk_hashes: HashSet::new(),
+        });
+        let outsider = sample_file_record();
+        let outsider_id = outsider.file_id;
+        store.upsert_file_record(stale).unwrap();
+        store.upsert_file_record(current).unwrap();
+        store.upsert_file_record(outsider).unwrap();
+
+        for id in [stale_id, current_id] {
+            let mut entry = sample_registry_entry(id);
+            entry.paths.clear();
+            entry.sync_group = Some("project-x".into());
+            store.upsert_registry_entry(entry).unwrap();
+        }
+        let mut other = sample_registry_entry(outsider_id);
+        other.paths.clear();
+        store.upsert_registry_entry(other).unwrap();
+
+        let mut expected = vec![stale_id, current_id];
+        expected.sort();
+        assert_eq!(store.files_in_group("project-x"), expected);
+        assert!(store.files_in_group("project-y").is_empty());
+
+        let plans = store.transfer_plans_for_group("project-x", TransferDirection::Pull, device);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].file_id, stale_id);
+        assert_eq!(
+            plans[0].version_id,
+            store.file_record(&stale_id).unwrap().head_version_id
+        );
+    }
+
+    #[test]
+    fn next_file_to_sync_prefers_lowest_priority_number() {
+        let mut store = LocalMetadataStore::new();
+        let background = sample_file_record();
+        let background_id = background.file_id;
+        let urgent = sample_file_record();
+        let urgent_id = urgent.file_id;
+        store.upsert_file_record(background).unwrap();
+        store.upsert_file_record(urgent).unwrap();
+
+        let mut entry = sample_registry_entry(background_id);
+        entry.paths.clear();
+        entry.priority = 255;
+        store.upsert_registry_entry(entry).unwrap();
+        let mut entry = sample_registry_entry(urgent_id);
+        entry.paths.clear();
+        entry.priority = 0;
+        store.upsert_registry_entry(entry).unwrap();
+
+        let device = ulid();
+        assert_eq!(
+            store.files_needing_sync(device),
+            vec![(urgent_id, 0), (background_id, 255)]
+        );
+        assert_eq!(store.next_file_to_sync(device), Some(urgent_id));
+    }
+
+    #[test]
+    fn starring_toggles_starred_files() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+        assert!(store.starred_files().is_empty());
+
+        store.set_starred(file_id, true).unwrap();
+        let starred = store.starred_files();
+        assert_eq!(starred.len(), 1);
+        assert_eq!(starred[0].file_id, file_id);
+
+        store.set_starred(file_id, false).unwrap();
+        assert!(store.starred_files().is_empty());
+        assert!(matches!(
+            store.set_starred(ulid(), true),
+            Err(LocalMetadataError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn collects_paths_on_mount_across_files() {
+        let mut store = LocalMetadataStore::new();
+        let f1 = ulid();
+        let f2 = ulid();
+        let mut e1 = sample_registry_entry(f1);
+        e1.paths[0].mount_point = Some("/Volumes/USB".into());
+        let mut e2 = sample_registry_entry(f2);
+        e2.paths = vec![PathBinding {
+            path: "/mnt/share/b".into(),
+            last_seen_at: Utc::now(),
+            writable: false,
+            mount_point: Some("/mnt/share".into()),
+        }];
+        store.upsert_registry_entry(e1).unwrap();
+        store.upsert_registry_entry(e2).unwrap();
+
+        let usb = store.all_paths_on_mount("/Volumes/USB");
+        assert_eq!(usb.len(), 1);
+        assert_eq!(usb[0].0, f1);
+        let share = store.all_paths_on_mount("/mnt/share");
+        assert_eq!(share.len(), 1);
+        assert_eq!(share[0].0, f2);
+        assert_eq!(share[0].1.path, "/mnt/share/b");
+
+        // Paths bound through the store carry their mount, including in replayed history.
+        store
+            .bind_path(
+                f1,
+                "/Volumes/USB/c".into(),
+                true,
+                Some("/Volumes/USB".into()),
+            )
+            .unwrap();
+        let usb: Vec<&str> = store
+            .all_paths_on_mount("/Volumes/USB")
+            .iter()
+            .map(|(_, p)| p.path.as_str())
+            .collect();
+        assert_eq!(usb, vec!["/tmp/a", "/Volumes/USB/c"]);
+        let snapshot = store.snapshot_at(Utc::now()).unwrap();
+        assert_eq!(snapshot.all_paths_on_mount("/Volumes/USB").len(), 2);
+
+        // Rebinding updates the mount.
+        store
+            .bind_path(f1, "/Volumes/USB/c".into(), true, None)
+            .unwrap();
+        assert_eq!(store.all_paths_on_mount("/Volumes/USB").len(), 1);
+    }
+
+    #[test]
+    fn snapshot_at_shows_only_earlier_records() {
+        let mut store = LocalMetadataStore::new();
+        let early = sample_file_record();
+        let early_id = early.file_id;
+        store.upsert_file_record(early).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(early_id))
+            .unwrap();
+        let checkpoint = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        let late = sample_file_record();
+        let late_id = late.file_id;
+        store.upsert_file_record(late).unwrap();
+        store
+            .bind_path(early_id, "/tmp/moved".into(), true, None)
+            .unwrap();
+
+        let snapshot = store.snapshot_at(checkpoint).unwrap();
+        assert!(snapshot.file_record(&early_id).is_some());
+        assert!(snapshot.file_record(&late_id).is_none());
+        let paths = &snapshot.registry_entry(&early_id).unwrap().paths;
+        assert!(!paths.iter().any(|p| p.path == "/tmp/moved"));
+        assert_eq!(snapshot.event_log().len(), 2);
+
+        let now = store.snapshot_at(Utc::now()).unwrap();
+        assert!(now.file_record(&late_id).is_some());
+        let paths = &now.registry_entry(&early_id).unwrap().paths;
+        assert!(paths.iter().any(|p| p.path == "/tmp/moved"));
+    }
+
+    #[test]
+    fn file_events_carry_only_new_versions() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        let first = record.versions[0].clone();
+        store.upsert_file_record(record).unwrap();
+
+        let mut second = first.clone();
+        second.version_id = ulid();
+        second.parent_version_id = Some(first.version_id);
+        store
+            .append_version(file_id, second.version_id, second.clone())
+            .unwrap();
+        store
+            .set_metadata_key(file_id, "project".into(), "demo".into())
+            .unwrap();
+
+        let logged: Vec<(Vec<VersionId>, Vec<VersionId>)> = store
+            .event_log()
+            .iter()
+            .map(|logged| match &logged.event {
+                StoreEvent::FileRecordUpserted {
+                    record,
+                    version_ids,
+                } => (
+                    record.versions.iter().map(|v| v.version_id).collect(),
+                    version_ids.clone(),
+                ),
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect();
+        let both = vec![first.version_id, second.version_id];
+        assert_eq!(
+            logged,
+            vec![
+                (vec![first.version_id], vec![first.version_id]),
+                (vec![second.version_id], both.clone()),
+                (vec![], both),
+            ]
+        );
+        let seqs: Vec<u64> = store.event_log().iter().map(|l| l.seq).collect();
+        assert_eq!(seqs, vec![0, 1, 2]);
+        assert!(store.event_log().windows(2).all(|w| w[0].at <= w[1].at));
+
+        let snapshot = store.snapshot_at(Utc::now()).unwrap();
+        assert_eq!(snapshot.file_record(&file_id), store.file_record(&file_id));
+    }
+
+    #[test]
+    fn compacts_the_event_log() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(file_id))
+            .unwrap();
+        let before_paths = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        for i in 0..4 {
+            store
+                .bind_path(file_id, format!("/tmp/extra-{i}"), true, None)
+                .unwrap();
+        }
+
+        store.set_max_log_events(3);
+        assert_eq!(store.max_log_events(), 3);
+        let seqs: Vec<u64> = store.event_log().iter().map(|l| l.seq).collect();
+        assert_eq!(seqs, vec![3, 4, 5]);
+        store.unbind_path(file_id, "/tmp/extra-0").unwrap();
+        assert_eq!(store.event_log().len(), 3);
+        assert_eq!(store.event_log()[2].seq, 6);
+
+        // Compacted history replays from the folded base.
+        let now = store.snapshot_at(Utc::now()).unwrap();
+        assert_eq!(now.file_record(&file_id), store.file_record(&file_id));
+        assert_eq!(now.registry_entry(&file_id), store.registry_entry(&file_id));
+        let oldest_kept = store.event_log()[0].at;
+        assert!(store.snapshot_at(oldest_kept).is_some());
+        // The base already holds binds made after this, so it cannot stand in for it.
+        assert!(store.snapshot_at(before_paths).is_none());
+
+        let json = serde_json::to_string(&store).unwrap();
+        let restored: LocalMetadataStore = serde_json::from_str(&json).unwrap();
+        let restored_now = restored.snapshot_at(Utc::now()).unwrap();
+        assert_eq!(
+            restored_now.registry_entry(&file_id),
+            store.registry_entry(&file_id)
+        );
+    }
+
+    #[test]
+    fn clears_errors_and_lists_files_with_errors() {
+        let mut store = LocalMetadataStore::new();
+        let r1 = sample_file_record();
+        let mut r2 = sample_file_record();
+        let (f1, f2) = (r1.file_id, r2.file_id);
+        let device_id = r2.device_states[0].device_id;
+        r2.device_states[0].last_error = Some("pull failed".into());
+        store.upsert_file_record(r1).unwrap();
+        store.upsert_file_record(r2).unwrap();
+        store
+            .upsert_registry_entry(sample_registry_entry(f1))
+            .unwrap();
+        store.set_local_error(f1, Some("disk full".into())).unwrap();
+
+        let mut expected = vec![f1, f2];
+        expected.sort();
+        assert_eq!(store.files_with_errors(), expected);
+
+        store.clear_all_local_errors();
+        assert_eq!(store.files_with_errors(), vec![f2]);
+
+        store.clear_device_errors(f2, device_id).unwrap();
+        assert!(store.files_with_errors().is_empty());
+        let missing = ulid();
+        assert_eq!(
+            store.clear_device_errors(f2, missing),
+            Err(LocalMetadataError::DeviceStateNotFound {
+                file_id: f2,
+                device_id: missing,
+            })
+        );
+    }
+
+    #[test]
+    fn releases_only_expired_locks() {
+        let mut store = LocalMetadataStore::new();
+        let now = Utc::now();
+        let expiries = [
+            Some(now - Duration::minutes(5)),
+            Some(now + Duration::minutes(5)),
+            None,
+        ];
+        let mut ids = Vec::new();
+        for expires_at in expiries {
+            let mut record = sample_file_record();
+            record.lock = Some(LockRecord {
+                lock_id: ulid(),
+                file_id: record.file_id,
+                owner_device_id: ulid(),
+                owner_user_id: "user".into(),
+                mode: LockMode::Exclusive,
+                acquired_at: now - Duration::minutes(10),
+                auto_lock: false,
+                expires_at,
+                session_id: None,
+            });
+            ids.push(record.file_id);
+            store.upsert_file_record(record).unwrap();
+        }
+        assert_eq!(store.locked_files().len(), 3);
+
+        assert_eq!(store.release_expired_locks(now), vec![ids[0]]);
+        let released = store.file_record(&ids[0]).unwrap();
+        assert!(released.lock.is_none());
+        assert_eq!(released.lock_history.len(), 1);
+        assert_eq!(store.locked_files().len(), 2);
+        assert!(store.release_expired_locks(now).is_empty());
+    }
+
+    #[test]
+    fn releases_locks_under_the_configured_history_cap() {
+        let mut store = LocalMetadataStore::new();
+        assert_eq!(store.lock_history_cap(), MAX_LOCK_HISTORY);
+        store.set_lock_history_cap(2);
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+
+        let device = ulid();
+        for _ in 0..3 {
+            let lock = LockRecord {
+                lock_id: ulid(),
+                file_id,
+                owner_device_id: device,
+                owner_user_id: "user".into(),
+                mode: LockMode::Exclusive,
+                acquired_at: Utc::now(),
+                auto_lock: false,
+                expires_at: None,
+                session_id: None,
+            };
+            store.set_lock(file_id, Some(lock)).unwrap();
+            // Another device cannot release it.
+            store.release_lock(file_id, ulid()).unwrap();
+            store.release_lock(file_id, device).unwrap();
+        }
+        let record = store.file_record(&file_id).unwrap();
+        assert!(record.lock.is_none());
+        assert_eq!(record.lock_history.len(), 2);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let restored: LocalMetadataStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.lock_history_cap(), 2);
+    }
+
+    #[test]
+    fn iterates_changes_since_cutoff() {
+        let mut store = LocalMetadataStore::new();
+        let cutoff = Utc::now();
+        let mut old = sample_file_record();
+        old.versions[0].timestamp = cutoff - Duration::hours(1);
+        let new = sample_file_record();
+        let (old_id, new_id) = (old.file_id, new.file_id);
+        store.upsert_file_record(old).unwrap();
+        store.upsert_file_record(new).unwrap();
+
+        let mut stale_entry = sample_registry_entry(old_id);
+        stale_entry.paths[0].last_seen_at = cutoff - Duration::hours(1);
+        store.upsert_registry_entry(stale_entry).unwrap();
+        let mut fresh_entry = sample_registry_entry(new_id);
+        fresh_entry.paths[0].path = "/tmp/b".into();
+        fresh_entry.paths[0].last_seen_at = cutoff + Duration::seconds(1);
+        store.upsert_registry_entry(fresh_entry).unwrap();
+
+        let files: Vec<FileId> = store
+            .iter_files_modified_since(cutoff - Duration::seconds(1))
+            .map(|f| f.file_id)
+            .collect();
+        assert_eq!(files, vec![new_id]);
+        let entries: Vec<FileId> = store
+            .iter_registry_updated_since(cutoff)
+            .map(|e| e.file_id)
+            .collect();
+        assert_eq!(entries, vec![new_id]);
+    }
+
+    #[test]
+    fn deduplicates_equivalent_paths() {
+        let mut store = LocalMetadataStore::new();
+        let record = sample_file_record();
+        let file_id = record.file_id;
+        store.upsert_file_record(record).unwrap();
+        let mut entry = sample_registry_entry(file_id);
+        let binding = entry.paths[0].clone();
+        for alias in ["/tmp/./a", "/tmp//a", "/tmp/b"] {
+            entry.paths.push(PathBinding {
+                path: alias.into(),
+                ..binding.clone()
+            });
+        }
+        store.upsert_registry_entry(entry).unwrap();
+
+        assert_eq!(store.deduplicate_paths(), 2);
+        let paths: Vec<&str> = store
+            .registry_entry(&file_id)
+            .unwrap()
+            .paths
+            .iter()
+            .map(|p| p.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["/tmp/a", "/tmp/b"]);
+        assert_eq!(store.deduplicate_paths(), 0);
+    }
+
+    #[test]
+    fn import_merges_without_clobbering() {
+        let mut source = LocalMetadataStore::new();
+        let shared = sample_file_record();
+        let unrelated = sample_file_record();
+        let (shared_id, unrelated_id) = (shared.file_id, unrelated.file_id);
+        let peer = shared.device_states[0].device_id;
+        source.upsert_file_record(shared.clone()).unwrap();
+        source.upsert_file_record(unrelated.clone()).unwrap();
+        let mut remote_entry = sample_registry_entry(shared_id);
+        remote_entry.paths[0].path = "/remote/a".into();
+        source.upsert_registry_entry(remote_entry).unwrap();
+
+        let export = source.export_for_device(peer);
+        assert_eq!(export.files.len(), 1);
+        assert_eq!(export.files[0].device_states.len(), 1);
+        let json = serde_json::to_string(&export).unwrap();
+        let export: DeviceStoreExport = serde_json::from_str(&json).unwrap();
+        let stranger = ulid();
+        assert_eq!(
+            LocalMetadataStore::new().import_from_device(export.clone(), stranger),
+            Err(LocalMetadataError::ExportDeviceMismatch {
+                expected: stranger,
+                actual: peer,
+            })
+        );
+
+        // Locally we know the shared file with our own device state and a local path.
+        let mut target = LocalMetadataStore::new();
+        let mut local = shared.clone();
+        let us = ulid();
+        local.device_states[0].device_id = us;
+        target.upsert_file_record(local).unwrap();
+        target
+            .upsert_registry_entry(sample_registry_entry(shared_id))
+            .unwrap();
+        let mut other = unrelated;
+        other.display_name = Some("keep me".into());
+        target.upsert_file_record(other.clone()).unwrap();
+
+        target.import_from_device(export, peer).unwrap();
+        let merged = target.file_record(&shared_id).unwrap();
+        let mut devices: Vec<DeviceId> = merged.device_states.iter().map(|d| d.device_id).collect();
+        devices.sort();
+        let mut expected = vec![us, peer];
+        expected.sort();
+        assert_eq!(devices, expected);
+        assert_eq!(
+            target.registry_entry(&shared_id).unwrap().paths[0].path,
+            "/tmp/a"
+        );
+        assert_eq!(target.file_record(&unrelated_id), Some(&other));
+    }
+
+    #[test]
+    fn import_does_not_copy_peer_registry_entries() {
+        let mut source = LocalMetadataStore::new();
+        let incoming = sample_file_record();
+        let incoming_id = incoming.file_id;
+        let peer = incoming.device_states[0].device_id;
+        source.upsert_file_record(incoming).unwrap();
+        source
+            .upsert_registry_entry(sample_registry_entry(incoming_id))
+            .unwrap();
+        let export = source.export_for_device(peer);
+        assert_eq!(export.registry[0].paths[0].path, "/tmp/a");
+
+        // The peer's path collides with one bound locally to another file.
+        let mut target = LocalMetadataStore::new();
+        let local = sample_file_record();
+        target.upsert_file_record(local.clone()).unwrap();
+        target
+            .upsert_registry_entry(sample_registry_entry(local.file_id))
+            .unwrap();
+
+        target.import_from_device(export, peer).unwrap();
+        assert_eq!(target.file_id_for_path("/tmp/a"), Some(local.file_id));
+        let entry = target.registry_entry(&incoming_id).unwrap();
+        assert!(entry.paths.is_empty());
+        assert_eq!(entry.hydration, Hydration::None);
+        assert!(matches!(entry.consent, Consent::PendingReview { .. }));
+        assert_eq!(entry.local_version_id, None);
+        assert!(!target
+            .files_needing_sync(ulid())
+            .iter()
+            .any(|(id, _)| *id == incoming_id));
+    }
+
+    #[test]
+    fn import_rejects_taken_display_names() {
+        let mut source = LocalMetadataStore::new();
+        let mut incoming = sample_file_record();
+        incoming.display_name = Some("Hero.fbx".into());
+        let peer = incoming.device_states[0].device_id;
+        source.upsert_file_record(incoming.clone()).unwrap();
+
+        let mut target = LocalMetadataStore::new();
+        let mut local = sample_file_record();
+        local.display_name = Some("HERO.FBX".into());
+        target.upsert_file_record(local.clone()).unwrap();
+
+        assert_eq!(
+            target.import_from_device(source.export_for_device(peer), peer),
+            Err(LocalMetadataError::DuplicateDisplayName {
+                name: "Hero.fbx".into(),
+                file_id: local.file_id,
+            })
+        );
+        assert!(target.file_record(&incoming.file_id).is_none());
+    }
+
+    #[test]
+    fn retain_files_drops_revoked() {
+        let mut store = LocalMetadataStore::new();
+        let mut ids = Vec::new();
+        for (i, consent) in [Consent::Approved, Consent::Revoked, Consent::Approved]
+            .into_iter()
+            .enumerate()
+        {
+            let record = sample_file_record();
+            let file_id = record.file_id;
+            store.upsert_file_record(record).unwrap();
+            let mut entry = sample_registry_entry(file_id);
+            entry.paths[0].path = format!("/tmp/{i}");
+            entry.consent = consent;
+            store.upsert_registry_entry(entry).unwrap();
+            ids.push(file_id);
+        }
+        let unregistered = sample_file_record();
+        let unregistered_id = unregistered.file_id;
+        store.upsert_file_record(unregistered).unwrap();
+
+        let removed =
+            store.retain_files(|_, entry| entry.is_none_or(|e| e.consent != Consent::Revoked));
+        assert_eq!(removed, 1);
+        assert!(store.file_record(&ids[1]).is_none());
+        assert!(store.registry_entry(&ids[1]).is_none());
+        for id in [ids[0], ids[2], unregistered_id] {
+            assert!(store.file_record(&id).is_some());
+        }
+        assert!(store
+            .snapshot_at(Utc::now())
+            .unwrap()
+            .file_record(&ids[1])
+            .is_none());
+    }
+
+    #[test]
+    fn aggregates_version_counts_and_bytes() {
+        let mut store = LocalMetadataStore::new();
+        assert_eq!(store.average_versions_per_file(), 0.0);
+        let mut expected_bytes = 0;
+        for count in [3u64, 5] {
+            let mut record = sample_file_record();
+            let template = record.versions[0].clone();
+            record.versions.clear();
+            for i in 0..count {
+                let version_id = ulid();
+                record.versions.push(VersionRecord {
+                    version_id,
+                    size_bytes: 100 * count + i,
+                    ..template.clone()
+                });
+                record.head_version_id = version_id;
+                expected_bytes += 100 * count + i;
+            }
+            record.device_states[0].known_head_version_id = Some(record.head_version_id);
+            store.upsert_file_record(record).unwrap();
+        }
+
+        assert_eq!(store.version_count(), 8);
+        assert_eq!(store.total_version_bytes(), expected_bytes);
+        assert_eq!(store.average_versions_per_file(), 4.0);
+    }
+
+    #[test]
+    fn tracks_local_disk_usage() {
+        let mut store = LocalMetadataStore::new();
+        let (a, b) = (ulid(), ulid());
+        store
+            .upsert_registry_entry(sample_registry_entry(a))
+            .unwrap();
+        let mut entry_b = sample_registry_entry(b);
+        entry_b.paths[0].path = "/tmp/b".into();
+        store.upsert_registry_entry(entry_b).unwrap();
+
+        store.set_local_size_bytes(a, Some(1_000)).unwrap();
+        store
+            .set_local_preferences(
+                b,
+                Some(Hydration::Partial {
+                    bytes_present: 250,
+                    total_bytes: 1_000,
+                }),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            store.registry_entry(&b).unwrap().local_size_bytes,
+            Some(250)
+        );
+        assert_eq!(store.total_local_disk_usage(), 1_250);
+
+        let err = store.set_local_size_bytes(b, Some(900)).unwrap_err();
+        assert_eq!(
+            err,
+            LocalMetadataError::Model(ModelError::LocalSizeMismatch {
+                expected: 250,
+                actual: Some(900),
+            })
+        );
+        assert_eq!(store.total_local_disk_usage(), 1_250);
+
+        store
+            .set_local_preferences(b, Some(Hydration::FullyPresent), None, None)
+            .unwrap();
+        assert_eq!(store.registry_entry(&b).unwrap().local_size_bytes, None);
+        assert_eq!(store.total_local_disk_usage(), 1_000);
+    }
+
+    #[test]
+    fn path_index_rebuilds_after_deserializing() {
+        let mut store = LocalMetadataStore::new();
+        let (f1, f2) = (ulid(), ulid());
+        store
+            .upsert_registry_entry(sample_registry_entry(f1))
+            .unwrap();
+        let mut entry = sample_registry_entry(f2);
+        entry.paths[0].path = "/tmp/B".into();
+        store.upsert_registry_entry(entry).unwrap();
+        store.bind_path(f1, "/tmp/c".into(), true, None).unwrap();
+        store.bind_path(f2, "/tmp/d".into(), false, None).unwrap();
+        store.bind_path(f2, "/tmp/e".into(), true, None).unwrap();
+
+        let json = serde_json::to_string(&store).unwrap();
+        let restored: LocalMetadataStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.verify_path_index(), Ok(()));
+        for (path, file_id) in [
+            ("/tmp/a", f1),
+            ("/tmp/b", f2),
+            ("/tmp/c", f1),
+            ("/tmp/d", f2),
+            ("/tmp/e", f2),
+        ] {
+            assert_eq!(restored.file_id_for_path(path), Some(file_id));
+        }
+        let mut restored = restored;
+        assert_eq!(
+            restored.bind_path(f2, "/tmp/c".into(), true, None),
+            Err(LocalMetadataError::PathAlreadyBound(f1))
+        );
+    }
+
+    #[test]
+    fn batch_upsert_stops_or_skips_invalid_records() {
+        let invalid = || {
+            let mut record = sample_file_record();
+            record.head_version_id = ulid();
+            record
+        };
+        let batch = || {
+            let records = vec![sample_file_record(), sample_file_record(), invalid()];
+            let bad_id = records[2].file_id;
+            (records, bad_id)
+        };
+
+        let mut store = LocalMetadataStore::new();
+        let (mut records, bad_id) = batch();
+        records.push(sample_file_record());
+        let (err, inserted) = store.upsert_file_records(records).unwrap_err();
+        assert!(matches!(
+            err,
+            LocalMetadataError::Model(ModelError::MissingHead(_))
+        ));
+        assert_eq!(inserted, 2);
+        assert_eq!(store.files().count(), 2);
+        assert!(store.file_record(&bad_id).is_none());
+
+        let mut store = LocalMetadataStore::new();
+        let (mut records, bad_id) = batch();
+        records.push(sample_file_record());
+        let (inserted, skipped) = store.upsert_file_records_lenient(records);
+        assert_eq!(inserted, 3);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, bad_id);
+        assert_eq!(store.files().count(), 3);
+
+        let mut store = LocalMetadataStore::new();
+        let valid = vec![sample_file_record(), sample_file_record()];
+        assert_eq!(store.upsert_file_records(valid), Ok(2));
+    }
+
+    #[test]
+    fn queries_files_from_a_device_perspective() {
+        let mut store = LocalMetadataStore::new();
+        let device_a = ulid();
+        let mut known = Vec::new();
+        for i in 0..5 {
+            let mut record = sample_file_record();
+            if i < 2 {
+                let mut state = record.device_states[0].clone();
+                state.device_id = device_a;
+                if i == 1 {
+                    state.state = DeviceFileStateKind::Pulling;
+                }
+                record.device_states.push(state);
+                known.push(record.file_id);
+            }
+            store.upsert_file_record(record).unwrap();
         }
+
+        let mut seen: Vec<FileId> = store
+            .file_records_for_device(device_a)
+            .map(|f| f.file_id)
+            .collect();
+        seen.sort();
+        assert_eq!(store.files_synced_by_device(device_a), vec![known[0]]);
+        known.sort();
+        assert_eq!(seen, known);
+        assert!(store.files_synced_by_device(ulid()).is_empty());
     }
 
     #[test]
-    fn upsert_and_bind_paths_without_changing_identity() {
+    fn tracks_and_resolves_conflict_records() {
         let mut store = LocalMetadataStore::new();
-        let record = sample_file_record();
+        let mut record = sample_file_record();
         let file_id = record.file_id;
+        let local_device = record.device_states[0].device_id;
+        let local_version_id = record.head_version_id;
+        let mut remote_version = record.versions[0].clone();
+        remote_version.version_id = ulid();
+        let remote_version_id = remote_version.version_id;
+        record.versions.push(remote_version);
         store.upsert_file_record(record).unwrap();
-        store
-            .upsert_registry_entry(sample_registry_entry(file_id))
-            .unwrap();
+
+        let remote_device = ulid();
+        let conflict = ConflictRecord {
+            file_id,
+            local_version_id,
+            remote_version_id,
+            local_device_id: local_device,
+            remote_device_id: remote_device,
+            detected_at: Utc::now(),
+        };
+        let missing = ulid();
+        let err = store
+            .apply_conflict_record(ConflictRecord {
+                remote_version_id: missing,
+                ..conflict.clone()
+            })
+            .unwrap_err();
+        assert_eq!(
+            err,
+            LocalMetadataError::VersionNotFound {
+                file_id,
+                version_id: missing,
+            }
+        );
+        assert!(store.pending_conflicts_for_device(local_device).is_empty());
+
+        store.apply_conflict_record(conflict.clone()).unwrap();
+        store.apply_conflict_record(conflict.clone()).unwrap();
+        assert_eq!(
+            store.pending_conflicts_for_device(remote_device),
+            vec![&conflict]
+        );
+        assert!(store.pending_conflicts_for_device(ulid()).is_empty());
+        let applied_at = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(2));
 
         store
-            .bind_path(file_id, "/tmp/renamed".into(), true)
+            .resolve_conflict(
+                file_id,
+                ConflictResolutionStrategy::KeepCurrent,
+                local_device,
+            )
             .unwrap();
-        let entry = store.registry_entry(&file_id).unwrap();
-        assert!(entry.paths.iter().any(|p| p.path == "/tmp/renamed"));
+        assert!(store.pending_conflicts_for_device(local_device).is_empty());
+        assert_eq!(
+            store.file_record(&file_id).unwrap().head_version_id,
+            local_version_id
+        );
+
+        // Conflict changes are logged, so snapshots see them come and go.
+        assert_eq!(
+            store
+                .snapshot_at(applied_at)
+                .unwrap()
+                .pending_conflicts_for_device(remote_device),
+            vec![&conflict]
+        );
+        assert!(store
+            .snapshot_at(Utc::now())
+            .unwrap()
+            .pending_conflicts_for_device(remote_device)
+            .is_empty());
     }
 
     #[test]
-    fn prevents_path_alias_across_files() {
+    fn removing_a_file_drops_its_conflicts() {
         let mut store = LocalMetadataStore::new();
-        let r1 = sample_file_record();
-        let r2 = sample_file_record();
-        let f1 = r1.file_id;
-        let f2 = r2.file_id;
-        store.upsert_file_record(r1).unwrap();
-        store.upsert_file_record(r2).unwrap();
-        store
-            .upsert_registry_entry(sample_registry_entry(f1))
-            .unwrap();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        let mut remote_version = record.versions[0].clone();
+        remote_version.version_id = ulid();
+        let conflict = ConflictRecord {
+            file_id,
+            local_version_id: record.head_version_id,
+            remote_version_id: remote_version.version_id,
+            local_device_id: record.device_states[0].device_id,
+            remote_device_id: ulid(),
+            detected_at: Utc::now(),
+        };
+        record.versions.push(remote_version);
+        store.upsert_file_record(record).unwrap();
+        store.apply_conflict_record(conflict.clone()).unwrap();
+
+        assert_eq!(store.retain_files(|f, _| f.file_id != file_id), 1);
+        assert!(store
+            .pending_conflicts_for_device(conflict.remote_device_id)
+            .is_empty());
+        assert!(store
+            .snapshot_at(Utc::now())
+            .unwrap()
+            .pending_conflicts_for_device(conflict.remote_device_id)
+            .is_empty());
+    }
+
+    #[test]
+    fn gc_drops_unreferenced_versions() {
+        let mut store = LocalMetadataStore::new();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        let head = record.head_version_id;
+        let [known, orphan] = [ulid(), ulid()].map(|id| {
+            let mut version = record.versions[0].clone();
+            version.version_id = id;
+            record.versions.push(version);
+            id
+        });
+        record.device_states[0].known_head_version_id = Some(known);
+        store.upsert_file_record(record).unwrap();
+        let untouched = sample_file_record();
+        store.upsert_file_record(untouched.clone()).unwrap();
+
+        let removed = store.gc_orphaned_versions();
+        assert_eq!(removed, HashMap::from([(file_id, vec![orphan])]));
+        let kept: Vec<VersionId> = store
+            .file_record(&file_id)
+            .unwrap()
+            .versions
+            .iter()
+            .map(|v| v.version_id)
+            .collect();
+        assert_eq!(kept, vec![head, known]);
+        assert_eq!(store.file_record(&untouched.file_id), Some(&untouched));
+        assert!(store.gc_orphaned_versions().is_empty());
+    }
+
+    #[test]
+    fn gc_keeps_pinned_versions() {
+        let mut store = LocalMetadataStore::new();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        let head = record.head_version_id;
+        let base = record.versions[0].timestamp;
+        let [oldest, newer, newest] = [1, 2, 3].map(|secs| {
+            let mut version = record.versions[0].clone();
+            version.version_id = ulid();
+            version.timestamp = base + chrono::Duration::seconds(secs);
+            record.versions.push(version.clone());
+            version.version_id
+        });
+        store.upsert_file_record(record).unwrap();
+        let mut entry = sample_registry_entry(file_id);
+        entry.pin = PinPreference::KeepN(2);
+        store.upsert_registry_entry(entry).unwrap();
+
+        let removed = store.gc_orphaned_versions();
+        assert_eq!(removed, HashMap::from([(file_id, vec![oldest])]));
+        let kept: Vec<VersionId> = store
+            .file_record(&file_id)
+            .unwrap()
+            .versions
+            .iter()
+            .map(|v| v.version_id)
+            .collect();
+        assert_eq!(kept, vec![head, newer, newest]);
+    }
+
+    #[test]
+    fn bulk_hydration_reports_missing_entries() {
+        let mut store = LocalMetadataStore::new();
+        let (a, b, missing) = (ulid(), ulid(), ulid());
         store
-            .upsert_registry_entry(sample_registry_entry(f2))
+            .upsert_registry_entry(sample_registry_entry(a))
             .unwrap();
+        let mut entry_b = sample_registry_entry(b);
+        entry_b.paths[0].path = "/tmp/b".into();
+        store.upsert_registry_entry(entry_b).unwrap();
+
+        assert_eq!(
+            store.set_bulk_hydration(
+                &[a, b],
+                Hydration::Partial {
+                    bytes_present: 0,
+                    total_bytes: 10,
+                }
+            ),
+            Ok(2)
+        );
 
         let err = store
-            .bind_path(f2, "/tmp/a".into(), true)
-            .expect_err("should reject alias");
-        assert!(matches!(err, LocalMetadataError::PathAlreadyBound(id) if id == f1));
+            .set_bulk_hydration(&[a, missing, b], Hydration::None)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            LocalMetadataError::BulkErrors(vec![LocalMetadataError::NotFound(missing)])
+        );
+        for id in [a, b] {
+            let entry = store.registry_entry(&id).unwrap();
+            assert_eq!(entry.hydration, Hydration::None);
+            assert_eq!(entry.local_size_bytes, None);
+        }
     }
 
     #[test]
-    fn updates_device_state_and_keeps_invariants() {
+    fn touch_refreshes_only_last_seen() {
         let mut store = LocalMetadataStore::new();
-        let record = sample_file_record();
+        let mut record = sample_file_record();
         let file_id = record.file_id;
         let device_id = record.device_states[0].device_id;
-        store.upsert_file_record(record.clone()).unwrap();
+        record.device_states[0].state = DeviceFileStateKind::Pulling;
+        record.device_states[0].last_error = Some("slow link".into());
+        let before = record.device_states[0].clone();
+        store.upsert_file_record(record).unwrap();
 
+        let at = before.last_seen_at + Duration::minutes(5);
         store
-            .upsert_device_state(
-                file_id,
-                DeviceFileState {
-                    device_id,
-                    state: DeviceFileStateKind::Pushing,
-                    known_head_version_id: record.device_states[0].known_head_version_id,
-                    last_seen_at: Utc::now() + Duration::seconds(1),
-                    last_error: None,
-                },
-            )
+            .touch_device_state_for_file(file_id, device_id, at)
             .unwrap();
+        let after = &store.file_record(&file_id).unwrap().device_states[0];
+        assert_eq!(after.last_seen_at, at);
+        assert_eq!(
+            after,
+            &DeviceFileState {
+                last_seen_at: at,
+                ..before
+            }
+        );
 
-        let updated = store.file_record(&file_id).unwrap();
+        let stranger = ulid();
         assert_eq!(
-            updated
-                .device_states
-                .iter()
-                .find(|d| d.device_id == device_id)
-                .unwrap()
-                .state,
-            DeviceFileStateKind::Pushing
+            store.touch_device_state_for_file(file_id, stranger, at),
+            Err(LocalMetadataError::DeviceStateNotFound {
+                file_id,
+                device_id: stranger,
+            })
         );
     }
 
     #[test]
-    fn sets_and_clears_lock() {
+    fn detects_pending_pushes_and_active_transfers() {
         let mut store = LocalMetadataStore::new();
-        let record = sample_file_record();
+        let mut record = sample_file_record();
         let file_id = record.file_id;
+        let head = record.head_version_id;
+        let local = record.device_states[0].device_id;
+        let mut peer = record.device_states[0].clone();
+        peer.device_id = ulid();
+        peer.known_head_version_id = None;
+        peer.state = DeviceFileStateKind::Pulling;
+        record.device_states.push(peer.clone());
         store.upsert_file_record(record).unwrap();
+        let mut entry = sample_registry_entry(file_id);
+        entry.local_version_id = Some(head);
+        store.upsert_registry_entry(entry).unwrap();
+
+        // Local has the head on disk and the peer is behind.
+        let pending: Vec<FileId> = store
+            .files_pending_push(local)
+            .iter()
+            .map(|f| f.file_id)
+            .collect();
+        assert_eq!(pending, vec![file_id]);
+        assert_eq!(
+            store.transfer_sessions_in_progress(),
+            vec![(file_id, peer.device_id, TransferDirection::Pull)]
+        );
 
+        // Once the peer catches up, nothing is pending until local starts pushing again.
+        let mut caught_up = peer.clone();
+        caught_up.known_head_version_id = Some(head);
+        caught_up.state = DeviceFileStateKind::Ready;
         store
-            .set_lock(
-                file_id,
-                Some(LockRecord {
-                    lock_id: ulid(),
-                    file_id,
-                    owner_device_id: ulid(),
-                    owner_user_id: "user".into(),
-                    mode: LockMode::Exclusive,
-                    acquired_at: Utc::now(),
-                    auto_lock: true,
-                    expires_at: None,
-                }),
-            )
+            .upsert_device_state(file_id, caught_up, DeviceStatePolicy::Permissive)
             .unwrap();
+        assert!(store.files_pending_push(local).is_empty());
+        assert!(store.transfer_sessions_in_progress().is_empty());
 
-        assert!(store.file_record(&file_id).unwrap().lock.is_some());
-        store.set_lock(file_id, None).unwrap();
-        assert!(store.file_record(&file_id).unwrap().lock.is_none());
+        let mut pushing = store.file_record(&file_id).unwrap().device_states[0].clone();
+        pushing.state = DeviceFileStateKind::Pushing;
+        store
+            .upsert_device_state(file_id, pushing, DeviceStatePolicy::Permissive)
+            .unwrap();
+        assert_eq!(store.files_pending_push(local).len(), 1);
+        assert_eq!(
+            store.transfer_sessions_in_progress(),
+            vec![(file_id, local, TransferDirection::Push)]
+        );
+
+        // Nothing leaves the device without consent, or while sync is paused.
+        let mut entry = store.registry_entry(&file_id).unwrap().clone();
+        entry.consent = Consent::Revoked;
+        store.upsert_registry_entry(entry.clone()).unwrap();
+        assert!(store.files_pending_push(local).is_empty());
+        entry.consent = Consent::Approved;
+        entry.sync_paused = true;
+        store.upsert_registry_entry(entry).unwrap();
+        assert!(store.files_pending_push(local).is_empty());
     }
 
     #[test]
-    fn append_version_updates_head_and_registry() {
+    fn files_for_key_id_uses_key_index() {
+        let mut store = LocalMetadataStore::new();
+        let mut with_key = |key_id: &str| {
+            let mut record = sample_file_record();
+            record.encryption = EncryptionInfo::aes_256_gcm(key_id.into(), None);
+            let file_id = record.file_id;
+            store.upsert_file_record(record).unwrap();
+            file_id
+        };
+        let a = with_key("k1");
+        let b = with_key("k2");
+        let c = with_key("k1");
+
+        let ids = |store: &LocalMetadataStore, key_id: &str| {
+            let mut ids: Vec<FileId> = store.files_for_key_id(key_id).map(|f| f.file_id).collect();
+            ids.sort();
+            ids
+        };
+        let mut expected = vec![a, c];
+        expected.sort();
+        assert_eq!(ids(&store, "k1"), expected);
+        assert_eq!(ids(&store, "k2"), vec![b]);
+        assert!(ids(&store, "k3").is_empty());
+
+        // Re-keying a file moves it between index entries.
+        let mut rekeyed = store.file_record(&a).unwrap().clone();
+        rekeyed.encryption = EncryptionInfo::aes_256_gcm("k2".into(), None);
+        store.upsert_file_record(rekeyed).unwrap();
+        assert_eq!(ids(&store, "k1"), vec![c]);
+        assert_eq!(ids(&store, "k2").len(), 2);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let restored: LocalMetadataStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(ids(&restored, "k2"), ids(&store, "k2"));
+    }
+
+    #[test]
+    fn version_lookups() {
         let mut store = LocalMetadataStore::new();
         let record = sample_file_record();
         let file_id = record.file_id;
+        let head = record.head_version_id;
+        let missing = ulid();
+        assert!(store.versions_for_file(&file_id).is_none());
+        assert!(store.version_by_id(&file_id, &head).is_none());
+        assert!(store.head_version_record(&file_id).is_none());
+
+        store.upsert_file_record(record.clone()).unwrap();
+        assert_eq!(
+            store.versions_for_file(&file_id),
+            Some(&record.versions[..])
+        );
+        assert_eq!(
+            store.version_by_id(&file_id, &head),
+            Some(&record.versions[0])
+        );
+        assert!(store.version_by_id(&file_id, &missing).is_none());
+        assert_eq!(
+            store.head_version_record(&file_id).map(|v| v.version_id),
+            Some(head)
+        );
+        assert!(store.head_version_record(&missing).is_none());
+    }
+
+    #[test]
+    fn retention_keeps_lagging_device_known_head() {
+        let mut store = LocalMetadataStore::new();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        let [older, oldest] = [1, 2].map(|days| {
+            let mut version = record.versions[0].clone();
+            version.version_id = ulid();
+            version.timestamp = Utc::now() - Duration::days(days);
+            record.versions.push(version.clone());
+            version.version_id
+        });
+        let mut lagging = record.device_states[0].clone();
+        lagging.device_id = ulid();
+        lagging.known_head_version_id = Some(oldest);
+        record.device_states.push(lagging.clone());
         store.upsert_file_record(record).unwrap();
+
+        let keep_head = VersionRetention {
+            max_versions: 1,
+            max_age: None,
+            per_device_max_age: HashMap::new(),
+        };
+        let pruned = store
+            .apply_retention_policy(&keep_head, file_id, SystemTime::now())
+            .unwrap();
+        assert_eq!(pruned, vec![older]);
+        assert!(store.version_by_id(&file_id, &oldest).is_some());
+
+        // Strict mutations on the record keep working.
+        lagging.state = DeviceFileStateKind::Pulling;
         store
-            .upsert_registry_entry(sample_registry_entry(file_id))
+            .upsert_device_state(file_id, lagging, DeviceStatePolicy::Strict)
             .unwrap();
+        let copy = store.file_record(&file_id).unwrap().clone();
+        store.upsert_file_record(copy).unwrap();
+    }
+
+    #[test]
+    fn retention_clears_pruned_local_version() {
+        let mut store = LocalMetadataStore::new();
+        let mut record = sample_file_record();
+        let file_id = record.file_id;
+        let head = record.head_version_id;
+        let [older, oldest] = [1, 2].map(|days| {
+            let mut version = record.versions[0].clone();
+            version.version_id = ulid();
+            version.timestamp = Utc::now() - Duration::days(days);
+            record.versions.push(version.clone());
+            version.version_id
+        });
+        store.upsert_file_record(record).unwrap();
+        let mut entry = sample_registry_entry(file_id);
+        entry.local_version_id = Some(older);
+        store.upsert_registry_entry(entry).unwrap();
+
+        let keep_two = VersionRetention {
+            max_versions: 2,
+            max_age: None,
+            per_device_max_age: HashMap::new(),
+        };
+        let pruned = store
+            .apply_retention_policy(&keep_two, file_id, SystemTime::now())
+            .unwrap();
+        assert_eq!(pruned, vec![oldest]);
+        assert_eq!(
+            store.registry_entry(&file_id).unwrap().local_version_id,
+            Some(older)
+        );
+
+        let keep_head = VersionRetention {
+            max_versions: 1,
+            ..keep_two
+        };
+        let pruned = store
+            .apply_retention_policy(&keep_head, file_id, SystemTime::now())
+            .unwrap();
+        assert_eq!(pruned, vec![older]);
+        assert_eq!(store.versions_for_file(&file_id).unwrap().len(), 1);
+        assert_eq!(
+            store.head_version_record(&file_id).unwrap().version_id,
+            head
+        );
+        assert_eq!(
+            store.registry_entry(&file_id).unwrap().local_version_id,
+            None
+        );
+
+        let missing = ulid();
+        assert_eq!(
+            store.apply_retention_policy(&keep_head, missing, SystemTime::now()),
+            Err(LocalMetadataError::NotFound(missing))
+        );
+    }
+
+    #[test]
+    fn tracks_registered_sessions() {
+        let mut store = LocalMetadataStore::new();
+        let (file_a, file_b) = (ulid(), ulid());
+        let start = SystemTime::now();
+        let session = |file_id: FileId, offset_secs: u64| TransferSession {
+            transfer_session_id: ulid(),
+            file_id,
+            direction: TransferDirection::Push,
+            from_device_id: ulid(),
+            to_device_id: ulid(),
+            active_chunks: Vec::new(),
+            retry_count: 0,
+            status: TransferStatus::InProgress,
+            started_at: start + std::time::Duration::from_secs(offset_secs),
+        };
+        let first = session(file_a, 0);
+        let second = session(file_b, 1);
+        let third = session(file_a, 2);
+        for s in [&third, &first, &second] {
+            store.upsert_session(s.clone());
+        }
+        let ids = |sessions: Vec<&TransferSession>| {
+            sessions
+                .into_iter()
+                .map(|s| s.transfer_session_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(store.active_sessions()),
+            vec![
+                first.transfer_session_id,
+                second.transfer_session_id,
+                third.transfer_session_id
+            ]
+        );
 
-        let new_version_id = ulid();
         store
-            .append_version(
-                file_id,
-                new_version_id,
-                VersionRecord {
-                    version_id: new_version_id,
-                    file_id,
-                    parent_version_id: None,
-                    origin_device_id: ulid(),
-                    timestamp: Utc::now(),
-                    content_hash: "hash2".into(),
-                    size_bytes: 20,
-                    chunks: vec![ChunkRef {
-                        offset: 0,
-                        length: 20,
-                        hash: "hash2".into(),
-                    }],
+            .complete_session(first.transfer_session_id, TransferStatus::Completed)
+            .unwrap();
+        store
+            .complete_session(
+                second.transfer_session_id,
+                TransferStatus::Paused {
+                    reason: "metered".into(),
                 },
             )
             .unwrap();
+        assert_eq!(
+            ids(store.active_sessions()),
+            vec![second.transfer_session_id, third.transfer_session_id]
+        );
+        let for_a = store.sessions_for_file(file_a);
+        assert_eq!(for_a.len(), 2);
+        assert_eq!(for_a[0].status, TransferStatus::Completed);
+        assert_eq!(for_a[1].transfer_session_id, third.transfer_session_id);
+        assert!(store.sessions_for_file(ulid()).is_empty());
 
-        let updated = store.file_record(&file_id).unwrap();
-        assert_eq!(updated.head_version_id, new_version_id);
-        assert_eq!(updated.versions.len(), 2);
+        let unknown = ulid();
         assert_eq!(
-            store.registry_entry(&file_id).unwrap().local_version_id,
-            Some(new_version_id)
+            store.complete_session(unknown, TransferStatus::Completed),
+            Err(LocalMetadataError::SessionNotFound(unknown))
         );
     }
 
     #[test]
-    fn set_local_preferences_updates_flags() {
+    fn paused_files_are_not_scheduled() {
         let mut store = LocalMetadataStore::new();
         let record = sample_file_record();
         let file_id = record.file_id;
@@ -433,19 +3892,137 @@ mod tests {
         store
             .upsert_registry_entry(sample_registry_entry(file_id))
             .unwrap();
+        let device = ulid();
+        assert_eq!(store.files_needing_sync(device), vec![(file_id, 128)]);
+
+        store.set_sync_paused(file_id, true).unwrap();
+        assert!(store.files_needing_sync(device).is_empty());
+        assert_eq!(store.paused_files(), vec![file_id]);
+
+        store.set_sync_paused(file_id, false).unwrap();
+        assert_eq!(store.files_needing_sync(device), vec![(file_id, 128)]);
+        assert!(store.paused_files().is_empty());
 
+        let missing = ulid();
+        assert_eq!(
+            store.set_sync_paused(missing, true),
+            Err(LocalMetadataError::NotFound(missing))
+        );
+    }
+
+    #[test]
+    fn bind_path_rejects_invalid_paths() {
+        let mut store = LocalMetadataStore::new();
+        let file_id = ulid();
         store
-            .set_local_preferences(
-                file_id,
-                Some(Hydration::None),
-                Some(Consent::Revoked),
-                Some(AutoLockPreference::Manual),
-            )
+            .upsert_registry_entry(sample_registry_entry(file_id))
             .unwrap();
+        assert_eq!(
+            store.bind_path(file_id, "relative".into(), true, None),
+            Err(LocalMetadataError::Model(ModelError::InvalidPath(
+                "relative".into()
+            )))
+        );
+        assert!(store.rename_path(file_id, "/tmp/a", String::new()).is_err());
+        assert_eq!(store.registry_entry(&file_id).unwrap().paths.len(), 1);
+    }
 
-        let entry = store.registry_entry(&file_id).unwrap();
-        assert!(matches!(entry.hydration, Hydration::None));
-        assert!(matches!(entry.consent, Consent::Revoked));
-        assert!(matches!(entry.auto_lock_preference, AutoLockPreference::Manual));
+    #[test]
+    fn reports_path_counts() {
+        let mut store = LocalMetadataStore::new();
+        assert_eq!(store.max_path_count(), 0);
+        let (busy, single) = (ulid(), ulid());
+        store
+            .upsert_registry_entry(sample_registry_entry(busy))
+            .unwrap();
+        for i in 1..5 {
+            store
+                .bind_path(busy, format!("/tmp/busy-{i}"), true, None)
+                .unwrap();
+        }
+        let mut entry = sample_registry_entry(single);
+        entry.paths[0].path = "/tmp/single".into();
+        store.upsert_registry_entry(entry).unwrap();
+
+        assert_eq!(store.paths_per_file_report(), vec![(busy, 5), (single, 1)]);
+        assert_eq!(store.files_with_multiple_paths(), vec![busy]);
+        assert_eq!(store.max_path_count(), 5);
+    }
+
+    #[test]
+    fn files_accessible_by_trusted_keys() {
+        let mut store = LocalMetadataStore::new();
+        let mut ids = Vec::new();
+        for key_id in ["k1", "k2", "k3"] {
+            let mut record = sample_file_record();
+            record.encryption = EncryptionInfo::aes_256_gcm(key_id.into(), None);
+            ids.push(record.file_id);
+            store.upsert_file_record(record).unwrap();
+        }
+        let device = ulid();
+        let identities = [DeviceIdentity {
+            device_id: device,
+            user_id: ulid(),
+            device_public_key: vec![1; 32],
+            attested_at: SystemTime::now(),
+            trusted_key_ids: vec!["k1".into(), "k3".into()],
+        }];
+        assert!(is_trusted_for_file(
+            &identities[0],
+            store.file_record(&ids[0]).unwrap()
+        ));
+        assert!(!is_trusted_for_file(
+            &identities[0],
+            store.file_record(&ids[1]).unwrap()
+        ));
+
+        let mut expected = vec![ids[0], ids[2]];
+        expected.sort();
+        assert_eq!(
+            store.files_accessible_to_device(device, &identities),
+            expected
+        );
+        assert!(store
+            .files_accessible_to_device(ulid(), &identities)
+            .is_empty());
+    }
+
+    #[test]
+    fn upserts_sessions_and_filters_by_status() {
+        let mut store = LocalMetadataStore::new();
+        let session = TransferSession {
+            transfer_session_id: ulid(),
+            file_id: ulid(),
+            direction: TransferDirection::Pull,
+            from_device_id: ulid(),
+            to_device_id: ulid(),
+            active_chunks: Vec::new(),
+            retry_count: 0,
+            status: TransferStatus::InProgress,
+            started_at: SystemTime::now(),
+        };
+        let id = session.transfer_session_id;
+        store.upsert_session(session.clone());
+        let retried = TransferSession {
+            retry_count: 1,
+            status: TransferStatus::Failed("timeout".into()),
+            ..session.clone()
+        };
+        store.upsert_session(retried.clone());
+        store.upsert_session(retried.clone());
+        assert_eq!(store.session_by_id(id), Some(&retried));
+        assert!(store.session_by_id(ulid()).is_none());
+
+        let other = TransferSession {
+            transfer_session_id: ulid(),
+            status: TransferStatus::Failed("disk full".into()),
+            ..session
+        };
+        store.upsert_session(other);
+        let failed = store.sessions_by_status(TransferStatus::Failed(String::new()));
+        assert_eq!(failed.len(), 2);
+        assert!(store
+            .sessions_by_status(TransferStatus::InProgress)
+            .is_empty());
     }
 }
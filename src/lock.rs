@@ -1,10 +1,14 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use ulid::Ulid;
 
-use crate::{DeviceFileStateKind, DeviceId, FileRecord, LockMode, LockRecord, VersionId};
+use crate::{
+    assert_file_invariants, verify_merkle_root, DeviceFileStateKind, DeviceId, FileRecord,
+    LockMode, LockRecord, ModelError, RevocationList, SessionId, VersionId, VersionRecord,
+    MAX_LOCK_HISTORY,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LockRequestKind {
@@ -23,6 +27,16 @@ pub enum LockAcquisition {
 pub struct LockDenial {
     pub holder_device: DeviceId,
     pub acquired_at: DateTime<Utc>,
+    /// When the blocking lock expires; `None` if it is held until released.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Time left until the blocking lock expires, for "locked for another 5 minutes" messages.
+/// Zero if it has already expired; `None` if the lock never expires.
+pub fn estimated_wait(denial: &LockDenial, now: DateTime<Utc>) -> Option<Duration> {
+    denial
+        .expires_at
+        .map(|expires_at| (expires_at - now).max(Duration::zero()))
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -31,61 +45,177 @@ pub enum LockError {
     MissingFile,
     #[error("lock mismatch: existing lock for a different file")]
     LockMismatch,
+    #[error("lock duration out of range")]
+    DurationOutOfRange,
+    #[error("file is locked by device {0}")]
+    LockedBy(DeviceId),
+    #[error("device {0} has no known head to keep")]
+    NoIncomingHead(DeviceId),
+    #[error("device {0} has not been seen recently")]
+    DeviceOffline(DeviceId),
+    #[error(transparent)]
+    Model(#[from] ModelError),
 }
 
-/// Attempt to acquire an exclusive lock for a device. If a lock exists, it is respected.
-pub fn acquire_lock(
-    file: &FileRecord,
-    device_id: DeviceId,
-    user_id: String,
-    _request: LockRequestKind,
-    auto_lock: bool,
-) -> Result<LockAcquisition, LockError> {
-    if let Some(lock) = &file.lock {
+/// A device's request for a lock; see `acquire_lock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockRequest {
+    pub device_id: DeviceId,
+    pub user_id: String,
+    /// `Auto` requests produce locks with `auto_lock` set.
+    pub kind: LockRequestKind,
+    pub mode: LockMode,
+    /// Session the lock is taken in, so revoking the session expires the lock.
+    pub session_id: Option<SessionId>,
+    /// Refuse the lock with `DeviceOffline` if the device's state on the file was last seen
+    /// longer ago than this. Devices without a state on the file are not checked.
+    pub max_device_age: Option<Duration>,
+}
+
+impl LockRequest {
+    /// An `Exclusive` request with no session and no device-age check.
+    pub fn new(device_id: DeviceId, user_id: String, kind: LockRequestKind) -> Self {
+        Self {
+            device_id,
+            user_id,
+            kind,
+            mode: LockMode::Exclusive,
+            session_id: None,
+            max_device_age: None,
+        }
+    }
+}
+
+/// Attempt to acquire an exclusive lock for a device. If an unexpired lock exists, it is
+/// respected; an expired one is treated as absent. `TimedExclusive` locks get `expires_at` set
+/// from their duration.
+pub fn acquire_lock(file: &FileRecord, request: LockRequest) -> Result<LockAcquisition, LockError> {
+    let LockRequest {
+        device_id,
+        user_id,
+        kind,
+        mode,
+        session_id,
+        max_device_age,
+    } = request;
+    let now = Utc::now();
+    if let Some(max_age) = max_device_age {
+        let stale = file
+            .device_states
+            .iter()
+            .any(|d| d.device_id == device_id && now - d.last_seen_at > max_age);
+        if stale {
+            return Err(LockError::DeviceOffline(device_id));
+        }
+    }
+    if let Some(lock) = file.lock.as_ref().filter(|lock| !lock.is_expired(now)) {
         if lock.file_id != file.file_id {
             return Err(LockError::LockMismatch);
         }
         return Ok(LockAcquisition::Denied(LockDenial {
             holder_device: lock.owner_device_id,
             acquired_at: lock.acquired_at,
+            expires_at: lock.expires_at,
         }));
     }
 
+    let expires_at = match &mode {
+        LockMode::Exclusive => None,
+        LockMode::TimedExclusive { duration } => {
+            Some(now + Duration::from_std(*duration).map_err(|_| LockError::DurationOutOfRange)?)
+        }
+    };
     let record = LockRecord {
         lock_id: Ulid::new(),
         file_id: file.file_id,
         owner_device_id: device_id,
         owner_user_id: user_id,
-        mode: LockMode::Exclusive,
-        acquired_at: Utc::now(),
-        auto_lock,
-        expires_at: None,
+        mode,
+        acquired_at: now,
+        auto_lock: kind == LockRequestKind::Auto,
+        expires_at,
+        session_id,
     };
 
     Ok(LockAcquisition::Acquired(record))
 }
 
-/// Release a lock if held by the device; otherwise no-op.
-pub fn release_lock(file: &mut FileRecord, device_id: DeviceId) -> Result<(), LockError> {
+/// True if the lock was taken in a session that has since been revoked. Locks without a
+/// session never expire this way.
+pub fn lock_session_expired(lock: &LockRecord, revocation: &RevocationList) -> bool {
+    lock.session_id
+        .is_some_and(|session_id| revocation.is_revoked(session_id))
+}
+
+/// Release a lock if held by the device; otherwise no-op. The released lock is kept in
+/// `lock_history`, which holds at most `history_cap` entries (see `push_lock_history`).
+pub fn release_lock(
+    file: &mut FileRecord,
+    device_id: DeviceId,
+    history_cap: usize,
+) -> Result<(), LockError> {
     if let Some(lock) = &file.lock {
         if lock.file_id != file.file_id {
             return Err(LockError::LockMismatch);
         }
-        if lock.owner_device_id == device_id {
-            file.lock = None;
+        if lock.is_held_by(device_id) {
+            force_release_lock(file, history_cap);
         }
     }
     Ok(())
 }
 
+/// Clear the lock regardless of holder (admin override or expiry), keeping it in
+/// `lock_history` capped at `history_cap`. Returns the released lock, if any.
+pub fn force_release_lock(file: &mut FileRecord, history_cap: usize) -> Option<LockRecord> {
+    let lock = file.lock.take()?;
+    push_lock_history(file, lock.clone(), history_cap);
+    Some(lock)
+}
+
+/// Release the lock if it has expired as of `now`, keeping it in `lock_history` capped at
+/// `history_cap`. Returns the released lock; `None` if there was no lock or it is still live.
+pub fn auto_release_if_expired(
+    file: &mut FileRecord,
+    now: DateTime<Utc>,
+    history_cap: usize,
+) -> Option<LockRecord> {
+    if file.lock.as_ref().is_some_and(|lock| lock.is_expired(now)) {
+        force_release_lock(file, history_cap)
+    } else {
+        None
+    }
+}
+
+/// Append a released lock to history, dropping the oldest entries beyond `cap` (itself capped
+/// at `MAX_LOCK_HISTORY`).
+pub fn push_lock_history(file: &mut FileRecord, lock: LockRecord, cap: usize) {
+    file.lock_history.push(lock);
+    let cap = cap.min(MAX_LOCK_HISTORY);
+    if file.lock_history.len() > cap {
+        let excess = file.lock_history.len() - cap;
+        file.lock_history.drain(..excess);
+    }
+}
+
+/// The `n` most recently acquired locks from history, newest first.
+pub fn recent_lock_holders(file: &FileRecord, n: usize) -> Vec<&LockRecord> {
+    let mut locks: Vec<&LockRecord> = file.lock_history.iter().collect();
+    locks.sort_by_key(|l| std::cmp::Reverse(l.acquired_at));
+    locks.truncate(n);
+    locks
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConflictCheck {
     Allowed,
     Conflict { current_head: VersionId, base_head: VersionId },
     LockedBy(DeviceId),
+    Quarantined { reason: String },
 }
 
 /// Simple conflict rule:
+/// - If caller's device state is quarantined -> Quarantined (checked before locks).
 /// - If lock is held by caller -> allowed.
 /// - If lock held by other -> LockedBy.
 /// - If no lock: require pushes to base on current head; else Conflict.
@@ -94,8 +224,19 @@ pub fn check_conflict(
     caller_device: DeviceId,
     caller_base_head: VersionId,
 ) -> ConflictCheck {
+    if let Some(DeviceFileStateKind::Quarantined { reason }) = file
+        .device_states
+        .iter()
+        .find(|s| s.device_id == caller_device)
+        .map(|s| &s.state)
+    {
+        return ConflictCheck::Quarantined {
+            reason: reason.clone(),
+        };
+    }
+
     if let Some(lock) = &file.lock {
-        if lock.owner_device_id == caller_device {
+        if lock.is_held_by(caller_device) {
             return ConflictCheck::Allowed;
         } else {
             return ConflictCheck::LockedBy(lock.owner_device_id);
@@ -112,21 +253,86 @@ pub fn check_conflict(
     }
 }
 
-/// Update per-device state to reflect lock blocked status.
-pub fn mark_lock_blocked(file: &mut FileRecord, device_id: DeviceId) {
-    if let Some(state) = file
+/// How a caller wants a `ConflictCheck::Conflict` settled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolutionStrategy {
+    /// Leave the shared head as is; the caller rebases or discards its edit.
+    KeepCurrent,
+    /// Adopt the caller device's known head as the shared head.
+    KeepIncoming,
+    /// Append a merge version and make it the head.
+    Merge(Box<VersionRecord>),
+}
+
+/// Apply a conflict resolution on behalf of `caller_device`.
+///
+/// Fails if another device holds the lock, if a merge version's Merkle root does not match
+/// its chunks, or if the result would break file invariants; the record is left unchanged on
+/// error.
+pub fn resolve_conflict(
+    file: &mut FileRecord,
+    strategy: ConflictResolutionStrategy,
+    caller_device: DeviceId,
+) -> Result<(), LockError> {
+    if let Some(lock) = &file.lock {
+        if !lock.is_held_by(caller_device) {
+            return Err(LockError::LockedBy(lock.owner_device_id));
+        }
+    }
+
+    let mut resolved = file.clone();
+    match strategy {
+        ConflictResolutionStrategy::KeepCurrent => return Ok(()),
+        ConflictResolutionStrategy::KeepIncoming => {
+            resolved.head_version_id = file
+                .device_states
+                .iter()
+                .find(|s| s.device_id == caller_device)
+                .and_then(|s| s.known_head_version_id)
+                .ok_or(LockError::NoIncomingHead(caller_device))?;
+        }
+        ConflictResolutionStrategy::Merge(version) => {
+            verify_merkle_root(&version)?;
+            resolved.head_version_id = version.version_id;
+            resolved.versions.push(*version);
+        }
+    }
+    assert_file_invariants(&resolved)?;
+    *file = resolved;
+    Ok(())
+}
+
+/// Update per-device state to reflect lock blocked status, recording `reason` (if any) as the
+/// device's `last_error`. Returns whether the device state was found and updated.
+///
+/// Quarantined devices are left untouched; quarantine outranks lock contention.
+pub fn mark_lock_blocked(
+    file: &mut FileRecord,
+    device_id: DeviceId,
+    reason: Option<String>,
+) -> bool {
+    let Some(state) = file
         .device_states
         .iter_mut()
         .find(|s| s.device_id == device_id)
-    {
-        state.state = DeviceFileStateKind::LockBlocked;
+    else {
+        return false;
+    };
+    if matches!(state.state, DeviceFileStateKind::Quarantined { .. }) {
+        return false;
     }
+    state.state = DeviceFileStateKind::LockBlocked;
+    if reason.is_some() {
+        state.last_error = reason;
+    }
+    true
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ChunkRef, DeviceFileState, EncryptionInfo, VersionRecord};
+    use std::collections::{HashMap, HashSet};
+    use crate::{ChunkRef, ContentEncoding, DeviceFileState, EncryptionInfo, VersionRecord};
     use chrono::Utc;
 
     fn sample_file() -> FileRecord {
@@ -149,7 +355,13 @@ mod tests {
                     offset: 0,
                     length: 1,
                     hash: "h".into(),
+                    sequence: 0,
                 }],
+                author_user_id: None,
+                commit_message: None,
+                encoding: ContentEncoding::Raw,
+                merkle_root: None,
+                size_on_wire: None,
             }],
             lock: None,
             device_states: vec![DeviceFileState {
@@ -158,12 +370,13 @@ mod tests {
                 known_head_version_id: Some(head),
                 last_seen_at: Utc::now(),
                 last_error: None,
+                known_chunk_hashes: HashSet::new(),
             }],
-            encryption: EncryptionInfo {
-                key_id: "k".into(),
-                algo: "AES-256-GCM".into(),
-                iv_salt: None,
-            },
+            encryption: EncryptionInfo::aes_256_gcm("k".into(), None),
+            custom_metadata: HashMap::new(),
+            tombstone: None,
+            display_name: None,
+            lock_history: Vec::new(),
         }
     }
 
@@ -173,10 +386,7 @@ mod tests {
         let device = Ulid::new();
         let res = acquire_lock(
             &file,
-            device,
-            "user".into(),
-            LockRequestKind::Manual,
-            false,
+            LockRequest::new(device, "user".into(), LockRequestKind::Manual),
         )
         .unwrap();
         matches!(res, LockAcquisition::Acquired(_));
@@ -189,10 +399,7 @@ mod tests {
         let device_b = Ulid::new();
         let lock = acquire_lock(
             &file,
-            device_a,
-            "user".into(),
-            LockRequestKind::Manual,
-            false,
+            LockRequest::new(device_a, "user".into(), LockRequestKind::Manual),
         )
         .unwrap();
         if let LockAcquisition::Acquired(lock) = lock {
@@ -200,10 +407,7 @@ mod tests {
             file_mut.lock = Some(lock);
             let denied = acquire_lock(
                 &file_mut,
-                device_b,
-                "user2".into(),
-                LockRequestKind::Manual,
-                false,
+                LockRequest::new(device_b, "user2".into(), LockRequestKind::Manual),
             )
             .unwrap();
             assert!(matches!(denied, LockAcquisition::Denied(_)));
@@ -232,8 +436,11 @@ mod tests {
     fn locked_by_other_blocks() {
         let file = sample_file();
         let device_a = Ulid::new();
-        if let LockAcquisition::Acquired(lock) =
-            acquire_lock(&file, device_a, "u".into(), LockRequestKind::Manual, false).unwrap()
+        if let LockAcquisition::Acquired(lock) = acquire_lock(
+            &file,
+            LockRequest::new(device_a, "u".into(), LockRequestKind::Manual),
+        )
+        .unwrap()
         {
             let mut f = file.clone();
             f.lock = Some(lock);
@@ -241,4 +448,322 @@ mod tests {
             assert!(matches!(res, ConflictCheck::LockedBy(_)));
         }
     }
+
+    #[test]
+    fn quarantined_caller_is_rejected() {
+        let mut file = sample_file();
+        let device = file.device_states[0].device_id;
+        file.device_states[0].state = DeviceFileStateKind::Quarantined {
+            reason: "pending review".into(),
+        };
+        let res = check_conflict(&file, device, file.head_version_id);
+        assert_eq!(
+            res,
+            ConflictCheck::Quarantined {
+                reason: "pending review".into()
+            }
+        );
+    }
+
+    #[test]
+    fn mark_lock_blocked_keeps_quarantine() {
+        let mut file = sample_file();
+        let device = file.device_states[0].device_id;
+        file.device_states[0].state = DeviceFileStateKind::Quarantined {
+            reason: "pending review".into(),
+        };
+        assert!(!mark_lock_blocked(&mut file, device, None));
+        assert!(matches!(
+            file.device_states[0].state,
+            DeviceFileStateKind::Quarantined { .. }
+        ));
+
+        file.device_states[0].state = DeviceFileStateKind::Syncing;
+        assert!(mark_lock_blocked(&mut file, device, None));
+        assert_eq!(file.device_states[0].state, DeviceFileStateKind::LockBlocked);
+    }
+
+    #[test]
+    fn mark_lock_blocked_records_reason() {
+        let mut file = sample_file();
+        let device = file.device_states[0].device_id;
+        assert!(!mark_lock_blocked(&mut file, Ulid::new(), None));
+        assert_eq!(file.device_states[0].state, DeviceFileStateKind::Ready);
+
+        assert!(mark_lock_blocked(
+            &mut file,
+            device,
+            Some("exclusive held by X".into())
+        ));
+        assert_eq!(
+            file.device_states[0].state,
+            DeviceFileStateKind::LockBlocked
+        );
+        assert_eq!(
+            file.device_states[0].last_error.as_deref(),
+            Some("exclusive held by X")
+        );
+    }
+
+    fn acquire(file: &FileRecord, device: DeviceId) -> LockRecord {
+        match acquire_lock(
+            file,
+            LockRequest::new(device, "user".into(), LockRequestKind::Manual),
+        )
+        .unwrap()
+        {
+            LockAcquisition::Acquired(lock) => lock,
+            LockAcquisition::Denied(_) => panic!("expected lock to be granted"),
+        }
+    }
+
+    #[test]
+    fn release_records_history() {
+        let mut file = sample_file();
+        let (first, second) = (Ulid::new(), Ulid::new());
+        file.lock = Some(acquire(&file, first));
+        release_lock(&mut file, first, MAX_LOCK_HISTORY).unwrap();
+        let mut lock = acquire(&file, second);
+        lock.acquired_at += chrono::Duration::seconds(1);
+        file.lock = Some(lock);
+        assert!(force_release_lock(&mut file, MAX_LOCK_HISTORY).is_some());
+        assert!(file.lock.is_none());
+
+        let holders: Vec<DeviceId> = recent_lock_holders(&file, 5)
+            .iter()
+            .map(|l| l.owner_device_id)
+            .collect();
+        assert_eq!(holders, vec![second, first]);
+        assert_eq!(recent_lock_holders(&file, 1).len(), 1);
+    }
+
+    #[test]
+    fn lock_history_is_capped() {
+        let mut file = sample_file();
+        for _ in 0..MAX_LOCK_HISTORY + 5 {
+            file.lock = Some(acquire(&file, Ulid::new()));
+            force_release_lock(&mut file, MAX_LOCK_HISTORY);
+        }
+        assert_eq!(file.lock_history.len(), MAX_LOCK_HISTORY);
+        crate::assert_file_invariants(&file).unwrap();
+
+        let lock = acquire(&file, Ulid::new());
+        push_lock_history(&mut file, lock, 3);
+        assert_eq!(file.lock_history.len(), 3);
+    }
+
+    #[test]
+    fn denial_reports_remaining_wait() {
+        let mut file = sample_file();
+        let now = Utc::now();
+        let mut lock = acquire(&file, Ulid::new());
+        lock.expires_at = Some(now + Duration::minutes(5));
+        file.lock = Some(lock);
+
+        let denied = acquire_lock(
+            &file,
+            LockRequest::new(Ulid::new(), "user".into(), LockRequestKind::Manual),
+        )
+        .unwrap();
+        let LockAcquisition::Denied(denial) = denied else {
+            panic!("expected denial");
+        };
+        assert_eq!(estimated_wait(&denial, now), Some(Duration::minutes(5)));
+        assert_eq!(
+            estimated_wait(&denial, now + Duration::minutes(10)),
+            Some(Duration::zero())
+        );
+
+        let forever = LockDenial {
+            expires_at: None,
+            ..denial
+        };
+        assert_eq!(estimated_wait(&forever, now), None);
+    }
+
+    #[test]
+    fn timed_lock_expires_on_its_own() {
+        let mut file = sample_file();
+        let (device_a, device_b) = (Ulid::new(), Ulid::new());
+        let duration = std::time::Duration::from_millis(1);
+        let before = Utc::now();
+        let LockAcquisition::Acquired(lock) = acquire_lock(
+            &file,
+            LockRequest {
+                mode: LockMode::TimedExclusive { duration },
+                ..LockRequest::new(device_a, "u".into(), LockRequestKind::Auto)
+            },
+        )
+        .unwrap() else {
+            panic!("expected timed lock to be acquired");
+        };
+        let expires_at = lock.expires_at.expect("timed lock sets expires_at");
+        assert_eq!(expires_at, lock.acquired_at + Duration::milliseconds(1));
+        assert!(lock.acquired_at >= before);
+        file.lock = Some(lock);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let res = acquire_lock(
+            &file,
+            LockRequest::new(device_b, "u".into(), LockRequestKind::Manual),
+        )
+        .unwrap();
+        assert!(
+            matches!(res, LockAcquisition::Acquired(l) if l.owner_device_id == device_b && l.expires_at.is_none())
+        );
+    }
+
+    #[test]
+    fn resolves_conflicts_with_each_strategy() {
+        let mut file = sample_file();
+        let base = file.head_version_id;
+        let device = file.device_states[0].device_id;
+        let mut incoming = file.versions[0].clone();
+        incoming.version_id = Ulid::new();
+        incoming.parent_version_id = Some(base);
+        file.versions.push(incoming.clone());
+
+        resolve_conflict(&mut file, ConflictResolutionStrategy::KeepCurrent, device).unwrap();
+        assert_eq!(file.head_version_id, base);
+
+        file.device_states[0].known_head_version_id = Some(incoming.version_id);
+        resolve_conflict(&mut file, ConflictResolutionStrategy::KeepIncoming, device).unwrap();
+        assert_eq!(file.head_version_id, incoming.version_id);
+        assert_file_invariants(&file).unwrap();
+
+        let mut merge = incoming.clone();
+        merge.version_id = Ulid::new();
+        merge.parent_version_id = Some(incoming.version_id);
+        let merge_id = merge.version_id;
+        let mut tampered = merge.clone();
+        tampered.merkle_root = Some("00".into());
+        assert_eq!(
+            resolve_conflict(
+                &mut file,
+                ConflictResolutionStrategy::Merge(Box::new(tampered)),
+                device,
+            ),
+            Err(LockError::Model(ModelError::MerkleRootMismatch(merge_id)))
+        );
+        assert_eq!(file.head_version_id, incoming.version_id);
+        resolve_conflict(
+            &mut file,
+            ConflictResolutionStrategy::Merge(Box::new(merge)),
+            device,
+        )
+        .unwrap();
+        assert_eq!(file.head_version_id, merge_id);
+        assert_eq!(file.versions.len(), 3);
+        assert_file_invariants(&file).unwrap();
+
+        let stranger = Ulid::new();
+        assert_eq!(
+            resolve_conflict(
+                &mut file,
+                ConflictResolutionStrategy::KeepIncoming,
+                stranger
+            ),
+            Err(LockError::NoIncomingHead(stranger))
+        );
+        file.lock = Some(acquire(&file, stranger));
+        assert_eq!(
+            resolve_conflict(&mut file, ConflictResolutionStrategy::KeepIncoming, device),
+            Err(LockError::LockedBy(stranger))
+        );
+        assert_eq!(file.head_version_id, merge_id);
+    }
+
+    #[test]
+    fn detects_locks_from_revoked_sessions() {
+        let file = sample_file();
+        let session = Ulid::new();
+        let LockAcquisition::Acquired(lock) = acquire_lock(
+            &file,
+            LockRequest {
+                session_id: Some(session),
+                ..LockRequest::new(Ulid::new(), "u".into(), LockRequestKind::Manual)
+            },
+        )
+        .unwrap() else {
+            panic!("expected lock to be granted");
+        };
+        assert_eq!(lock.session_id, Some(session));
+
+        let mut revocation = RevocationList::default();
+        revocation.revoke(Ulid::new());
+        assert!(!lock_session_expired(&lock, &revocation));
+        revocation.revoke(session);
+        assert!(lock_session_expired(&lock, &revocation));
+
+        let sessionless = acquire(&file, Ulid::new());
+        assert!(!lock_session_expired(&sessionless, &revocation));
+    }
+
+    #[test]
+    fn auto_release_only_clears_expired_locks() {
+        let mut file = sample_file();
+        let now = Utc::now();
+        assert_eq!(
+            auto_release_if_expired(&mut file, now, MAX_LOCK_HISTORY),
+            None
+        );
+
+        let device = Ulid::new();
+        let mut lock = acquire(&file, device);
+        assert!(lock.is_held_by(device));
+        assert!(!lock.is_held_by(Ulid::new()));
+        assert!(lock.is_held_by_user("user"));
+        assert!(!lock.is_held_by_user("someone-else"));
+
+        file.lock = Some(lock.clone());
+        assert_eq!(
+            auto_release_if_expired(&mut file, now, MAX_LOCK_HISTORY),
+            None
+        );
+        assert!(file.lock.is_some());
+
+        lock.expires_at = Some(now + Duration::minutes(5));
+        file.lock = Some(lock.clone());
+        assert_eq!(
+            auto_release_if_expired(&mut file, now, MAX_LOCK_HISTORY),
+            None
+        );
+        assert_eq!(
+            auto_release_if_expired(&mut file, now + Duration::minutes(5), MAX_LOCK_HISTORY),
+            Some(lock.clone())
+        );
+        assert!(file.lock.is_none());
+        assert_eq!(file.lock_history.last(), Some(&lock));
+    }
+
+    #[test]
+    fn stale_device_cannot_lock() {
+        let mut file = sample_file();
+        let device = file.device_states[0].device_id;
+        let request = |file: &FileRecord, device| {
+            acquire_lock(
+                file,
+                LockRequest {
+                    max_device_age: Some(Duration::minutes(5)),
+                    ..LockRequest::new(device, "user".into(), LockRequestKind::Manual)
+                },
+            )
+        };
+        file.device_states[0].last_seen_at = Utc::now() - Duration::minutes(10);
+        assert_eq!(
+            request(&file, device),
+            Err(LockError::DeviceOffline(device))
+        );
+
+        file.device_states[0].last_seen_at = Utc::now();
+        assert!(matches!(
+            request(&file, device),
+            Ok(LockAcquisition::Acquired(_))
+        ));
+        // Unknown devices are not gated.
+        assert!(matches!(
+            request(&file, Ulid::new()),
+            Ok(LockAcquisition::Acquired(_))
+        ));
+    }
 }
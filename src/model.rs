@@ -1,8 +1,14 @@
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use ulid::Ulid;
 
+use crate::identity::{DeviceIdentity, SessionId};
+
 /// Stable, path-independent identifiers.
 pub type FileId = Ulid;
 pub type DeviceId = Ulid;
@@ -16,6 +22,28 @@ pub struct ChunkRef {
     pub offset: u64,
     pub length: u64,
     pub hash: String, // strong hash (e.g., SHA-256 hex)
+    /// Position in the version's chunk list; lets out-of-order transfers reassemble.
+    #[serde(default)]
+    pub sequence: u32,
+}
+
+/// Deserialize a version's chunk list. Chunks written before `sequence` existed all read as 0;
+/// they were stored in order, so they are numbered by position.
+fn deserialize_chunks<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ChunkRef>, D::Error> {
+    let mut chunks = Vec::<ChunkRef>::deserialize(deserializer)?;
+    if chunks.len() > 1 && chunks.iter().all(|c| c.sequence == 0) {
+        for (sequence, chunk) in (0..).zip(chunks.iter_mut()) {
+            chunk.sequence = sequence;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Order chunks by `sequence` for reassembly.
+pub fn sort_chunks_by_sequence(chunks: &mut [ChunkRef]) {
+    chunks.sort_by_key(|c| c.sequence);
 }
 
 /// Lightweight version record (shared).
@@ -28,7 +56,165 @@ pub struct VersionRecord {
     pub timestamp: DateTime<Utc>,
     pub content_hash: String,
     pub size_bytes: u64,
+    #[serde(deserialize_with = "deserialize_chunks")]
     pub chunks: Vec<ChunkRef>,
+    #[serde(default)]
+    pub author_user_id: Option<String>,
+    /// Free-form description of the change; at most `MAX_COMMIT_MESSAGE_LEN` characters.
+    #[serde(default)]
+    pub commit_message: Option<String>,
+    /// How the stored bytes are encoded; chunk hashes cover the encoded form.
+    #[serde(default)]
+    pub encoding: ContentEncoding,
+    /// Merkle root over the chunk hashes (see `compute_merkle_root`), for integrity audits.
+    #[serde(default)]
+    pub merkle_root: Option<String>,
+    /// Bytes actually sent over the network after compression and encryption; non-zero.
+    #[serde(default)]
+    pub size_on_wire: Option<u64>,
+}
+
+impl VersionRecord {
+    /// True if this version is `file`'s current head.
+    pub fn is_head_of(&self, file: &FileRecord) -> bool {
+        self.version_id == file.head_version_id
+    }
+
+    /// True if this version is reached by following parent links from `other` within `file`.
+    /// A version is not its own ancestor; malformed parent cycles end the walk.
+    pub fn is_ancestor_of(&self, other: &VersionRecord, file: &FileRecord) -> bool {
+        let mut visited = HashSet::new();
+        let mut current = other.parent_version_id;
+        while let Some(id) = current {
+            if id == self.version_id {
+                return true;
+            }
+            if !visited.insert(id) {
+                return false;
+            }
+            current = file
+                .versions
+                .iter()
+                .find(|v| v.version_id == id)
+                .and_then(|v| v.parent_version_id);
+        }
+        false
+    }
+}
+
+/// Storage encoding of a version's content. Records written before encodings existed are `Raw`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentEncoding {
+    #[default]
+    Raw,
+    Gzip,
+    Lz4,
+    Zstd,
+}
+
+pub fn declared_encoding(record: &VersionRecord) -> ContentEncoding {
+    record.encoding
+}
+
+/// Raw bytes of `record.content_hash`, which is stored as hex.
+pub fn content_hash_bytes(record: &VersionRecord) -> Result<Vec<u8>, ModelError> {
+    decode_hash(&record.content_hash)
+}
+
+/// Raw bytes of `chunk.hash`, which is stored as hex.
+pub fn chunk_hash_bytes(chunk: &ChunkRef) -> Result<Vec<u8>, ModelError> {
+    decode_hash(&chunk.hash)
+}
+
+fn decode_hash(hash: &str) -> Result<Vec<u8>, ModelError> {
+    hex::decode(hash).map_err(|_| ModelError::InvalidContentHash(hash.to_string()))
+}
+
+/// Hex-encoded Merkle root over `version`'s chunk hashes in sequence order. Leaves are the
+/// bytes of each chunk hash as stored; each parent is the SHA-256 of its two children
+/// concatenated, and an unpaired last node is carried up unchanged. A version without
+/// chunks hashes to the SHA-256 of nothing.
+pub fn compute_merkle_root(version: &VersionRecord) -> String {
+    let mut chunks: Vec<&ChunkRef> = version.chunks.iter().collect();
+    chunks.sort_by_key(|c| c.sequence);
+    let mut level: Vec<Vec<u8>> = chunks.iter().map(|c| c.hash.as_bytes().to_vec()).collect();
+    if level.is_empty() {
+        return hex::encode(Sha256::digest([]));
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new()
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .to_vec(),
+                [single] => single.clone(),
+                _ => unreachable!("chunks(2) yields one or two nodes"),
+            })
+            .collect();
+    }
+    hex::encode(&level[0])
+}
+
+/// Check `version.merkle_root`, if set, against its chunks. Not part of
+/// `assert_file_invariants` because it hashes every chunk; check versions when they are added.
+pub fn verify_merkle_root(version: &VersionRecord) -> Result<(), ModelError> {
+    match &version.merkle_root {
+        Some(root) if *root != compute_merkle_root(version) => {
+            Err(ModelError::MerkleRootMismatch(version.version_id))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// True if every chunk of `version` is in `held_chunks` (by hash), so it can be restored
+/// without fetching anything.
+pub fn is_restorable(version: &VersionRecord, held_chunks: &HashSet<String>) -> bool {
+    version.chunks.iter().all(|c| held_chunks.contains(&c.hash))
+}
+
+/// Chunks of `version` whose hash is not in `held_chunks`, in chunk order.
+pub fn missing_chunks<'a>(
+    version: &'a VersionRecord,
+    held_chunks: &HashSet<String>,
+) -> Vec<&'a ChunkRef> {
+    version
+        .chunks
+        .iter()
+        .filter(|c| !held_chunks.contains(&c.hash))
+        .collect()
+}
+
+/// Chunks of `version` the device does not hold yet, in chunk order.
+pub fn chunks_device_needs<'a>(
+    device_state: &DeviceFileState,
+    version: &'a VersionRecord,
+) -> Vec<&'a ChunkRef> {
+    missing_chunks(version, &device_state.known_chunk_hashes)
+}
+
+/// `size_bytes / size_on_wire`: above 1 when compression shrank the transfer. `None` if the
+/// wire size is unknown (or zero).
+pub fn compression_ratio(version: &VersionRecord) -> Option<f64> {
+    let on_wire = version.size_on_wire.filter(|bytes| *bytes > 0)?;
+    Some(version.size_bytes as f64 / on_wire as f64)
+}
+
+/// Check that every chunk hash of `version` is in `good_hashes` (e.g., the hashes verified
+/// during a transfer). Reports the first unknown hash in chunk order.
+pub fn verify_chunk_hashes(
+    version: &VersionRecord,
+    good_hashes: &HashSet<String>,
+) -> Result<(), ModelError> {
+    match missing_chunks(version, good_hashes).first() {
+        Some(chunk) => Err(ModelError::UnknownChunkHash {
+            version_id: version.version_id,
+            hash: chunk.hash.clone(),
+        }),
+        None => Ok(()),
+    }
 }
 
 /// Per-file lock metadata (shared).
@@ -42,11 +228,33 @@ pub struct LockRecord {
     pub acquired_at: DateTime<Utc>,
     pub auto_lock: bool,
     pub expires_at: Option<DateTime<Utc>>,
+    /// Session the lock was taken in; revoking the session releases the lock.
+    #[serde(default)]
+    pub session_id: Option<SessionId>,
+}
+
+impl LockRecord {
+    /// True once `expires_at` has passed; locks without an expiry never expire.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    pub fn is_held_by(&self, device_id: DeviceId) -> bool {
+        self.owner_device_id == device_id
+    }
+
+    pub fn is_held_by_user(&self, user_id: &str) -> bool {
+        self.owner_user_id == user_id
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LockMode {
     Exclusive,
+    /// Exclusive lock that expires `duration` after acquisition without an explicit release.
+    TimedExclusive {
+        duration: std::time::Duration,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -56,9 +264,20 @@ pub enum DeviceFileStateKind {
     Pulling,
     Ready,
     Pushing,
+    /// Bidirectional sync in flight (pull and push active at once).
+    Syncing,
     LockBlocked,
     Conflict,
     Error,
+    /// File held back pending security review; no transfers or edits allowed.
+    Quarantined { reason: String },
+}
+
+impl DeviceFileStateKind {
+    /// True while a transfer is actively moving bytes for this device.
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Pulling | Self::Pushing | Self::Syncing)
+    }
 }
 
 /// Per-device state vector (shared).
@@ -69,6 +288,9 @@ pub struct DeviceFileState {
     pub known_head_version_id: Option<VersionId>,
     pub last_seen_at: DateTime<Utc>,
     pub last_error: Option<String>,
+    /// Hashes of the chunks the device actually holds (may be a subset of its known head).
+    #[serde(default)]
+    pub known_chunk_hashes: HashSet<String>,
 }
 
 /// Encryption envelope metadata (shared, keys stored locally).
@@ -77,6 +299,67 @@ pub struct EncryptionInfo {
     pub key_id: String,
     pub algo: String, // e.g., "AES-256-GCM"
     pub iv_salt: Option<String>,
+    /// Set when the key is derived from a password; requires `iv_salt`.
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+}
+
+/// Key-derivation parameters for password-derived keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KdfParams {
+    Pbkdf2 {
+        iterations: u32,
+        salt: String,
+    },
+    Argon2 {
+        time_cost: u32,
+        memory_cost: u32,
+        salt: String,
+    },
+}
+
+/// True if the key is password-derived.
+pub fn has_kdf(info: &EncryptionInfo) -> bool {
+    info.kdf.is_some()
+}
+
+/// `EncryptionInfo::algo` for AES-256 in Galois/Counter Mode.
+pub const AES_256_GCM: &str = "AES-256-GCM";
+
+/// `EncryptionInfo::algo` for ChaCha20-Poly1305.
+pub const CHACHA20_POLY1305: &str = "ChaCha20-Poly1305";
+
+impl EncryptionInfo {
+    pub fn aes_256_gcm(key_id: String, iv_salt: Option<String>) -> Self {
+        Self {
+            key_id,
+            algo: AES_256_GCM.into(),
+            iv_salt,
+            kdf: None,
+        }
+    }
+
+    pub fn chacha20_poly1305(key_id: String, iv_salt: Option<String>) -> Self {
+        Self {
+            key_id,
+            algo: CHACHA20_POLY1305.into(),
+            iv_salt,
+            kdf: None,
+        }
+    }
+
+    /// True for AEAD algorithms, which authenticate the ciphertext as well as encrypting it.
+    pub fn is_authenticated_encryption(&self) -> bool {
+        matches!(self.algo.as_str(), AES_256_GCM | CHACHA20_POLY1305)
+    }
+}
+
+/// Accept only the encryption algorithms this crate knows how to describe.
+pub fn validate_algo(algo: &str) -> Result<(), ModelError> {
+    match algo {
+        AES_256_GCM | CHACHA20_POLY1305 => Ok(()),
+        other => Err(ModelError::UnsupportedEncryptionAlgo(other.to_string())),
+    }
 }
 
 /// File-level shared record.
@@ -90,6 +373,181 @@ pub struct FileRecord {
     pub lock: Option<LockRecord>,
     pub device_states: Vec<DeviceFileState>,
     pub encryption: EncryptionInfo,
+    /// Application-defined properties (e.g., project id, classification tag).
+    #[serde(default)]
+    pub custom_metadata: HashMap<String, String>,
+    /// Set when the file is soft-deleted; recoverable until purged.
+    #[serde(default)]
+    pub tombstone: Option<TombstoneRecord>,
+    /// Human-readable name for files that may have no local path.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Released locks, oldest first, trimmed to the cap given on release and never longer than
+    /// `MAX_LOCK_HISTORY`.
+    #[serde(default)]
+    pub lock_history: Vec<LockRecord>,
+}
+
+impl FileRecord {
+    /// The version `head_version_id` points at; only `None` for records that fail invariants.
+    pub fn head_version(&self) -> Option<&VersionRecord> {
+        self.versions
+            .iter()
+            .find(|v| v.version_id == self.head_version_id)
+    }
+
+    /// Chunks of the head version, or an empty slice if the head is missing.
+    pub fn head_version_chunks(&self) -> &[ChunkRef] {
+        self.head_version().map_or(&[], |v| &v.chunks)
+    }
+
+    /// Content hash of the head version, or `None` if the head is missing.
+    pub fn head_version_content_hash(&self) -> Option<&str> {
+        self.head_version().map(|v| v.content_hash.as_str())
+    }
+
+    /// The most recent version created at or before `at`, i.e. what the file looked like
+    /// then. `None` if every version is newer.
+    pub fn version_at_time(&self, at: DateTime<Utc>) -> Option<&VersionRecord> {
+        self.versions
+            .iter()
+            .filter(|v| v.timestamp <= at)
+            .max_by_key(|v| v.timestamp)
+    }
+
+    /// Users involved with this file: the owners of devices with a state on it (looked up in
+    /// `identities`; unknown devices are skipped) plus the lock holder, if any.
+    pub fn participating_user_ids(&self, identities: &[DeviceIdentity]) -> HashSet<String> {
+        let mut users: HashSet<String> = self
+            .device_states
+            .iter()
+            .filter_map(|d| identities.iter().find(|i| i.device_id == d.device_id))
+            .map(|identity| identity.user_id.to_string())
+            .collect();
+        if let Some(lock) = &self.lock {
+            users.insert(lock.owner_user_id.clone());
+        }
+        users
+    }
+
+    /// Id of the version that was head at `at`; see `version_at_time`.
+    pub fn head_at_time(&self, at: DateTime<Utc>) -> Option<VersionId> {
+        self.version_at_time(at).map(|v| v.version_id)
+    }
+
+    /// Every distinct chunk hash referenced by any version, for block-level dedup.
+    pub fn all_unique_chunk_hashes(&self) -> HashSet<&str> {
+        self.versions
+            .iter()
+            .flat_map(|v| &v.chunks)
+            .map(|c| c.hash.as_str())
+            .collect()
+    }
+
+    /// Chunks of `version_id` whose hash no other version references; safe to garbage-collect
+    /// if that version is pruned. Empty if the version does not exist.
+    pub fn chunks_only_in_version(&self, version_id: VersionId) -> Vec<&ChunkRef> {
+        let elsewhere: HashSet<&str> = self
+            .versions
+            .iter()
+            .filter(|v| v.version_id != version_id)
+            .flat_map(|v| &v.chunks)
+            .map(|c| c.hash.as_str())
+            .collect();
+        self.versions
+            .iter()
+            .filter(|v| v.version_id == version_id)
+            .flat_map(|v| &v.chunks)
+            .filter(|c| !elsewhere.contains(c.hash.as_str()))
+            .collect()
+    }
+
+    /// Refresh `last_seen_at` for `device_id` (a heartbeat) without touching anything else.
+    /// Returns false if the device has no state on this record.
+    pub fn touch_device_state(&mut self, device_id: DeviceId, at: DateTime<Utc>) -> bool {
+        match self
+            .device_states
+            .iter_mut()
+            .find(|d| d.device_id == device_id)
+        {
+            Some(state) => {
+                state.last_seen_at = at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copy of this record carrying only `device_id`'s state (none if it has no state), so
+    /// other devices' states aren't disclosed when sending it to that device.
+    pub fn clone_for_device(&self, device_id: DeviceId) -> FileRecord {
+        let mut record = self.clone();
+        record.device_states.retain(|d| d.device_id == device_id);
+        record
+    }
+}
+
+/// Unresolved divergence between two heads of a file; both versions are kept until the user
+/// picks a winner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictRecord {
+    pub file_id: FileId,
+    pub local_version_id: VersionId,
+    pub remote_version_id: VersionId,
+    pub local_device_id: DeviceId,
+    pub remote_device_id: DeviceId,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl ConflictRecord {
+    /// True if `device_id` produced either side of the conflict.
+    pub fn involves(&self, device_id: DeviceId) -> bool {
+        self.local_device_id == device_id || self.remote_device_id == device_id
+    }
+}
+
+/// Soft-delete marker (shared).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TombstoneRecord {
+    pub deleted_at: DateTime<Utc>,
+    pub deleted_by_device: DeviceId,
+    pub deleted_by_user: String,
+}
+
+pub fn is_tombstoned(record: &FileRecord) -> bool {
+    record.tombstone.is_some()
+}
+
+/// Reconcile device states from another replica of the same file into `primary`.
+///
+/// For a device known to both, the state with the later `last_seen_at` wins (ties keep
+/// `primary`'s); devices only in `secondary` are appended. The merged record is then validated.
+pub fn merge_device_states(
+    primary: &mut FileRecord,
+    secondary: &FileRecord,
+) -> Result<(), ModelError> {
+    for theirs in &secondary.device_states {
+        match primary
+            .device_states
+            .iter_mut()
+            .find(|d| d.device_id == theirs.device_id)
+        {
+            Some(ours) if theirs.last_seen_at > ours.last_seen_at => *ours = theirs.clone(),
+            Some(_) => {}
+            None => primary.device_states.push(theirs.clone()),
+        }
+    }
+    assert_file_invariants(primary)
+}
+
+/// Clear a soft-delete marker, making the file live again. The restored record is validated
+/// first; on error `record` is left untouched.
+pub fn restore_tombstone(record: &mut FileRecord) -> Result<(), ModelError> {
+    let mut restored = record.clone();
+    restored.tombstone = None;
+    assert_file_invariants(&restored)?;
+    *record = restored;
+    Ok(())
 }
 
 /// Local-only registry entry; path mappings keep identity stable.
@@ -98,11 +556,34 @@ pub struct LocalRegistryEntry {
     pub file_id: FileId,
     pub paths: Vec<PathBinding>,
     pub local_version_id: Option<VersionId>,
+    #[serde(deserialize_with = "deserialize_hydration")]
     pub hydration: Hydration,
     pub consent: Consent,
     pub pin: PinPreference,
     pub auto_lock_preference: AutoLockPreference,
     pub last_error: Option<String>,
+    /// Related files (e.g., one project) that should sync together.
+    #[serde(default)]
+    pub sync_group: Option<String>,
+    /// Transfer ordering hint; 0 is highest priority.
+    #[serde(default = "default_sync_priority")]
+    pub priority: u8,
+    /// User-marked for quick access.
+    #[serde(default)]
+    pub starred: bool,
+    /// Bytes of this file actually on local disk; may lag the head version during downloads.
+    #[serde(default)]
+    pub local_size_bytes: Option<u64>,
+    /// User has suspended sync for this file; it stays registered but is not scheduled.
+    #[serde(default)]
+    pub sync_paused: bool,
+}
+
+/// `LocalRegistryEntry::priority` for entries that predate priorities: the middle of the range.
+pub const DEFAULT_SYNC_PRIORITY: u8 = 128;
+
+fn default_sync_priority() -> u8 {
+    DEFAULT_SYNC_PRIORITY
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -110,25 +591,77 @@ pub struct PathBinding {
     pub path: String,
     pub last_seen_at: DateTime<Utc>,
     pub writable: bool,
+    /// Filesystem mount the path lives on (e.g., a removable drive or network share).
+    #[serde(default)]
+    pub mount_point: Option<String>,
+}
+
+/// Bindings of `entry` that live on the given mount point.
+pub fn paths_on_mount<'a>(entry: &'a LocalRegistryEntry, mount: &str) -> Vec<&'a PathBinding> {
+    entry
+        .paths
+        .iter()
+        .filter(|p| p.mount_point.as_deref() == Some(mount))
+        .collect()
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Hydration {
     FullyPresent,
-    Partial,
+    Partial {
+        bytes_present: u64,
+        total_bytes: u64,
+    },
     None,
 }
 
+/// Deserialize a registry entry's hydration. Entries written before `Partial` carried byte
+/// counts hold a bare `"Partial"`; with the counts unknown, it reads as zero bytes present.
+fn deserialize_hydration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Hydration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Current(Hydration),
+        Legacy(LegacyHydration),
+    }
+    #[derive(Deserialize)]
+    enum LegacyHydration {
+        Partial,
+    }
+
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::Current(hydration) => hydration,
+        Stored::Legacy(LegacyHydration::Partial) => Hydration::Partial {
+            bytes_present: 0,
+            total_bytes: 0,
+        },
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Consent {
     Approved,
     Revoked,
+    /// Submitted for compliance review; sync stays blocked until approved.
+    PendingReview {
+        submitted_at: DateTime<Utc>,
+        reviewer_id: Option<String>,
+    },
+}
+
+/// Only explicitly approved files may sync.
+pub fn is_sync_allowed(consent: &Consent) -> bool {
+    matches!(consent, Consent::Approved)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PinPreference {
     None,
     KeepLatest,
+    /// Keep the N most recent versions fully present regardless of retention.
+    KeepN(usize),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -148,6 +681,8 @@ pub struct TransferSession {
     pub active_chunks: Vec<ChunkRef>,
     pub retry_count: u32,
     pub status: TransferStatus,
+    #[serde(default = "SystemTime::now")]
+    pub started_at: SystemTime,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -159,8 +694,14 @@ pub enum TransferDirection {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransferStatus {
     InProgress,
+    /// Deliberately deferred (e.g., waiting for Wi-Fi); resumable.
+    Paused { reason: String },
     Completed,
     Failed(String),
+    /// Every chunk not completed was abandoned; the transfer cannot progress further.
+    PartiallyComplete {
+        abandoned: Vec<u64>,
+    },
 }
 
 /// Errors when validating invariants or state transitions.
@@ -174,21 +715,180 @@ pub enum ModelError {
     MultipleLocks,
     #[error("device state missing for device {0}")]
     MissingDevice(DeviceId),
+    #[error("invalid custom metadata key {0:?}")]
+    InvalidMetadataKey(String),
+    #[error("invalid path {0:?}")]
+    InvalidPath(String),
+    #[error("tombstoned file still has an active lock")]
+    TombstonedWithActiveLock,
+    #[error("commit message on version {0} exceeds {MAX_COMMIT_MESSAGE_LEN} characters")]
+    CommitMessageTooLong(VersionId),
+    #[error("version {0} has an empty content hash")]
+    EmptyContentHash(VersionId),
+    #[error("chunk hash {hash} of version {version_id} is not known-good")]
+    UnknownChunkHash { version_id: VersionId, hash: String },
+    #[error("unsupported encryption algorithm {0:?}")]
+    UnsupportedEncryptionAlgo(String),
+    #[error("encryption has key-derivation parameters but no IV/salt")]
+    KdfWithoutIvSalt,
+    #[error("chunk sequence numbers of version {0} are not contiguous from 0")]
+    NonContiguousChunkSequence(VersionId),
+    #[error("merkle root of version {0} does not match its chunk hashes")]
+    MerkleRootMismatch(VersionId),
+    #[error("version {0} has a zero on-wire size")]
+    ZeroSizeOnWire(VersionId),
+    #[error("device {device_id} claims unknown head version {version_id}")]
+    UnknownKnownHead {
+        device_id: DeviceId,
+        version_id: VersionId,
+    },
+    #[error("content hash {0:?} is not valid hex")]
+    InvalidContentHash(String),
+    #[error("local size {actual:?} does not match {expected} bytes present")]
+    LocalSizeMismatch { expected: u64, actual: Option<u64> },
+    #[error("lock history holds {0} entries, more than {MAX_LOCK_HISTORY}")]
+    LockHistoryTooLong(usize),
+    #[error("lock references file {lock_file} but is attached to file {record_file}")]
+    LockFileMismatch {
+        lock_file: FileId,
+        record_file: FileId,
+    },
 }
 
-/// Validate invariants for a shared FileRecord.
+/// Longest permitted `VersionRecord::commit_message`, in characters.
+pub const MAX_COMMIT_MESSAGE_LEN: usize = 512;
+
+/// Most released locks kept in `FileRecord::lock_history`.
+pub const MAX_LOCK_HISTORY: usize = 100;
+
+/// Longest permitted `custom_metadata` key, in characters.
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+
+/// Custom metadata keys must be non-empty and at most `MAX_METADATA_KEY_LEN` characters.
+pub fn validate_metadata_key(key: &str) -> Result<(), ModelError> {
+    if key.is_empty() || key.chars().count() > MAX_METADATA_KEY_LEN {
+        return Err(ModelError::InvalidMetadataKey(key.to_string()));
+    }
+    Ok(())
+}
+
+/// Longest permitted `PathBinding::path`, in bytes.
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Bound paths must be non-empty, absolute for the current platform (`/...` on Unix, a drive
+/// letter such as `C:\...` or a UNC share such as `\\server\share\...` on Windows), free of
+/// NUL bytes, and at most `MAX_PATH_LEN` bytes.
+pub fn validate_path(path: &str) -> Result<(), ModelError> {
+    let absolute = if cfg!(windows) {
+        is_windows_absolute(path)
+    } else {
+        path.starts_with('/')
+    };
+    if !absolute || path.contains('\0') || path.len() > MAX_PATH_LEN {
+        return Err(ModelError::InvalidPath(path.to_string()));
+    }
+    Ok(())
+}
+
+/// A drive-letter path (`C:\...`) or a UNC path naming both a server and a share.
+fn is_windows_absolute(path: &str) -> bool {
+    let is_separator = |c: char| matches!(c, '\\' | '/');
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return is_separator(bytes[2] as char);
+    }
+    let Some(unc) = path
+        .strip_prefix("\\\\")
+        .or_else(|| path.strip_prefix("//"))
+    else {
+        return false;
+    };
+    let mut parts = unc.split(is_separator);
+    matches!(
+        (parts.next(), parts.next()),
+        (Some(server), Some(share)) if !server.is_empty() && !share.is_empty()
+    )
+}
+
+/// How strictly `assert_file_invariants_with` treats cross-references between device states
+/// and the version list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileInvariantMode {
+    /// Every reference must resolve within the record.
+    #[default]
+    Strict,
+    /// Allow device states to reference versions this replica has not received yet, for
+    /// eventually-consistent stores.
+    Relaxed,
+}
+
+/// Validate invariants for a local registry entry.
+///
+/// - A partially hydrated entry must report `local_size_bytes == Some(bytes_present)`. An
+///   unknown size counts as zero, as in `total_local_disk_usage`.
+pub fn assert_registry_invariants(entry: &LocalRegistryEntry) -> Result<(), ModelError> {
+    if let Hydration::Partial { bytes_present, .. } = entry.hydration {
+        if entry.local_size_bytes.unwrap_or(0) != bytes_present {
+            return Err(ModelError::LocalSizeMismatch {
+                expected: bytes_present,
+                actual: entry.local_size_bytes,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Validate invariants for a shared FileRecord in `FileInvariantMode::Strict`.
 ///
 /// - Head version must exist in versions list.
 /// - Versions list must not contain duplicates.
-/// - At most one active lock.
+/// - Commit messages must not exceed `MAX_COMMIT_MESSAGE_LEN` characters.
+/// - Raw-encoded versions must carry a content hash.
+/// - At most one active lock, and it must reference this file.
+/// - Lock history holds at most `MAX_LOCK_HISTORY` entries.
 /// - Each DeviceFileState must have a unique device_id.
+/// - A device's `known_head_version_id` must exist in versions (strict mode only).
+/// - Custom metadata keys must be valid (see `validate_metadata_key`).
+/// - A tombstoned file must not hold a lock.
 pub fn assert_file_invariants(record: &FileRecord) -> Result<(), ModelError> {
+    assert_file_invariants_with(record, FileInvariantMode::Strict)
+}
+
+/// Validate invariants for a shared FileRecord under the given `mode`.
+pub fn assert_file_invariants_with(
+    record: &FileRecord,
+    mode: FileInvariantMode,
+) -> Result<(), ModelError> {
     let mut seen_versions = std::collections::HashSet::new();
     let mut head_present = false;
     for v in &record.versions {
         if !seen_versions.insert(v.version_id) {
             return Err(ModelError::DuplicateVersion(v.version_id));
         }
+        if v
+            .commit_message
+            .as_ref()
+            .is_some_and(|m| m.chars().count() > MAX_COMMIT_MESSAGE_LEN)
+        {
+            return Err(ModelError::CommitMessageTooLong(v.version_id));
+        }
+        // Without the payload we can't verify the hash matches the encoding; at least require
+        // raw content to be hashed.
+        if v.encoding == ContentEncoding::Raw && v.content_hash.is_empty() {
+            return Err(ModelError::EmptyContentHash(v.version_id));
+        }
+        let mut sequences: Vec<u32> = v.chunks.iter().map(|c| c.sequence).collect();
+        sequences.sort_unstable();
+        if sequences
+            .iter()
+            .zip(0u32..)
+            .any(|(seq, expected)| *seq != expected)
+        {
+            return Err(ModelError::NonContiguousChunkSequence(v.version_id));
+        }
+        if v.size_on_wire == Some(0) {
+            return Err(ModelError::ZeroSizeOnWire(v.version_id));
+        }
         if v.version_id == record.head_version_id {
             head_present = true;
         }
@@ -197,11 +897,18 @@ pub fn assert_file_invariants(record: &FileRecord) -> Result<(), ModelError> {
         return Err(ModelError::MissingHead(record.head_version_id));
     }
 
-    if record.lock.is_some() {
-        // Because lock is optional and singular, a second lock would require a different field.
-        // This guard ensures the intent is explicit.
-        // (Retained to document invariant explicitly; runtime check is trivial.)
-        // Additional enforcement could check lock.file_id == record.file_id.
+    // Lock is optional and singular, so "at most one" holds by construction; the lock must
+    // still belong to this record.
+    if let Some(lock) = &record.lock {
+        if lock.file_id != record.file_id {
+            return Err(ModelError::LockFileMismatch {
+                lock_file: lock.file_id,
+                record_file: record.file_id,
+            });
+        }
+    }
+    if record.lock_history.len() > MAX_LOCK_HISTORY {
+        return Err(ModelError::LockHistoryTooLong(record.lock_history.len()));
     }
 
     let mut seen_devices = std::collections::HashSet::new();
@@ -209,6 +916,28 @@ pub fn assert_file_invariants(record: &FileRecord) -> Result<(), ModelError> {
         if !seen_devices.insert(state.device_id) {
             return Err(ModelError::MissingDevice(state.device_id));
         }
+        if mode == FileInvariantMode::Strict {
+            if let Some(version_id) = state.known_head_version_id {
+                if !seen_versions.contains(&version_id) {
+                    return Err(ModelError::UnknownKnownHead {
+                        device_id: state.device_id,
+                        version_id,
+                    });
+                }
+            }
+        }
+    }
+
+    for key in record.custom_metadata.keys() {
+        validate_metadata_key(key)?;
+    }
+
+    if has_kdf(&record.encryption) && record.encryption.iv_salt.is_none() {
+        return Err(ModelError::KdfWithoutIvSalt);
+    }
+
+    if record.tombstone.is_some() && record.lock.is_some() {
+        return Err(ModelError::TombstonedWithActiveLock);
     }
 
     Ok(())
@@ -235,7 +964,13 @@ mod tests {
                 offset: 0,
                 length: 10,
                 hash: "hash".into(),
+                sequence: 0,
             }],
+            author_user_id: None,
+            commit_message: None,
+            encoding: ContentEncoding::Raw,
+            merkle_root: None,
+            size_on_wire: None,
         }
     }
 
@@ -255,12 +990,13 @@ mod tests {
                 known_head_version_id: Some(version_id),
                 last_seen_at: Utc::now(),
                 last_error: None,
+                known_chunk_hashes: HashSet::new(),
             }],
-            encryption: EncryptionInfo {
-                key_id: "k1".into(),
-                algo: "AES-256-GCM".into(),
-                iv_salt: None,
-            },
+            encryption: EncryptionInfo::aes_256_gcm("k1".into(), None),
+            custom_metadata: HashMap::new(),
+            tombstone: None,
+            display_name: None,
+            lock_history: Vec::new(),
         }
     }
 
@@ -297,8 +1033,808 @@ mod tests {
             known_head_version_id: record.device_states[0].known_head_version_id,
             last_seen_at: Utc::now(),
             last_error: None,
+            known_chunk_hashes: HashSet::new(),
         });
         let err = assert_file_invariants(&record).unwrap_err();
         assert!(matches!(err, ModelError::MissingDevice(_)));
     }
+
+    #[test]
+    fn is_active_covers_transfer_states_only() {
+        let active = [
+            DeviceFileStateKind::Pulling,
+            DeviceFileStateKind::Pushing,
+            DeviceFileStateKind::Syncing,
+        ];
+        let inactive = [
+            DeviceFileStateKind::Absent,
+            DeviceFileStateKind::AvailableRemote,
+            DeviceFileStateKind::Ready,
+            DeviceFileStateKind::LockBlocked,
+            DeviceFileStateKind::Conflict,
+            DeviceFileStateKind::Error,
+            DeviceFileStateKind::Quarantined {
+                reason: "malware scan".into(),
+            },
+        ];
+        assert!(active.iter().all(|s| s.is_active()));
+        assert!(inactive.iter().all(|s| !s.is_active()));
+    }
+
+    #[test]
+    fn only_approved_consent_allows_sync() {
+        let pending = Consent::PendingReview {
+            submitted_at: Utc::now(),
+            reviewer_id: Some("compliance".into()),
+        };
+        assert!(is_sync_allowed(&Consent::Approved));
+        assert!(!is_sync_allowed(&Consent::Revoked));
+        assert!(!is_sync_allowed(&pending));
+    }
+
+    #[test]
+    fn pending_review_consent_round_trips() {
+        let pending = Consent::PendingReview {
+            submitted_at: Utc::now(),
+            reviewer_id: None,
+        };
+        let json = serde_json::to_string(&pending).unwrap();
+        let back: Consent = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, pending);
+    }
+
+    #[test]
+    fn rejects_invalid_metadata_keys() {
+        let mut record = sample_file_record();
+        record
+            .custom_metadata
+            .insert("k".repeat(MAX_METADATA_KEY_LEN), "ok".into());
+        assert_file_invariants(&record).unwrap();
+
+        record.custom_metadata.insert(String::new(), "v".into());
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(err, ModelError::InvalidMetadataKey(String::new()));
+
+        record.custom_metadata.remove("");
+        let long = "k".repeat(MAX_METADATA_KEY_LEN + 1);
+        record.custom_metadata.insert(long.clone(), "v".into());
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(err, ModelError::InvalidMetadataKey(long));
+    }
+
+    #[test]
+    fn custom_metadata_round_trips() {
+        let mut record = sample_file_record();
+        record
+            .custom_metadata
+            .insert("project".into(), "atlas".into());
+        let json = serde_json::to_string(&record).unwrap();
+        let back: FileRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, record);
+    }
+
+    #[test]
+    fn records_from_older_builds_deserialize() {
+        let strip = |value: &mut serde_json::Value, fields: &[&str]| {
+            let object = value.as_object_mut().unwrap();
+            for field in fields {
+                assert!(object.remove(*field).is_some(), "{field}");
+            }
+        };
+
+        let mut record = sample_file_record();
+        record.versions[0].chunks.push(ChunkRef {
+            offset: 10,
+            length: 10,
+            hash: "tail".into(),
+            sequence: 1,
+        });
+        let mut json = serde_json::to_value(&record).unwrap();
+        strip(
+            &mut json,
+            &[
+                "custom_metadata",
+                "tombstone",
+                "display_name",
+                "lock_history",
+            ],
+        );
+        strip(&mut json["encryption"], &["kdf"]);
+        strip(&mut json["device_states"][0], &["known_chunk_hashes"]);
+        let version = &mut json["versions"][0];
+        strip(
+            version,
+            &[
+                "author_user_id",
+                "commit_message",
+                "encoding",
+                "merkle_root",
+                "size_on_wire",
+            ],
+        );
+        strip(&mut version["chunks"][0], &["sequence"]);
+        strip(&mut version["chunks"][1], &["sequence"]);
+        let back: FileRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(back, record);
+        assert_file_invariants(&back).unwrap();
+
+        let entry = LocalRegistryEntry {
+            file_id: ulid(),
+            paths: vec![PathBinding {
+                path: "/tmp/a.psd".into(),
+                last_seen_at: Utc::now(),
+                writable: true,
+                mount_point: None,
+            }],
+            local_version_id: None,
+            hydration: Hydration::FullyPresent,
+            consent: Consent::Approved,
+            pin: PinPreference::None,
+            auto_lock_preference: AutoLockPreference::OnEdit,
+            last_error: None,
+            sync_group: None,
+            priority: DEFAULT_SYNC_PRIORITY,
+            starred: false,
+            local_size_bytes: None,
+            sync_paused: false,
+        };
+        let mut json = serde_json::to_value(&entry).unwrap();
+        strip(
+            &mut json,
+            &[
+                "sync_group",
+                "priority",
+                "starred",
+                "local_size_bytes",
+                "sync_paused",
+            ],
+        );
+        strip(&mut json["paths"][0], &["mount_point"]);
+        let back: LocalRegistryEntry = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(back, entry);
+
+        // `Partial` was a unit variant before it carried byte counts.
+        json["hydration"] = "Partial".into();
+        let back: LocalRegistryEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            back.hydration,
+            Hydration::Partial {
+                bytes_present: 0,
+                total_bytes: 0,
+            }
+        );
+        assert_registry_invariants(&back).unwrap();
+    }
+
+    fn sample_tombstone() -> TombstoneRecord {
+        TombstoneRecord {
+            deleted_at: Utc::now(),
+            deleted_by_device: ulid(),
+            deleted_by_user: "user".into(),
+        }
+    }
+
+    #[test]
+    fn tombstone_and_restore() {
+        let mut record = sample_file_record();
+        assert!(!is_tombstoned(&record));
+        record.tombstone = Some(sample_tombstone());
+        assert!(is_tombstoned(&record));
+        assert_file_invariants(&record).unwrap();
+
+        restore_tombstone(&mut record).unwrap();
+        assert!(!is_tombstoned(&record));
+
+        // A record that fails validation keeps its tombstone.
+        record.tombstone = Some(sample_tombstone());
+        record.head_version_id = ulid();
+        let before = record.clone();
+        assert!(restore_tombstone(&mut record).is_err());
+        assert_eq!(record, before);
+    }
+
+    #[test]
+    fn rejects_tombstone_with_active_lock() {
+        let mut record = sample_file_record();
+        record.tombstone = Some(sample_tombstone());
+        record.lock = Some(LockRecord {
+            lock_id: ulid(),
+            file_id: record.file_id,
+            owner_device_id: ulid(),
+            owner_user_id: "user".into(),
+            mode: LockMode::Exclusive,
+            acquired_at: Utc::now(),
+            auto_lock: false,
+            expires_at: None,
+            session_id: None,
+        });
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(err, ModelError::TombstonedWithActiveLock);
+    }
+
+    #[test]
+    fn rejects_lock_for_other_file() {
+        let mut record = sample_file_record();
+        let other_file = ulid();
+        record.lock = Some(LockRecord {
+            lock_id: ulid(),
+            file_id: other_file,
+            owner_device_id: ulid(),
+            owner_user_id: "user".into(),
+            mode: LockMode::Exclusive,
+            acquired_at: Utc::now(),
+            auto_lock: false,
+            expires_at: None,
+            session_id: None,
+        });
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(
+            err,
+            ModelError::LockFileMismatch {
+                lock_file: other_file,
+                record_file: record.file_id,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_known_head_is_strict_only() {
+        let mut record = sample_file_record();
+        let device_id = record.device_states[0].device_id;
+        let version_id = ulid();
+        record.device_states[0].known_head_version_id = Some(version_id);
+
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(
+            err,
+            ModelError::UnknownKnownHead {
+                device_id,
+                version_id,
+            }
+        );
+        assert_file_invariants_with(&record, FileInvariantMode::Relaxed).unwrap();
+    }
+
+    #[test]
+    fn chunk_hashes_across_versions() {
+        let mut record = sample_file_record();
+        let first = record.versions[0].version_id;
+        let second = ulid();
+        let mut next = sample_version(record.file_id, second);
+        next.chunks.push(ChunkRef {
+            offset: 10,
+            length: 5,
+            hash: "tail".into(),
+            sequence: 1,
+        });
+        record.versions.push(next);
+
+        assert_eq!(
+            record.all_unique_chunk_hashes(),
+            HashSet::from(["hash", "tail"])
+        );
+        assert!(record.chunks_only_in_version(first).is_empty());
+        let only_second = record.chunks_only_in_version(second);
+        assert_eq!(only_second.len(), 1);
+        assert_eq!(only_second[0].hash, "tail");
+    }
+
+    #[test]
+    fn restorable_only_with_all_chunks_held() {
+        let mut version = sample_version(ulid(), ulid());
+        version.chunks.push(ChunkRef {
+            offset: 10,
+            length: 5,
+            hash: "tail".into(),
+            sequence: 1,
+        });
+        let mut held = HashSet::from(["hash".to_string()]);
+        assert!(!is_restorable(&version, &held));
+        let missing = missing_chunks(&version, &held);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].hash, "tail");
+
+        held.insert("tail".into());
+        assert!(is_restorable(&version, &held));
+        assert!(missing_chunks(&version, &held).is_empty());
+    }
+
+    #[test]
+    fn decodes_hex_hashes() {
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let mut version = sample_version(ulid(), ulid());
+        version.content_hash = sha256.into();
+        let bytes = content_hash_bytes(&version).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(hex::encode(&bytes), sha256);
+
+        let err = chunk_hash_bytes(&version.chunks[0]).unwrap_err();
+        assert_eq!(err, ModelError::InvalidContentHash("hash".into()));
+    }
+
+    #[test]
+    fn rejects_long_commit_message() {
+        let mut record = sample_file_record();
+        record.versions[0].commit_message = Some("m".repeat(MAX_COMMIT_MESSAGE_LEN));
+        assert_file_invariants(&record).unwrap();
+
+        record.versions[0].commit_message = Some("m".repeat(MAX_COMMIT_MESSAGE_LEN + 1));
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(
+            err,
+            ModelError::CommitMessageTooLong(record.versions[0].version_id)
+        );
+    }
+
+    #[test]
+    fn rejects_raw_version_without_hash() {
+        let mut record = sample_file_record();
+        record.versions[0].content_hash.clear();
+        let err = assert_file_invariants(&record).unwrap_err();
+        assert_eq!(err, ModelError::EmptyContentHash(record.versions[0].version_id));
+    }
+
+    #[test]
+    fn content_encoding_round_trips() {
+        let mut version = sample_version(ulid(), ulid());
+        for encoding in [
+            ContentEncoding::Raw,
+            ContentEncoding::Gzip,
+            ContentEncoding::Lz4,
+            ContentEncoding::Zstd,
+        ] {
+            version.encoding = encoding;
+            let json = serde_json::to_string(&version).unwrap();
+            let back: VersionRecord = serde_json::from_str(&json).unwrap();
+            assert_eq!(declared_encoding(&back), encoding);
+        }
+    }
+
+    #[test]
+    fn filters_paths_by_mount() {
+        let binding = |path: &str, mount: Option<&str>| PathBinding {
+            path: path.into(),
+            last_seen_at: Utc::now(),
+            writable: true,
+            mount_point: mount.map(Into::into),
+        };
+        let entry = LocalRegistryEntry {
+            file_id: ulid(),
+            paths: vec![
+                binding("/Volumes/USB/a.psd", Some("/Volumes/USB")),
+                binding("/mnt/share/a.psd", Some("/mnt/share")),
+                binding("/tmp/a.psd", None),
+            ],
+            local_version_id: None,
+            hydration: Hydration::FullyPresent,
+            consent: Consent::Approved,
+            pin: PinPreference::None,
+            auto_lock_preference: AutoLockPreference::OnEdit,
+            last_error: None,
+            sync_group: None,
+            priority: 0,
+            starred: false,
+            local_size_bytes: None,
+            sync_paused: false,
+        };
+        let usb = paths_on_mount(&entry, "/Volumes/USB");
+        assert_eq!(usb.len(), 1);
+        assert_eq!(usb[0].path, "/Volumes/USB/a.psd");
+        assert!(paths_on_mount(&entry, "/Volumes/Other").is_empty());
+    }
+
+    #[test]
+    fn merkle_root_of_fixed_chunks() {
+        let mut version = sample_version(ulid(), ulid());
+        version.chunks = ["aa", "bb", "cc"]
+            .into_iter()
+            .zip(0u32..)
+            .map(|(hash, sequence)| ChunkRef {
+                offset: u64::from(sequence) * 10,
+                length: 10,
+                hash: hash.into(),
+                sequence,
+            })
+            .collect();
+        // h(h(aa || bb) || cc); cc is unpaired at the first level.
+        let expected = "6c32f4b360cbef9220de6ecbbe0cb5d88bf6b2348bd107e4df0ca3374901b26d";
+        assert_eq!(compute_merkle_root(&version), expected);
+        version.chunks.reverse();
+        assert_eq!(compute_merkle_root(&version), expected);
+
+        // A single chunk's root is its leaf: the chunk hash bytes themselves.
+        let mut single = version.clone();
+        single.chunks.retain(|c| c.hash == "aa");
+        single.chunks[0].sequence = 0;
+        assert_eq!(compute_merkle_root(&single), hex::encode("aa"));
+
+        verify_merkle_root(&version).unwrap();
+        version.merkle_root = Some(expected.into());
+        verify_merkle_root(&version).unwrap();
+        version.merkle_root = Some("00".into());
+        assert_eq!(
+            verify_merkle_root(&version),
+            Err(ModelError::MerkleRootMismatch(version.version_id))
+        );
+    }
+
+    #[test]
+    fn chunk_sequences_must_be_contiguous() {
+        let mut record = sample_file_record();
+        let version_id = record.versions[0].version_id;
+        let chunks = &mut record.versions[0].chunks;
+        for (i, hash) in ["b", "c"].into_iter().enumerate() {
+            chunks.push(ChunkRef {
+                offset: 10 * (i as u64 + 1),
+                length: 10,
+                hash: hash.into(),
+                sequence: i as u32 + 1,
+            });
+        }
+        assert_file_invariants(&record).unwrap();
+
+        record.versions[0].chunks[2].sequence = 3;
+        assert_eq!(
+            assert_file_invariants(&record),
+            Err(ModelError::NonContiguousChunkSequence(version_id))
+        );
+        record.versions[0].chunks[2].sequence = 1;
+        assert_eq!(
+            assert_file_invariants(&record),
+            Err(ModelError::NonContiguousChunkSequence(version_id))
+        );
+    }
+
+    #[test]
+    fn sorting_by_sequence_matches_offset_order() {
+        let mut chunks: Vec<ChunkRef> = (0..4u32)
+            .map(|i| ChunkRef {
+                offset: u64::from(i) * 8,
+                length: 8,
+                hash: format!("h{i}"),
+                sequence: i,
+            })
+            .collect();
+        chunks.swap(0, 3);
+        chunks.swap(1, 2);
+        let mut by_offset = chunks.clone();
+        by_offset.sort_by_key(|c| c.offset);
+        sort_chunks_by_sequence(&mut chunks);
+        assert_eq!(chunks, by_offset);
+    }
+
+    #[test]
+    fn encryption_factories_set_algo() {
+        let aes = EncryptionInfo::aes_256_gcm("k1".into(), Some("salt".into()));
+        assert_eq!(aes.algo, "AES-256-GCM");
+        assert_eq!(aes.iv_salt.as_deref(), Some("salt"));
+        let chacha = EncryptionInfo::chacha20_poly1305("k2".into(), None);
+        assert_eq!(chacha.algo, "ChaCha20-Poly1305");
+
+        for info in [&aes, &chacha] {
+            validate_algo(&info.algo).unwrap();
+            assert!(info.is_authenticated_encryption());
+        }
+        let legacy = EncryptionInfo {
+            algo: "AES-256-CBC".into(),
+            ..aes
+        };
+        assert!(!legacy.is_authenticated_encryption());
+        assert_eq!(
+            validate_algo(&legacy.algo),
+            Err(ModelError::UnsupportedEncryptionAlgo("AES-256-CBC".into()))
+        );
+    }
+
+    #[test]
+    fn clone_for_device_keeps_only_that_state() {
+        let mut record = sample_file_record();
+        let mut other = record.device_states[0].clone();
+        other.device_id = ulid();
+        record.device_states.push(other.clone());
+
+        let reduced = record.clone_for_device(other.device_id);
+        assert_eq!(reduced.device_states, vec![other]);
+        assert_eq!(reduced.versions, record.versions);
+        assert_file_invariants(&reduced).unwrap();
+        assert_eq!(record.device_states.len(), 2);
+
+        let stranger = record.clone_for_device(ulid());
+        assert!(stranger.device_states.is_empty());
+        assert_file_invariants(&stranger).unwrap();
+    }
+
+    #[test]
+    fn head_and_ancestor_checks() {
+        let mut record = sample_file_record();
+        let grandparent = record.versions[0].clone();
+        let mut parent = sample_version(record.file_id, ulid());
+        parent.parent_version_id = Some(grandparent.version_id);
+        let mut head = sample_version(record.file_id, ulid());
+        head.parent_version_id = Some(parent.version_id);
+        record.head_version_id = head.version_id;
+        record.versions.extend([parent.clone(), head.clone()]);
+
+        assert!(head.is_head_of(&record));
+        assert!(!grandparent.is_head_of(&record));
+        assert!(grandparent.is_ancestor_of(&head, &record));
+        assert!(parent.is_ancestor_of(&head, &record));
+        assert!(!head.is_ancestor_of(&grandparent, &record));
+        assert!(!head.is_ancestor_of(&head, &record));
+
+        // A cycle must not hang the walk.
+        record.versions[0].parent_version_id = Some(head.version_id);
+        let stranger = sample_version(record.file_id, ulid());
+        assert!(!stranger.is_ancestor_of(&head, &record));
+    }
+
+    #[test]
+    fn merges_device_states_by_recency() {
+        let mut primary = sample_file_record();
+        let shared_device = primary.device_states[0].device_id;
+        let seen = primary.device_states[0].last_seen_at;
+        let mut secondary = primary.clone();
+        secondary.device_states[0].state = DeviceFileStateKind::Pulling;
+        secondary.device_states[0].last_seen_at = seen + chrono::Duration::seconds(5);
+
+        let mut only_primary = primary.device_states[0].clone();
+        only_primary.device_id = ulid();
+        only_primary.last_seen_at = seen + chrono::Duration::seconds(60);
+        primary.device_states.push(only_primary.clone());
+        let mut stale = only_primary.clone();
+        stale.state = DeviceFileStateKind::Error;
+        stale.last_seen_at = seen;
+        let mut only_secondary = primary.device_states[0].clone();
+        only_secondary.device_id = ulid();
+        secondary
+            .device_states
+            .extend([stale, only_secondary.clone()]);
+
+        merge_device_states(&mut primary, &secondary).unwrap();
+        let states: Vec<(DeviceId, DeviceFileStateKind)> = primary
+            .device_states
+            .iter()
+            .map(|d| (d.device_id, d.state.clone()))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (shared_device, DeviceFileStateKind::Pulling),
+                (only_primary.device_id, DeviceFileStateKind::Ready),
+                (only_secondary.device_id, DeviceFileStateKind::Ready),
+            ]
+        );
+    }
+
+    #[test]
+    fn verifies_chunk_hashes_against_good_set() {
+        let mut version = sample_version(ulid(), ulid());
+        version.chunks = ["h0", "h1", "h2"]
+            .into_iter()
+            .zip(0u32..)
+            .map(|(hash, sequence)| ChunkRef {
+                offset: u64::from(sequence) * 10,
+                length: 10,
+                hash: hash.into(),
+                sequence,
+            })
+            .collect();
+        let mut good: HashSet<String> = ["h0", "h2"].map(String::from).into();
+        assert_eq!(
+            verify_chunk_hashes(&version, &good),
+            Err(ModelError::UnknownChunkHash {
+                version_id: version.version_id,
+                hash: "h1".into(),
+            })
+        );
+        good.insert("h1".into());
+        assert_eq!(verify_chunk_hashes(&version, &good), Ok(()));
+    }
+
+    #[test]
+    fn kdf_requires_iv_salt_and_round_trips() {
+        let mut record = sample_file_record();
+        assert!(!has_kdf(&record.encryption));
+        record.encryption.kdf = Some(KdfParams::Pbkdf2 {
+            iterations: 600_000,
+            salt: "s1".into(),
+        });
+        assert!(has_kdf(&record.encryption));
+        assert_eq!(
+            assert_file_invariants(&record),
+            Err(ModelError::KdfWithoutIvSalt)
+        );
+        record.encryption.iv_salt = Some("iv".into());
+        assert_file_invariants(&record).unwrap();
+
+        for kdf in [
+            KdfParams::Pbkdf2 {
+                iterations: 600_000,
+                salt: "s1".into(),
+            },
+            KdfParams::Argon2 {
+                time_cost: 3,
+                memory_cost: 65_536,
+                salt: "s2".into(),
+            },
+        ] {
+            let info = EncryptionInfo {
+                kdf: Some(kdf),
+                ..EncryptionInfo::aes_256_gcm("k1".into(), Some("iv".into()))
+            };
+            let json = serde_json::to_string(&info).unwrap();
+            assert_eq!(serde_json::from_str::<EncryptionInfo>(&json).unwrap(), info);
+        }
+    }
+
+    #[test]
+    fn chunks_device_needs_skips_held_hashes() {
+        let mut version = sample_version(ulid(), ulid());
+        version.chunks = ["h0", "h1", "h2"]
+            .into_iter()
+            .zip(0u32..)
+            .map(|(hash, sequence)| ChunkRef {
+                offset: u64::from(sequence) * 10,
+                length: 10,
+                hash: hash.into(),
+                sequence,
+            })
+            .collect();
+        let mut state = sample_file_record().device_states.remove(0);
+        state.known_chunk_hashes = HashSet::from(["h0".to_string(), "h2".to_string()]);
+        let needed = chunks_device_needs(&state, &version);
+        assert_eq!(needed.len(), 1);
+        assert_eq!(needed[0].hash, "h1");
+
+        state.known_chunk_hashes.insert("h1".into());
+        assert!(chunks_device_needs(&state, &version).is_empty());
+    }
+
+    #[test]
+    fn head_version_accessors() {
+        let mut record = sample_file_record();
+        let mut head = sample_version(record.file_id, ulid());
+        head.content_hash = "head".into();
+        head.chunks[0].hash = "head".into();
+        head.chunks.push(ChunkRef {
+            offset: 10,
+            length: 5,
+            hash: "tail".into(),
+            sequence: 1,
+        });
+        record.head_version_id = head.version_id;
+        record.versions.push(head);
+
+        assert_eq!(
+            record.head_version_chunks(),
+            record.head_version().unwrap().chunks.as_slice()
+        );
+        assert_eq!(record.head_version_chunks().len(), 2);
+        assert_eq!(record.head_version_content_hash(), Some("head"));
+
+        record.head_version_id = ulid();
+        assert!(record.head_version().is_none());
+        assert!(record.head_version_chunks().is_empty());
+        assert_eq!(record.head_version_content_hash(), None);
+    }
+
+    #[test]
+    fn version_at_time_picks_latest_not_after() {
+        let mut record = sample_file_record();
+        let t = record.versions[0].timestamp;
+        let first = record.versions[0].version_id;
+        let [second, third] = [1, 2].map(|hours| {
+            let mut version = sample_version(record.file_id, ulid());
+            version.timestamp = t + chrono::Duration::hours(hours);
+            record.versions.push(version.clone());
+            version.version_id
+        });
+        // Out of timestamp order on purpose.
+        record.versions.swap(0, 2);
+
+        let at = |minutes| t + chrono::Duration::minutes(minutes);
+        assert_eq!(record.head_at_time(at(90)), Some(second));
+        assert_eq!(record.version_at_time(at(90)).unwrap().version_id, second);
+        assert_eq!(record.head_at_time(at(0)), Some(first));
+        assert_eq!(record.head_at_time(at(180)), Some(third));
+        assert_eq!(record.head_at_time(at(-1)), None);
+    }
+
+    #[test]
+    fn validates_paths() {
+        let valid = if cfg!(windows) {
+            "C:\\Projects\\scene.blend"
+        } else {
+            "/home/user/scene.blend"
+        };
+        validate_path(valid).unwrap();
+        for bad in [
+            String::new(),
+            "relative/scene.blend".to_string(),
+            format!("{valid}\0.bak"),
+            format!("{valid}{}", "a".repeat(MAX_PATH_LEN)),
+        ] {
+            assert_eq!(
+                validate_path(&bad),
+                Err(ModelError::InvalidPath(bad.clone()))
+            );
+        }
+
+        for windows in [
+            "C:\\Projects\\scene.blend",
+            "d:/Projects/scene.blend",
+            "\\\\server\\share\\scene.blend",
+            "\\\\server\\share",
+            "//server/share/scene.blend",
+        ] {
+            assert!(is_windows_absolute(windows), "{windows}");
+        }
+        for not_windows in [
+            "C:scene.blend",
+            "\\Projects\\scene.blend",
+            "\\\\server",
+            "\\\\server\\",
+            "\\\\\\share\\scene.blend",
+            "/home/user/scene.blend",
+        ] {
+            assert!(!is_windows_absolute(not_windows), "{not_windows}");
+        }
+    }
+
+    #[test]
+    fn compression_ratio_and_wire_size_validation() {
+        let mut record = sample_file_record();
+        let version = &mut record.versions[0];
+        version.size_bytes = 1000;
+        assert_eq!(compression_ratio(version), None);
+        version.size_on_wire = Some(250);
+        assert_eq!(compression_ratio(version), Some(4.0));
+        assert_file_invariants(&record).unwrap();
+
+        record.versions[0].size_on_wire = Some(0);
+        assert_eq!(compression_ratio(&record.versions[0]), None);
+        assert_eq!(
+            assert_file_invariants(&record),
+            Err(ModelError::ZeroSizeOnWire(record.versions[0].version_id))
+        );
+    }
+
+    #[test]
+    fn participating_users_from_devices_and_lock() {
+        let mut record = sample_file_record();
+        let mut second = record.device_states[0].clone();
+        second.device_id = ulid();
+        record.device_states.push(second);
+        let identity = |device_id, user_id| DeviceIdentity {
+            device_id,
+            user_id,
+            device_public_key: vec![1; 32],
+            attested_at: SystemTime::now(),
+            trusted_key_ids: Vec::new(),
+        };
+        let (alice, bob) = (ulid(), ulid());
+        let identities = [
+            identity(record.device_states[0].device_id, alice),
+            identity(record.device_states[1].device_id, bob),
+            identity(ulid(), ulid()),
+        ];
+        record.lock = Some(LockRecord {
+            lock_id: ulid(),
+            file_id: record.file_id,
+            owner_device_id: ulid(),
+            owner_user_id: "carol".into(),
+            mode: LockMode::Exclusive,
+            acquired_at: Utc::now(),
+            auto_lock: false,
+            expires_at: None,
+            session_id: None,
+        });
+
+        let expected: HashSet<String> = [alice.to_string(), bob.to_string(), "carol".into()].into();
+        assert_eq!(record.participating_user_ids(&identities), expected);
+
+        // The lock holder may also own one of the devices.
+        record.lock.as_mut().unwrap().owner_user_id = alice.to_string();
+        assert_eq!(record.participating_user_ids(&identities).len(), 2);
+    }
 }
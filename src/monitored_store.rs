@@ -0,0 +1,270 @@
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard};
+
+use chrono::Utc;
+
+use crate::{
+    is_sync_allowed, valid_transition, DeviceFileStateKind, DeviceId, DeviceStatePolicy,
+    FileChangeKind, FileEvent, FileEventSink, FileMonitor, FileMonitorConfig, FileMonitorError,
+    LocalMetadataError, LocalMetadataStore,
+};
+
+/// A `LocalMetadataStore` kept up to date by a `FileMonitor`.
+///
+/// When a bound path is modified, this device's state for the file moves to `Pushing`.
+/// Events for unbound paths, or for files this device has no state for, are ignored. So are
+/// files whose consent does not allow sync, files in `Conflict` or `LockBlocked` (they need
+/// resolving first), and states that cannot move to `Pushing` at all (e.g., `Quarantined`).
+pub struct MonitoredStore {
+    store: Arc<RwLock<LocalMetadataStore>>,
+    monitor: FileMonitor,
+    sink: Arc<StoreSink>,
+}
+
+impl MonitoredStore {
+    /// Start watching `config.paths` on behalf of `device_id`.
+    pub fn new(
+        store: LocalMetadataStore,
+        device_id: DeviceId,
+        config: FileMonitorConfig,
+    ) -> Result<MonitoredStore, FileMonitorError> {
+        let store = Arc::new(RwLock::new(store));
+        let sink = Arc::new(StoreSink {
+            store: store.clone(),
+            device_id,
+            last_error: Mutex::new(None),
+        });
+        let monitor = FileMonitor::with_config(config, sink.clone())?;
+        Ok(MonitoredStore {
+            store,
+            monitor,
+            sink,
+        })
+    }
+
+    /// Read access to the store. Hold the guard briefly: events wait for it.
+    pub fn store(&self) -> RwLockReadGuard<'_, LocalMetadataStore> {
+        self.store.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn monitor(&self) -> &FileMonitor {
+        &self.monitor
+    }
+
+    /// The most recent error from applying an event to the store, cleared by this call.
+    pub fn take_error(&self) -> Option<LocalMetadataError> {
+        self.sink
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+struct StoreSink {
+    store: Arc<RwLock<LocalMetadataStore>>,
+    device_id: DeviceId,
+    last_error: Mutex<Option<LocalMetadataError>>,
+}
+
+impl FileEventSink for StoreSink {
+    fn handle(&self, event: FileEvent) {
+        if event.kind != FileChangeKind::Modified {
+            return;
+        }
+        let Ok(mut store) = self.store.write() else {
+            return;
+        };
+        let Some(file_id) = store.file_id_for_path(&event.path.to_string_lossy()) else {
+            return;
+        };
+        if !store
+            .registry_entry(&file_id)
+            .is_some_and(|entry| is_sync_allowed(&entry.consent))
+        {
+            return;
+        }
+        let Some(mut state) = store.file_record(&file_id).and_then(|record| {
+            record
+                .device_states
+                .iter()
+                .find(|d| d.device_id == self.device_id)
+                .cloned()
+        }) else {
+            return;
+        };
+        let blocked = matches!(
+            state.state,
+            DeviceFileStateKind::Conflict | DeviceFileStateKind::LockBlocked
+        );
+        if blocked || !valid_transition(&state.state, &DeviceFileStateKind::Pushing) {
+            return;
+        }
+        state.state = DeviceFileStateKind::Pushing;
+        state.last_seen_at = Utc::now();
+        if let Err(err) = store.upsert_device_state(file_id, state, DeviceStatePolicy::Strict) {
+            *self
+                .last_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ChunkRef, Consent, ContentEncoding, DeviceFileState, EncryptionInfo, FileRecord, Hydration,
+        LocalRegistryEntry, PathBinding, VersionRecord,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use ulid::Ulid;
+
+    fn bound_store(path: &Path, device_id: DeviceId) -> (LocalMetadataStore, Ulid) {
+        let file_id = Ulid::new();
+        let version_id = Ulid::new();
+        let record = FileRecord {
+            file_id,
+            origin_device_id: device_id,
+            created_at: Utc::now(),
+            head_version_id: version_id,
+            versions: vec![VersionRecord {
+                version_id,
+                file_id,
+                parent_version_id: None,
+                origin_device_id: device_id,
+                timestamp: Utc::now(),
+                content_hash: "hash".into(),
+                size_bytes: 4,
+                chunks: vec![ChunkRef {
+                    offset: 0,
+                    length: 4,
+                    hash: "hash".into(),
+                    sequence: 0,
+                }],
+                author_user_id: None,
+                commit_message: None,
+                encoding: ContentEncoding::Raw,
+                merkle_root: None,
+                size_on_wire: None,
+            }],
+            lock: None,
+            device_states: vec![DeviceFileState {
+                device_id,
+                state: DeviceFileStateKind::Ready,
+                known_head_version_id: Some(version_id),
+                last_seen_at: Utc::now(),
+                last_error: None,
+                known_chunk_hashes: HashSet::new(),
+            }],
+            encryption: EncryptionInfo::aes_256_gcm("k1".into(), None),
+            custom_metadata: HashMap::new(),
+            tombstone: None,
+            display_name: None,
+            lock_history: Vec::new(),
+        };
+        let mut store = LocalMetadataStore::new();
+        store.upsert_file_record(record).unwrap();
+        store
+            .upsert_registry_entry(LocalRegistryEntry {
+                file_id,
+                paths: vec![PathBinding {
+                    path: path.to_string_lossy().into_owned(),
+                    last_seen_at: Utc::now(),
+                    writable: true,
+                    mount_point: None,
+                }],
+                local_version_id: Some(version_id),
+                hydration: Hydration::FullyPresent,
+                consent: Consent::Approved,
+                pin: crate::PinPreference::None,
+                auto_lock_preference: crate::AutoLockPreference::OnEdit,
+                last_error: None,
+                sync_group: None,
+                priority: 128,
+                starred: false,
+                local_size_bytes: None,
+                sync_paused: false,
+            })
+            .unwrap();
+        (store, file_id)
+    }
+
+    fn watch_dir(dir: &Path) -> FileMonitorConfig {
+        FileMonitorConfig {
+            paths: vec![dir.to_path_buf()],
+            ..FileMonitorConfig::default()
+        }
+    }
+
+    fn state_of(monitored: &MonitoredStore, file_id: &Ulid) -> DeviceFileStateKind {
+        monitored
+            .store()
+            .file_record(file_id)
+            .unwrap()
+            .device_states[0]
+            .state
+            .clone()
+    }
+
+    /// Poll until the monitor has delivered at least one `Modified` event.
+    fn wait_for_modified(monitored: &MonitoredStore) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let stats = monitored.monitor().stats();
+            if stats.events_by_kind.get("modified").is_some_and(|n| *n > 0) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("no modified event delivered");
+    }
+
+    fn scene_file(dir: &Path) -> PathBuf {
+        let file = dir.join("scene.blend");
+        fs::write(&file, b"v1").unwrap();
+        file
+    }
+
+    #[test]
+    fn modifying_a_bound_file_marks_it_pushing() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = scene_file(dir.path());
+        let device_id = Ulid::new();
+        let (store, file_id) = bound_store(&file, device_id);
+
+        let monitored = MonitoredStore::new(store, device_id, watch_dir(dir.path())).unwrap();
+        fs::write(&file, b"v2").unwrap();
+
+        wait_for_modified(&monitored);
+        assert_eq!(state_of(&monitored, &file_id), DeviceFileStateKind::Pushing);
+        assert!(monitored.take_error().is_none());
+    }
+
+    #[test]
+    fn quarantined_file_stays_quarantined_after_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = scene_file(dir.path());
+        let device_id = Ulid::new();
+        let (mut store, file_id) = bound_store(&file, device_id);
+        let quarantined = DeviceFileStateKind::Quarantined {
+            reason: "malware scan".into(),
+        };
+        let mut state = store.file_record(&file_id).unwrap().device_states[0].clone();
+        state.state = quarantined.clone();
+        store
+            .upsert_device_state(file_id, state, DeviceStatePolicy::Strict)
+            .unwrap();
+
+        let monitored = MonitoredStore::new(store, device_id, watch_dir(dir.path())).unwrap();
+        fs::write(&file, b"v2").unwrap();
+
+        wait_for_modified(&monitored);
+        assert_eq!(state_of(&monitored, &file_id), quarantined);
+        assert!(monitored.take_error().is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::DeviceFileStateKind;
+
+/// Whether device-state updates must follow `valid_transition`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceStatePolicy {
+    /// Accept any state change (e.g., when replaying state from a trusted peer).
+    #[default]
+    Permissive,
+    /// Reject changes that `valid_transition` does not allow.
+    Strict,
+}
+
+/// Allowed per-device state changes, following the flows in `docs/data-model.md`.
+///
+/// - Staying in the same state is always allowed (e.g., refreshing `last_seen_at`).
+/// - Any state may move to `Error` or `Quarantined`, and any state except `Quarantined` may
+///   move to `LockBlocked` (a lock must not lift a quarantine).
+/// - `Absent` → `AvailableRemote` (remote exists) or `Pushing` (new file).
+/// - `AvailableRemote` → `Pulling`.
+/// - `Ready` → `Pulling`, `Pushing`, `Syncing`, `AvailableRemote`, or `Conflict`.
+/// - `Pulling`, `Pushing`, `Syncing` → `Ready` or `Conflict`.
+/// - `LockBlocked`, `Conflict`, `Error` resume to `AvailableRemote`, `Pulling`, `Pushing`,
+///   or `Ready`; `Error` may also fall back to `Absent`.
+/// - `Quarantined` → `Absent`, `AvailableRemote`, or `Ready` once cleared.
+pub fn valid_transition(from: &DeviceFileStateKind, to: &DeviceFileStateKind) -> bool {
+    use DeviceFileStateKind::*;
+
+    if std::mem::discriminant(from) == std::mem::discriminant(to) {
+        return true;
+    }
+    if matches!(to, Error | Quarantined { .. }) {
+        return true;
+    }
+    if matches!(to, LockBlocked) {
+        return !matches!(from, Quarantined { .. });
+    }
+    match from {
+        Absent => matches!(to, AvailableRemote | Pushing),
+        AvailableRemote => matches!(to, Pulling),
+        Ready => matches!(to, Pulling | Pushing | Syncing | AvailableRemote | Conflict),
+        Pulling | Pushing | Syncing => matches!(to, Ready | Conflict),
+        LockBlocked | Conflict => matches!(to, AvailableRemote | Pulling | Pushing | Ready),
+        Error => matches!(to, Absent | AvailableRemote | Pulling | Pushing | Ready),
+        Quarantined { .. } => matches!(to, Absent | AvailableRemote | Ready),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DeviceFileStateKind::*;
+
+    fn all_states() -> Vec<DeviceFileStateKind> {
+        vec![
+            Absent,
+            AvailableRemote,
+            Pulling,
+            Ready,
+            Pushing,
+            Syncing,
+            LockBlocked,
+            Conflict,
+            Error,
+            Quarantined {
+                reason: "scan".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn transition_matrix() {
+        // Rows: from; columns: to, in `all_states` order.
+        // Ab AR Pl Rd Ps Sy LB Cf Er Qu
+        let matrix = [
+            [1, 1, 0, 0, 1, 0, 1, 0, 1, 1], // Absent
+            [0, 1, 1, 0, 0, 0, 1, 0, 1, 1], // AvailableRemote
+            [0, 0, 1, 1, 0, 0, 1, 1, 1, 1], // Pulling
+            [0, 1, 1, 1, 1, 1, 1, 1, 1, 1], // Ready
+            [0, 0, 0, 1, 1, 0, 1, 1, 1, 1], // Pushing
+            [0, 0, 0, 1, 0, 1, 1, 1, 1, 1], // Syncing
+            [0, 1, 1, 1, 1, 0, 1, 0, 1, 1], // LockBlocked
+            [0, 1, 1, 1, 1, 0, 1, 1, 1, 1], // Conflict
+            [1, 1, 1, 1, 1, 0, 1, 0, 1, 1], // Error
+            [1, 1, 0, 1, 0, 0, 0, 0, 1, 1], // Quarantined
+        ];
+        let states = all_states();
+        for (from, row) in states.iter().zip(matrix) {
+            for (to, allowed) in states.iter().zip(row) {
+                assert_eq!(
+                    valid_transition(from, to),
+                    allowed == 1,
+                    "{from:?} -> {to:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_skipped_steps() {
+        assert!(!valid_transition(&Absent, &Ready));
+        assert!(!valid_transition(&AvailableRemote, &Pushing));
+        assert!(!valid_transition(&Pulling, &Pushing));
+        assert!(!valid_transition(
+            &Quarantined {
+                reason: "scan".into()
+            },
+            &Pushing
+        ));
+        assert!(!valid_transition(
+            &Quarantined {
+                reason: "scan".into()
+            },
+            &LockBlocked
+        ));
+    }
+}
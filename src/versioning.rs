@@ -1,10 +1,14 @@
+use std::collections::{HashMap, HashSet};
 use std::time::{Duration, SystemTime};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{assert_file_invariants, FileRecord, ModelError, VersionId, VersionRecord};
+use crate::{
+    assert_file_invariants, assert_file_invariants_with, verify_merkle_root, ChunkRef, DeviceId,
+    FileInvariantMode, FileRecord, ModelError, PinPreference, VersionId, VersionRecord,
+};
 
 /// Retention policy for automatic version window.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,16 +17,95 @@ pub struct VersionRetention {
     pub max_versions: usize,
     /// Optionally drop versions older than this age (relative to now).
     pub max_age: Option<Duration>,
+    /// Stricter age limits for versions created by specific devices, applied on top of
+    /// `max_age`.
+    #[serde(default)]
+    pub per_device_max_age: HashMap<DeviceId, Duration>,
 }
 
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum VersioningError {
     #[error("version {0} not found")]
     MissingVersion(VersionId),
+    #[error("rollback version must have parent {expected}, got {got:?}")]
+    InvalidParent {
+        expected: VersionId,
+        got: Option<VersionId>,
+    },
+    #[error("version history loops back to {0}")]
+    CycleDetected(VersionId),
     #[error(transparent)]
     Model(#[from] ModelError),
 }
 
+/// Chunk-level difference between two versions of a file, compared by chunk hash.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VersionDiff {
+    /// Chunks in the target version whose hash is absent from the source version.
+    pub added: Vec<ChunkRef>,
+    /// Chunks in the source version whose hash is absent from the target version.
+    pub removed: Vec<ChunkRef>,
+    /// Chunks in the target version whose hash also appears in the source version.
+    pub unchanged: usize,
+}
+
+/// Indexed view of the parent/child links between a file's versions.
+///
+/// Built once from a `FileRecord`; does not track later mutations.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VersionGraph {
+    children: HashMap<VersionId, Vec<VersionId>>,
+    parents: HashMap<VersionId, Option<VersionId>>,
+    order: Vec<VersionId>,
+}
+
+impl VersionGraph {
+    pub fn from_file(file: &FileRecord) -> VersionGraph {
+        let mut graph = VersionGraph::default();
+        for v in &file.versions {
+            graph.order.push(v.version_id);
+            graph.parents.insert(v.version_id, v.parent_version_id);
+            graph.children.entry(v.version_id).or_default();
+            if let Some(parent) = v.parent_version_id {
+                graph.children.entry(parent).or_default().push(v.version_id);
+            }
+        }
+        graph
+    }
+
+    /// Direct children of a version, in stored order.
+    pub fn children_of(&self, version_id: VersionId) -> Vec<VersionId> {
+        self.children.get(&version_id).cloned().unwrap_or_default()
+    }
+
+    /// True if `ancestor` is reachable by following parent links from `descendant`.
+    /// A version is not its own ancestor.
+    pub fn is_ancestor(&self, ancestor: VersionId, descendant: VersionId) -> bool {
+        let mut seen = HashSet::new();
+        let mut current = self.parents.get(&descendant).copied().flatten();
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            if !seen.insert(id) {
+                // Malformed cycle; stop rather than loop forever.
+                return false;
+            }
+            current = self.parents.get(&id).copied().flatten();
+        }
+        false
+    }
+
+    /// Versions with no parent, in stored order.
+    pub fn roots(&self) -> Vec<VersionId> {
+        self.order
+            .iter()
+            .filter(|id| matches!(self.parents.get(id), Some(None)))
+            .copied()
+            .collect()
+    }
+}
+
 /// List versions ordered as stored (usually insertion order).
 pub fn list_versions(file: &FileRecord) -> &[VersionRecord] {
     &file.versions
@@ -31,7 +114,8 @@ pub fn list_versions(file: &FileRecord) -> &[VersionRecord] {
 /// Create a rollback version that points to a previous version and make it the head.
 ///
 /// Caller provides the new VersionRecord (with content hash/chunks for the restored data).
-/// This ensures the target exists and updates head, preserving history.
+/// This ensures the target exists and that `new_version` is parented on it, then updates head,
+/// preserving history.
 pub fn rollback_to_version(
     file: &mut FileRecord,
     target_version_id: VersionId,
@@ -40,53 +124,163 @@ pub fn rollback_to_version(
     if !file.versions.iter().any(|v| v.version_id == target_version_id) {
         return Err(VersioningError::MissingVersion(target_version_id));
     }
+    if new_version.parent_version_id != Some(target_version_id) {
+        return Err(VersioningError::InvalidParent {
+            expected: target_version_id,
+            got: new_version.parent_version_id,
+        });
+    }
 
+    verify_merkle_root(&new_version)?;
     file.versions.push(new_version.clone());
     file.head_version_id = new_version.version_id;
     assert_file_invariants(file)?;
     Ok(())
 }
 
-/// Apply retention: keeps head, then prunes by count and age.
+/// Version IDs that retention must never prune under the given pin preference.
+///
+/// `KeepN(n)` selects the `n` most recent versions by timestamp.
+pub fn pinned_versions(file: &FileRecord, pref: &PinPreference) -> Vec<VersionId> {
+    match pref {
+        PinPreference::None => Vec::new(),
+        PinPreference::KeepLatest => vec![file.head_version_id],
+        PinPreference::KeepN(n) => {
+            let mut by_recency: Vec<&VersionRecord> = file.versions.iter().collect();
+            by_recency.sort_by_key(|v| std::cmp::Reverse(v.timestamp));
+            by_recency.iter().take(*n).map(|v| v.version_id).collect()
+        }
+    }
+}
+
+/// Apply retention: keeps head and pinned versions, then prunes by age (global and
+/// per-origin-device) and count.
 pub fn apply_retention(
     file: &mut FileRecord,
     policy: &VersionRetention,
+    pin: &PinPreference,
     now: SystemTime,
 ) -> Result<(), VersioningError> {
-    // Always preserve the head version.
-    let head_id = file.head_version_id;
+    // Always preserve the head version and anything pinned.
+    let mut protected: HashSet<VersionId> = pinned_versions(file, pin).into_iter().collect();
+    protected.insert(file.head_version_id);
+
+    let cutoff_for = |max_age: Duration| -> DateTime<Utc> {
+        DateTime::from(now.checked_sub(max_age).unwrap_or(SystemTime::UNIX_EPOCH))
+    };
 
     // Filter by age first if configured.
     if let Some(max_age) = policy.max_age {
-        let cutoff = now
-            .checked_sub(max_age)
-            .unwrap_or(SystemTime::UNIX_EPOCH);
-        let cutoff: DateTime<Utc> = DateTime::from(cutoff);
+        let cutoff = cutoff_for(max_age);
         file.versions
-            .retain(|v| v.version_id == head_id || v.timestamp >= cutoff);
+            .retain(|v| protected.contains(&v.version_id) || v.timestamp >= cutoff);
+    }
+    if !policy.per_device_max_age.is_empty() {
+        file.versions.retain(|v| {
+            protected.contains(&v.version_id)
+                || policy
+                    .per_device_max_age
+                    .get(&v.origin_device_id)
+                    .is_none_or(|max_age| v.timestamp >= cutoff_for(*max_age))
+        });
     }
 
-    // Enforce max_versions (including head).
+    // Enforce max_versions (including protected ones).
     if file.versions.len() > policy.max_versions {
-        // Keep head plus most recent others by timestamp.
+        // Keep protected plus the most recent others by timestamp.
         file.versions.sort_by_key(|v| v.timestamp);
-        let keep_from = file
+        let protected_count = file
             .versions
-            .len()
-            .saturating_sub(policy.max_versions);
-        let cutoff_ts = file.versions[keep_from].timestamp;
-        file.versions
-            .retain(|v| v.version_id == head_id || v.timestamp >= cutoff_ts);
+            .iter()
+            .filter(|v| protected.contains(&v.version_id))
+            .count();
+        let mut budget = policy.max_versions.saturating_sub(protected_count);
+        let mut keep = protected.clone();
+        for v in file.versions.iter().rev() {
+            if budget == 0 {
+                break;
+            }
+            if keep.insert(v.version_id) {
+                budget -= 1;
+            }
+        }
+        file.versions.retain(|v| keep.contains(&v.version_id));
     }
 
-    assert_file_invariants(file)?;
+    // Lagging devices may still report a pruned version as their known head.
+    assert_file_invariants_with(file, FileInvariantMode::Relaxed)?;
     Ok(())
 }
 
+/// Which chunks changed between `from_version_id` and `to_version_id`, in each version's chunk
+/// order.
+pub fn diff_versions(
+    file: &FileRecord,
+    from_version_id: VersionId,
+    to_version_id: VersionId,
+) -> Result<VersionDiff, VersioningError> {
+    let find = |id: VersionId| {
+        file.versions
+            .iter()
+            .find(|v| v.version_id == id)
+            .ok_or(VersioningError::MissingVersion(id))
+    };
+    let from = find(from_version_id)?;
+    let to = find(to_version_id)?;
+    let from_hashes: HashSet<&str> = from.chunks.iter().map(|c| c.hash.as_str()).collect();
+    let to_hashes: HashSet<&str> = to.chunks.iter().map(|c| c.hash.as_str()).collect();
+
+    let (kept, added): (Vec<&ChunkRef>, Vec<&ChunkRef>) = to
+        .chunks
+        .iter()
+        .partition(|c| from_hashes.contains(c.hash.as_str()));
+    Ok(VersionDiff {
+        added: added.into_iter().cloned().collect(),
+        removed: from
+            .chunks
+            .iter()
+            .filter(|c| !to_hashes.contains(c.hash.as_str()))
+            .cloned()
+            .collect(),
+        unchanged: kept.len(),
+    })
+}
+
+/// Number of parent links between `version_id` and the root of its lineage (0 for a root).
+///
+/// Fails with `MissingVersion` if the version, or a parent along the way, is not in `file`, and
+/// with `CycleDetected` (naming the first repeated version) if the parent links loop.
+pub fn parent_chain_depth(
+    file: &FileRecord,
+    version_id: VersionId,
+) -> Result<usize, VersioningError> {
+    let parents: HashMap<VersionId, Option<VersionId>> = file
+        .versions
+        .iter()
+        .map(|v| (v.version_id, v.parent_version_id))
+        .collect();
+    let mut seen = HashSet::from([version_id]);
+    let mut current = *parents
+        .get(&version_id)
+        .ok_or(VersioningError::MissingVersion(version_id))?;
+    let mut depth = 0;
+    while let Some(id) = current {
+        if !seen.insert(id) {
+            return Err(VersioningError::CycleDetected(id));
+        }
+        depth += 1;
+        current = *parents
+            .get(&id)
+            .ok_or(VersioningError::MissingVersion(id))?;
+    }
+    Ok(depth)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ChunkRef, EncryptionInfo};
+    use std::collections::HashMap;
+    use crate::{ContentEncoding, EncryptionInfo};
     use chrono::{Duration as ChronoDuration, Utc};
 
     fn ulid() -> VersionId {
@@ -105,14 +299,20 @@ mod tests {
                 file_id,
                 parent_version_id: None,
                 origin_device_id: ulid(),
-                timestamp: (Utc::now() - ChronoDuration::seconds((count - i) as i64)).into(),
+                timestamp: Utc::now() - ChronoDuration::seconds((count - i) as i64),
                 content_hash: format!("h{i}"),
                 size_bytes: 1,
                 chunks: vec![ChunkRef {
                     offset: 0,
                     length: 1,
                     hash: format!("h{i}"),
+                    sequence: 0,
                 }],
+                author_user_id: None,
+                commit_message: None,
+                encoding: ContentEncoding::Raw,
+                merkle_root: None,
+                size_on_wire: None,
             });
         }
 
@@ -124,11 +324,11 @@ mod tests {
             versions,
             lock: None,
             device_states: vec![],
-            encryption: EncryptionInfo {
-                key_id: "k".into(),
-                algo: "AES-256-GCM".into(),
-                iv_salt: None,
-            },
+            encryption: EncryptionInfo::aes_256_gcm("k".into(), None),
+            custom_metadata: HashMap::new(),
+            tombstone: None,
+            display_name: None,
+            lock_history: Vec::new(),
         }
     }
 
@@ -145,20 +345,206 @@ mod tests {
             content_hash: "restored".into(),
             size_bytes: 1,
             chunks: file.versions[0].chunks.clone(),
+            author_user_id: None,
+            commit_message: None,
+            encoding: ContentEncoding::Raw,
+            merkle_root: None,
+            size_on_wire: None,
         };
         rollback_to_version(&mut file, target, restore_version).unwrap();
         assert_eq!(file.head_version_id, file.versions.last().unwrap().version_id);
     }
 
+    #[test]
+    fn rollback_requires_parent_to_be_target() {
+        let mut file = sample_file_with_versions(2);
+        let target = file.versions[0].version_id;
+        let other = file.versions[1].version_id;
+        let mut restore_version = file.versions[0].clone();
+        restore_version.version_id = ulid();
+
+        for parent in [None, Some(other)] {
+            restore_version.parent_version_id = parent;
+            let err = rollback_to_version(&mut file, target, restore_version.clone()).unwrap_err();
+            assert_eq!(
+                err,
+                VersioningError::InvalidParent {
+                    expected: target,
+                    got: parent,
+                }
+            );
+        }
+        assert_eq!(file.versions.len(), 2);
+
+        restore_version.parent_version_id = Some(target);
+        rollback_to_version(&mut file, target, restore_version).unwrap();
+    }
+
     #[test]
     fn retention_limits_versions() {
         let mut file = sample_file_with_versions(5);
         let policy = VersionRetention {
             max_versions: 3,
             max_age: None,
+            per_device_max_age: HashMap::new(),
         };
-        apply_retention(&mut file, &policy, SystemTime::now()).unwrap();
+        apply_retention(&mut file, &policy, &PinPreference::None, SystemTime::now()).unwrap();
         assert!(file.versions.len() <= 3);
         assert!(file.versions.iter().any(|v| v.version_id == file.head_version_id));
     }
+
+    #[test]
+    fn retention_applies_per_device_age_limits() {
+        // Versions are 5s..1s old; the last one is head.
+        let mut file = sample_file_with_versions(5);
+        let (short_lived, other) = (ulid(), ulid());
+        for (i, v) in file.versions.iter_mut().enumerate() {
+            v.origin_device_id = if i < 2 { other } else { short_lived };
+        }
+        let ids: Vec<VersionId> = file.versions.iter().map(|v| v.version_id).collect();
+        let policy = VersionRetention {
+            max_versions: 10,
+            max_age: Some(Duration::from_secs(3600)),
+            per_device_max_age: HashMap::from([(short_lived, Duration::from_millis(1500))]),
+        };
+        apply_retention(&mut file, &policy, &PinPreference::None, SystemTime::now()).unwrap();
+
+        let kept: Vec<VersionId> = file.versions.iter().map(|v| v.version_id).collect();
+        // The other device's older versions survive; the short-lived device keeps only head.
+        assert_eq!(kept, vec![ids[0], ids[1], ids[4]]);
+    }
+
+    #[test]
+    fn pinned_versions_by_preference() {
+        let file = sample_file_with_versions(5);
+        assert!(pinned_versions(&file, &PinPreference::None).is_empty());
+        assert_eq!(
+            pinned_versions(&file, &PinPreference::KeepLatest),
+            vec![file.head_version_id]
+        );
+        let pinned = pinned_versions(&file, &PinPreference::KeepN(3));
+        assert_eq!(pinned.len(), 3);
+        let newest: Vec<VersionId> = file.versions[2..]
+            .iter()
+            .rev()
+            .map(|v| v.version_id)
+            .collect();
+        assert_eq!(pinned, newest);
+    }
+
+    #[test]
+    fn retention_keeps_pinned_versions() {
+        let mut file = sample_file_with_versions(5);
+        let pinned = pinned_versions(&file, &PinPreference::KeepN(3));
+        let policy = VersionRetention {
+            max_versions: 1,
+            max_age: Some(Duration::from_secs(0)),
+            per_device_max_age: HashMap::new(),
+        };
+        apply_retention(
+            &mut file,
+            &policy,
+            &PinPreference::KeepN(3),
+            SystemTime::now(),
+        )
+        .unwrap();
+        assert_eq!(file.versions.len(), 3);
+        assert!(pinned
+            .iter()
+            .all(|id| file.versions.iter().any(|v| v.version_id == *id)));
+    }
+
+    #[test]
+    fn version_graph_tracks_forks_and_chains() {
+        // root -> a -> b, and root -> c (fork); d is a separate root.
+        let mut file = sample_file_with_versions(5);
+        let ids: Vec<VersionId> = file.versions.iter().map(|v| v.version_id).collect();
+        let (root, a, b, c, d) = (ids[0], ids[1], ids[2], ids[3], ids[4]);
+        file.versions[1].parent_version_id = Some(root);
+        file.versions[2].parent_version_id = Some(a);
+        file.versions[3].parent_version_id = Some(root);
+
+        let graph = VersionGraph::from_file(&file);
+        assert_eq!(graph.roots(), vec![root, d]);
+        assert_eq!(graph.children_of(root), vec![a, c]);
+        assert_eq!(graph.children_of(a), vec![b]);
+        assert!(graph.children_of(b).is_empty());
+
+        assert!(graph.is_ancestor(root, b));
+        assert!(graph.is_ancestor(a, b));
+        assert!(!graph.is_ancestor(c, b));
+        assert!(!graph.is_ancestor(b, root));
+        assert!(!graph.is_ancestor(b, b));
+        assert!(!graph.is_ancestor(root, d));
+    }
+
+    #[test]
+    fn diff_reports_added_chunks_by_hash() {
+        let mut file = sample_file_with_versions(2);
+        let (v0, v1) = (file.versions[0].version_id, file.versions[1].version_id);
+        let same = diff_versions(&file, v0, v0).unwrap();
+        assert!(same.added.is_empty() && same.removed.is_empty());
+        assert_eq!(same.unchanged, 1);
+
+        let appended = ChunkRef {
+            offset: 1,
+            length: 1,
+            hash: "tail".into(),
+            sequence: 1,
+        };
+        file.versions[1].chunks = vec![file.versions[0].chunks[0].clone(), appended.clone()];
+        let diff = diff_versions(&file, v0, v1).unwrap();
+        assert_eq!(diff.added, vec![appended]);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.unchanged, 1);
+
+        let missing = ulid();
+        assert_eq!(
+            diff_versions(&file, v0, missing),
+            Err(VersioningError::MissingVersion(missing))
+        );
+    }
+
+    #[test]
+    fn parent_chain_depth_counts_hops_to_root() {
+        let mut file = sample_file_with_versions(4);
+        let ids: Vec<VersionId> = file.versions.iter().map(|v| v.version_id).collect();
+        file.versions[1].parent_version_id = Some(ids[0]);
+        file.versions[2].parent_version_id = Some(ids[1]);
+
+        assert_eq!(parent_chain_depth(&file, ids[0]), Ok(0));
+        assert_eq!(parent_chain_depth(&file, ids[2]), Ok(2));
+        assert_eq!(parent_chain_depth(&file, ids[3]), Ok(0));
+        let missing = ulid();
+        assert_eq!(
+            parent_chain_depth(&file, missing),
+            Err(VersioningError::MissingVersion(missing))
+        );
+        file.versions[3].parent_version_id = Some(missing);
+        assert_eq!(
+            parent_chain_depth(&file, ids[3]),
+            Err(VersioningError::MissingVersion(missing))
+        );
+    }
+
+    #[test]
+    fn parent_chain_depth_detects_cycles() {
+        // a -> b -> c -> a
+        let mut file = sample_file_with_versions(3);
+        let ids: Vec<VersionId> = file.versions.iter().map(|v| v.version_id).collect();
+        file.versions[0].parent_version_id = Some(ids[1]);
+        file.versions[1].parent_version_id = Some(ids[2]);
+        file.versions[2].parent_version_id = Some(ids[0]);
+
+        assert_eq!(
+            parent_chain_depth(&file, ids[0]),
+            Err(VersioningError::CycleDetected(ids[0]))
+        );
+        // A version that is its own parent.
+        file.versions[2].parent_version_id = Some(ids[2]);
+        assert_eq!(
+            parent_chain_depth(&file, ids[2]),
+            Err(VersioningError::CycleDetected(ids[2]))
+        );
+    }
 }